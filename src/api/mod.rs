@@ -1,8 +1,21 @@
-use crate::cpis::{PluginSystem, PluginExecutor, PluginError};
-use rocket::{self, get, post, response::Responder, routes, serde::json::Json};
+use crate::cpis::{
+    ActionPlan, ActionRequest, CancellationStatus, CircuitState, CircuitStats, ExecutionAttempt,
+    ExecutionResult, MetricsSnapshot, PluginSystem, PluginExecutor,
+    PluginError, PluginViolations, ExecutionPage, ExecutionQuery, ExecutionRecord, Job, JobManager, JobRequest,
+    ProgressUpdate, ProviderQueueStats, Schedule, ScheduleDefinition, ScheduleManager, ScheduleRun,
+    StepStatus, Workflow, WorkflowResult,
+};
+use crate::cpis::json_cpi::{CpiDefinition, ResolvedMapping};
+use crate::cpis::rollback::{roll_back, CompensationResult, CompletedAction};
+use rocket::{self, delete, get, post, put, response::Responder, routes, serde::json::Json, FromForm};
+use rocket::http::ContentType;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::stream::{Event, EventStream};
+use tokio::sync::broadcast::error::RecvError;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, env, sync::Arc, time::Duration};
 use serde_json::Value;
+use uuid::Uuid;
 
 // Create the index module
 pub mod index;
@@ -11,6 +24,8 @@ pub mod index;
 pub struct CpiState {
     pub plugin_system: Arc<PluginSystem>,
     pub executor: Arc<PluginExecutor>,
+    pub jobs: Arc<JobManager>,
+    pub schedules: Arc<ScheduleManager>,
 }
 
 // Request format for plugin actions
@@ -23,21 +38,86 @@ struct PluginActionRequest {
     params: HashMap<String, Value>,
     #[serde(default = "default_timeout")]
     timeout_seconds: u64,
+    /// Repeats with the same key get the first request's result
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Id to cancel the request by while it runs, generated when missing;
+    /// an id that is already in flight is refused with 409
+    #[serde(default)]
+    request_id: Option<Uuid>,
+    /// Return what the action would do instead of running it
+    #[serde(default)]
+    dry_run: bool,
 }
 
 fn default_timeout() -> u64 {
     60
 }
 
+impl PluginActionRequest {
+    fn to_action_request(&self, timeout: Duration, caller: &Caller) -> ActionRequest {
+        let mut request = ActionRequest::new(&self.provider, &self.feature, &self.action, self.params.clone(), Some(timeout));
+        request.idempotency_key = self.idempotency_key.clone();
+        request.caller = caller.0.clone();
+        if let Some(request_id) = self.request_id {
+            request.request_id = request_id;
+        }
+        request
+    }
+}
+
 // Response format for plugin actions
 #[derive(Debug, Serialize)]
 struct PluginActionResponse {
+    request_id: Uuid,
     success: bool,
     result: Option<Value>,
     error: Option<String>,
     execution_time_ms: u64,
     feature: String,
     action: String,
+    attempts: Vec<ExecutionAttempt>,
+    /// What the action would do, for dry runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<ActionPlan>,
+}
+
+impl PluginActionResponse {
+    fn from_execution(request: &PluginActionRequest, execution: ExecutionResult) -> Self {
+        let (success, result, error) = match execution.result {
+            Ok(value) => (true, Some(value), None),
+            Err(err) => (false, None, Some(err.to_string())),
+        };
+        Self {
+            request_id: execution.request_id,
+            success,
+            result,
+            error,
+            execution_time_ms: execution.execution_time.as_millis() as u64,
+            feature: request.feature.clone(),
+            action: request.action.clone(),
+            attempts: execution.attempts,
+            plan: None,
+        }
+    }
+
+    fn from_plan(request: &ActionRequest, plan: Result<ActionPlan, PluginError>) -> Self {
+        let (success, plan, error) = match plan {
+            Ok(plan) => (true, Some(plan), None),
+            Err(err) => (false, None, Some(err.to_string())),
+        };
+        Self {
+            request_id: request.request_id,
+            success,
+            result: None,
+            error,
+            execution_time_ms: 0,
+            feature: request.feature.clone(),
+            action: request.action.clone(),
+            attempts: Vec::new(),
+            plan,
+        }
+    }
 }
 
 // Custom error handling
@@ -54,6 +134,12 @@ enum ApiError {
 
     #[response(status = 408)]
     Timeout(String),
+
+    #[response(status = 409)]
+    Conflict(String),
+
+    #[response(status = 503)]
+    Unavailable(String),
 }
 
 impl From<PluginError> for ApiError {
@@ -68,9 +154,12 @@ impl From<PluginError> for ApiError {
             PluginError::InvalidArgument(msg) => {
                 ApiError::BadRequest(format!("Invalid argument: {}", msg))
             }
-            PluginError::ExecutionFailed(msg) if msg.contains("timeout") => {
+            PluginError::Timeout(msg) => {
                 ApiError::Timeout(format!("Execution timed out: {}", msg))
             }
+            PluginError::Conflict(msg) => ApiError::Conflict(msg),
+            PluginError::NotFound(msg) => ApiError::NotFound(msg),
+            PluginError::CircuitOpen(msg) => ApiError::Unavailable(format!("Provider unavailable: {}", msg)),
             PluginError::ExecutionFailed(msg) => {
                 ApiError::Internal(format!("Execution failed: {}", msg))
             }
@@ -81,61 +170,290 @@ impl From<PluginError> for ApiError {
 
 type ApiResult<T> = Result<Json<T>, ApiError>;
 
+/// Header naming who makes a request, recorded in the execution history
+const CALLER_HEADER: &str = "X-Caller";
+
+// Who made an API request, from the X-Caller header or else the client address.
+// Nothing authenticates the header, so it is recorded as unverified.
+struct Caller(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let caller = request.headers().get_one(CALLER_HEADER)
+            .map(|caller| format!("unverified:{}", caller))
+            .or_else(|| request.client_ip().map(|ip| format!("address:{}", ip)));
+        Outcome::Success(Caller(caller))
+    }
+}
+
 // Route handlers
 #[post("/action", format = "json", data = "<action_request>")]
 async fn execute_action(
     action_request: Json<PluginActionRequest>,
+    caller: Caller,
     cpi_state: &rocket::State<CpiState>,
 ) -> ApiResult<PluginActionResponse> {
     let request = action_request.into_inner();
     println!("🎯 Received action request: provider={}, feature={}, action={}", request.provider, request.feature, request.action);
 
-    let start_time = std::time::Instant::now();
-    
-    // Execute the plugin action (now with provider)
     let timeout = Duration::from_secs(request.timeout_seconds);
-    // Use ExecutionRequestBuilder to ensure provider is set
-    let result = crate::cpis::executor::ExecutionRequestBuilder::new()
-        .plugin_name(&request.provider)
-        .feature(&request.feature)
-        .action(&request.action)
-        .arguments(request.params)
-        .timeout(timeout)
-        .execute(&cpi_state.executor)
-        .await;
-
-    let execution_time_ms = start_time.elapsed().as_millis() as u64;
-
-    let response = match result {
-        Ok(value) => {
-            println!("✅ Action succeeded in {}ms", execution_time_ms);
-            PluginActionResponse {
-                success: true,
-                result: Some(value),
-                error: None,
-                execution_time_ms,
-                feature: request.feature,
-                action: request.action,
-            }
+    if request.dry_run {
+        let action_request = request.to_action_request(timeout, &caller);
+        let plan = cpi_state.executor.plan(&action_request).await;
+        return Ok(Json(PluginActionResponse::from_plan(&action_request, plan)));
+    }
+
+    // Execute the plugin action (now with provider)
+    let execution = cpi_state.executor.execute(request.to_action_request(timeout, &caller)).await;
+
+    // Reusing an idempotency key for another request, or the id of a running
+    // one, is rejected outright
+    if let Err(err @ PluginError::Conflict(_)) = &execution.result {
+        return Err(err.clone().into());
+    }
+    let response = PluginActionResponse::from_execution(&request, execution);
+
+    match &response.error {
+        None => println!("✅ Action succeeded in {}ms", response.execution_time_ms),
+        Some(err) => println!("❌ Action failed in {}ms after {} attempt(s): {}",
+                              response.execution_time_ms, response.attempts.len(), err),
+    }
+
+    Ok(Json(response))
+}
+
+// Submit an action as a background job, returns before the action runs
+#[post("/jobs", format = "json", data = "<action_request>")]
+async fn submit_job(
+    action_request: Json<PluginActionRequest>,
+    caller: Caller,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<Job> {
+    let request = action_request.into_inner();
+    println!("🗂️ Received job request: provider={}, feature={}, action={}", request.provider, request.feature, request.action);
+    if request.dry_run {
+        return Err(ApiError::BadRequest("Dry runs go through /action, not /jobs".to_string()));
+    }
+
+    let job = cpi_state.jobs.submit(JobRequest {
+        provider: request.provider,
+        feature: request.feature,
+        action: request.action,
+        arguments: request.params,
+        timeout_seconds: request.timeout_seconds,
+        idempotency_key: request.idempotency_key,
+        caller: caller.0,
+    }).await?;
+
+    Ok(Json(job))
+}
+
+// List all jobs, newest first
+#[get("/jobs")]
+async fn list_jobs(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<Job>> {
+    Ok(Json(cpi_state.jobs.list_jobs().await))
+}
+
+// Get a job's status, result and timings
+#[get("/jobs/<id>")]
+async fn get_job(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<Job> {
+    let job_id = parse_job_id(&id)?;
+    let job = cpi_state.jobs.get_job(job_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("Job not found: {}", id)))?;
+    Ok(Json(job))
+}
+
+// Cancel a job that hasn't finished
+#[delete("/jobs/<id>")]
+async fn cancel_job(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<Job> {
+    let job_id = parse_job_id(&id)?;
+    let job = cpi_state.jobs.cancel_job(job_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("Job not found: {}", id)))?;
+    Ok(Json(job))
+}
+
+fn parse_job_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::BadRequest(format!("Invalid job id: {}", id)))
+}
+
+// Create a recurring schedule for an action
+#[post("/schedules", format = "json", data = "<definition>")]
+async fn create_schedule(
+    definition: Json<ScheduleDefinition>,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<Schedule> {
+    let definition = definition.into_inner();
+    println!("⏰ Creating schedule '{}' for {}/{}/{}",
+             definition.cron, definition.provider, definition.feature, definition.action);
+    Ok(Json(cpi_state.schedules.create(definition).await?))
+}
+
+// List all schedules, oldest first
+#[get("/schedules")]
+async fn list_schedules(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<Schedule>> {
+    Ok(Json(cpi_state.schedules.list().await))
+}
+
+// Get a schedule with its next run and recent runs
+#[get("/schedules/<id>")]
+async fn get_schedule(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<Schedule> {
+    let schedule_id = parse_schedule_id(&id)?;
+    let schedule = cpi_state.schedules.get(schedule_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("Schedule not found: {}", id)))?;
+    Ok(Json(schedule))
+}
+
+// Replace what a schedule runs and when
+#[put("/schedules/<id>", format = "json", data = "<definition>")]
+async fn update_schedule(
+    id: String,
+    definition: Json<ScheduleDefinition>,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<Schedule> {
+    let schedule_id = parse_schedule_id(&id)?;
+    let schedule = cpi_state.schedules.update(schedule_id, definition.into_inner()).await?
+        .ok_or_else(|| ApiError::NotFound(format!("Schedule not found: {}", id)))?;
+    Ok(Json(schedule))
+}
+
+// Delete a schedule, runs already started finish
+#[delete("/schedules/<id>")]
+async fn delete_schedule(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<Schedule> {
+    let schedule_id = parse_schedule_id(&id)?;
+    let schedule = cpi_state.schedules.delete(schedule_id).await?
+        .ok_or_else(|| ApiError::NotFound(format!("Schedule not found: {}", id)))?;
+    Ok(Json(schedule))
+}
+
+// Get the recent runs of a schedule, newest first
+#[get("/schedules/<id>/runs")]
+async fn get_schedule_runs(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<ScheduleRun>> {
+    let schedule_id = parse_schedule_id(&id)?;
+    let runs = cpi_state.schedules.runs(schedule_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("Schedule not found: {}", id)))?;
+    Ok(Json(runs))
+}
+
+fn parse_schedule_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::BadRequest(format!("Invalid schedule id: {}", id)))
+}
+
+// Cancel a running action request, telling its plugin to stop
+#[delete("/requests/<id>")]
+async fn cancel_request(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<CancellationStatus> {
+    let request_id = parse_request_id(&id)?;
+    let status = cpi_state.executor.cancel_request(request_id).await?;
+    Ok(Json(status))
+}
+
+// Get whether a cancelled request was acknowledged by its plugin
+#[get("/requests/<id>/cancellation")]
+async fn get_cancellation(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<CancellationStatus> {
+    let request_id = parse_request_id(&id)?;
+    let status = cpi_state.executor.get_cancellation(request_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("No cancellation for request: {}", id)))?;
+    Ok(Json(status))
+}
+
+// Stream a request's progress as Server-Sent Events, ending when it finishes
+//
+// Updates reported before connecting are replayed first. Each update is a
+// `progress` event, the stream closes with a `finished` event.
+#[get("/requests/<id>/progress")]
+async fn stream_progress(id: String, cpi_state: &rocket::State<CpiState>) -> Result<EventStream![], ApiError> {
+    let request_id = parse_request_id(&id)?;
+    let subscription = cpi_state.executor.subscribe_progress(request_id);
+
+    Ok(EventStream! {
+        for progress in &subscription.history {
+            yield Event::json(progress).event("progress");
         }
-        Err(err) => {
-            println!("❌ Action failed in {}ms: {}", execution_time_ms, err);
-            PluginActionResponse {
-                success: false,
-                result: None,
-                error: Some(err.to_string()),
-                execution_time_ms,
-                feature: request.feature,
-                action: request.action,
+        let mut updates = subscription.updates;
+        if !subscription.finished {
+            loop {
+                match updates.recv().await {
+                    Ok(ProgressUpdate::Progress(progress)) => {
+                        yield Event::json(&progress).event("progress");
+                    }
+                    // A slow client skips the updates it missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Ok(ProgressUpdate::Finished) | Err(RecvError::Closed) => break,
+                }
             }
         }
-    };
+        yield Event::data(request_id.to_string()).event("finished");
+    })
+}
 
-    Ok(Json(response))
+// Filters and page of the execution history, times are RFC 3339
+#[derive(Debug, FromForm)]
+struct ExecutionFilter {
+    provider: Option<String>,
+    feature: Option<String>,
+    action: Option<String>,
+    status: Option<String>,
+    caller: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    #[field(default = 0)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl ExecutionFilter {
+    fn into_query(self) -> Result<ExecutionQuery, ApiError> {
+        let time = |value: Option<String>| value
+            .map(|value| chrono::DateTime::parse_from_rfc3339(&value)
+                .map(|time| time.with_timezone(&chrono::Utc))
+                .map_err(|_| ApiError::BadRequest(format!("Invalid time, expected RFC 3339: {}", value))))
+            .transpose();
+
+        Ok(ExecutionQuery {
+            status: self.status.map(|status| status.parse()).transpose()?,
+            since: time(self.since)?,
+            until: time(self.until)?,
+            provider: self.provider,
+            feature: self.feature,
+            action: self.action,
+            caller: self.caller,
+            offset: self.offset,
+            limit: self.limit,
+        })
+    }
+}
+
+// Search the execution history, newest first
+#[get("/executions?<filter..>")]
+async fn list_executions(filter: ExecutionFilter, cpi_state: &rocket::State<CpiState>) -> ApiResult<ExecutionPage> {
+    let query = filter.into_query()?;
+    Ok(Json(cpi_state.executor.query_executions(&query).await))
+}
+
+// Get the recorded execution of a request
+#[get("/executions/<id>")]
+async fn get_execution(id: String, cpi_state: &rocket::State<CpiState>) -> ApiResult<ExecutionRecord> {
+    let request_id = parse_request_id(&id)?;
+    let record = cpi_state.executor.get_execution(request_id).await
+        .ok_or_else(|| ApiError::NotFound(format!("No execution recorded for request: {}", id)))?;
+    Ok(Json(record))
+}
+
+fn parse_request_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::BadRequest(format!("Invalid request id: {}", id)))
 }
 
 // Get available features (replaces get_providers)
 
+// Provider summary, including actions the current platform cannot run
+#[derive(Debug, Serialize)]
+struct ProviderInfo {
+    name: String,
+    unavailable_actions: Vec<String>,
+}
+
 // Get available providers (plugin names)
 #[get("/providers")]
 async fn get_providers(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<String>> {
@@ -143,6 +461,45 @@ async fn get_providers(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<Str
     Ok(Json(providers))
 }
 
+// Get available providers with the actions each can't run on this platform
+#[get("/providers/details")]
+async fn get_provider_details(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<ProviderInfo>> {
+    let names = cpi_state.plugin_system.plugin_registry.list_plugins().await;
+    let mut providers = Vec::with_capacity(names.len());
+
+    for name in names {
+        let unavailable_actions = match cpi_state.plugin_system.cpi_host.get_definition(&name).await {
+            Some(definition) => definition.unavailable_actions(),
+            None => Vec::new(),
+        };
+        providers.push(ProviderInfo { name, unavailable_actions });
+    }
+
+    Ok(Json(providers))
+}
+
+// Get a CPI provider's definition with inheritance applied
+#[get("/providers/<provider>/definition")]
+async fn get_provider_definition(
+    provider: String,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<CpiDefinition> {
+    let definition = cpi_state.plugin_system.cpi_host.get_definition(&provider).await
+        .ok_or_else(|| PluginError::PluginNotFound(provider.clone()))?;
+    Ok(Json(definition.as_ref().clone()))
+}
+
+// Get the feature action mappings in effect for a CPI provider
+#[get("/providers/<provider>/mappings")]
+async fn get_provider_mappings(
+    provider: String,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<Vec<ResolvedMapping>> {
+    let mappings = cpi_state.plugin_system.cpi_host.effective_mappings(&provider).await
+        .ok_or_else(|| PluginError::PluginNotFound(provider.clone()))?;
+    Ok(Json(mappings))
+}
+
 // Get available features (capabilities)
 #[get("/features")]
 async fn get_features(cpi_state: &rocket::State<CpiState>) -> ApiResult<Vec<String>> {
@@ -218,6 +575,12 @@ struct BatchRequest {
     actions: Vec<PluginActionRequest>,
     #[serde(default = "default_batch_timeout")]
     timeout_seconds: u64,
+    /// Undo the actions that succeeded when another one fails
+    #[serde(default)]
+    rollback: bool,
+    /// Return what every action would do instead of running them
+    #[serde(default)]
+    dry_run: bool,
 }
 
 fn default_batch_timeout() -> u64 {
@@ -231,11 +594,15 @@ struct BatchResponse {
     total_execution_time_ms: u64,
     successful_count: usize,
     failed_count: usize,
+    /// Actions undone after a failure, the last one first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compensations: Option<Vec<CompensationResult>>,
 }
 
 #[post("/batch", format = "json", data = "<batch_request>")]
 async fn execute_batch(
     batch_request: Json<BatchRequest>,
+    caller: Caller,
     cpi_state: &rocket::State<CpiState>,
 ) -> ApiResult<BatchResponse> {
     let request = batch_request.into_inner();
@@ -245,50 +612,57 @@ async fn execute_batch(
     let timeout = Duration::from_secs(request.timeout_seconds);
 
     // Convert to the format expected by executor
-    let batch_actions: Vec<(String, String, String, HashMap<String, Value>)> = request.actions
+    let batch_actions: Vec<ActionRequest> = request.actions
         .iter()
-        .map(|req| (req.provider.clone(), req.feature.clone(), req.action.clone(), req.params.clone()))
+        .map(|req| req.to_action_request(timeout, &caller))
         .collect();
 
+    // One action asking for a dry run is enough, nothing runs half-planned
+    if request.dry_run || request.actions.iter().any(|action| action.dry_run) {
+        let mut results = Vec::with_capacity(batch_actions.len());
+        for action in &batch_actions {
+            results.push(PluginActionResponse::from_plan(action, cpi_state.executor.plan(action).await));
+        }
+        let successful_count = results.iter().filter(|response| response.success).count();
+        let failed_count = results.len() - successful_count;
+        println!("📦 Batch dry run: {}/{} actions planned", successful_count, results.len());
+
+        return Ok(Json(BatchResponse {
+            success: failed_count == 0,
+            results,
+            total_execution_time_ms: start_time.elapsed().as_millis() as u64,
+            successful_count,
+            failed_count,
+            compensations: None,
+        }));
+    }
+
     // Execute batch (now with provider)
-    let results = cpi_state.executor.execute_batch(batch_actions, Some(timeout)).await;
+    let results = cpi_state.executor.execute_batch(batch_actions.clone()).await;
+
+    // Undo what succeeded if anything failed, in reverse request order
+    let compensations = if request.rollback && results.iter().any(|execution| execution.result.is_err()) {
+        let completed = batch_actions.into_iter()
+            .zip(&results)
+            .filter_map(|(action, execution)| execution.result.as_ref().ok()
+                .map(|value| CompletedAction { request: action, result: value.clone() }))
+            .collect();
+        let compensations = roll_back(&cpi_state.executor, completed).await;
+        println!("↩️ Rolled back {} actions", compensations.len());
+        Some(compensations)
+    } else {
+        None
+    };
     let total_execution_time_ms = start_time.elapsed().as_millis() as u64;
 
     // Convert results to response format
-    let mut response_results = Vec::new();
-    let mut successful_count = 0;
-    let mut failed_count = 0;
-
-    for (i, result) in results.into_iter().enumerate() {
-        let original_request = &request.actions[i];
-        
-        let response = match result {
-            Ok(value) => {
-                successful_count += 1;
-                PluginActionResponse {
-                    success: true,
-                    result: Some(value),
-                    error: None,
-                    execution_time_ms: 0, // Individual timing not available in batch
-                    feature: original_request.feature.clone(),
-                    action: original_request.action.clone(),
-                }
-            }
-            Err(err) => {
-                failed_count += 1;
-                PluginActionResponse {
-                    success: false,
-                    result: None,
-                    error: Some(err.to_string()),
-                    execution_time_ms: 0,
-                    feature: original_request.feature.clone(),
-                    action: original_request.action.clone(),
-                }
-            }
-        };
-        
-        response_results.push(response);
-    }
+    let response_results: Vec<PluginActionResponse> = results
+        .into_iter()
+        .zip(&request.actions)
+        .map(|(execution, original_request)| PluginActionResponse::from_execution(original_request, execution))
+        .collect();
+    let successful_count = response_results.iter().filter(|response| response.success).count();
+    let failed_count = response_results.len() - successful_count;
 
     let batch_response = BatchResponse {
         success: failed_count == 0,
@@ -296,6 +670,7 @@ async fn execute_batch(
         total_execution_time_ms,
         successful_count,
         failed_count,
+        compensations,
     };
 
     println!("📦 Batch completed: {}/{} successful in {}ms", 
@@ -304,6 +679,24 @@ async fn execute_batch(
     Ok(Json(batch_response))
 }
 
+// Run actions that depend on each other's results, in dependency order
+#[post("/workflow", format = "json", data = "<workflow>")]
+async fn execute_workflow(
+    workflow: Json<Workflow>,
+    caller: Caller,
+    cpi_state: &rocket::State<CpiState>,
+) -> ApiResult<WorkflowResult> {
+    let workflow = workflow.into_inner();
+    println!("🔀 Received workflow {} with {} steps", workflow.name.as_deref().unwrap_or("(unnamed)"), workflow.steps.len());
+
+    let result = workflow.run(&cpi_state.executor, caller.0).await?;
+    let succeeded = result.steps.iter().filter(|step| step.status == StepStatus::Succeeded).count();
+    println!("🔀 Workflow completed: {}/{} steps successful in {}ms",
+             succeeded, result.steps.len(), result.execution_time_ms);
+
+    Ok(Json(result))
+}
+
 // Get system statistics
 #[derive(Debug, Serialize)]
 struct SystemStats {
@@ -314,6 +707,9 @@ struct SystemStats {
     pending_requests: usize,
     global_arguments: usize,
     plugin_arguments: usize,
+    provider_queues: HashMap<String, ProviderQueueStats>,
+    result_violations: HashMap<String, PluginViolations>,
+    circuit_breakers: HashMap<String, CircuitStats>,
 }
 
 #[get("/stats")]
@@ -346,21 +742,45 @@ async fn get_system_stats(cpi_state: &rocket::State<CpiState>) -> ApiResult<Syst
         pending_requests: exec_stats.pending_requests,
         global_arguments: arg_stats.global_arguments,
         plugin_arguments: arg_stats.plugin_arguments,
+        provider_queues: exec_stats.provider_queues,
+        result_violations: exec_stats.result_violations,
+        circuit_breakers: exec_stats.circuit_breakers,
     };
 
     Ok(Json(stats))
 }
 
+// Everything the system counts, in the Prometheus text format
+#[get("/metrics")]
+async fn get_metrics(cpi_state: &rocket::State<CpiState>) -> (ContentType, String) {
+    let plugin_system = &cpi_state.plugin_system;
+    let snapshot = MetricsSnapshot {
+        actions: cpi_state.executor.action_metrics(),
+        events: plugin_system.event_system.get_stats().await,
+        plugins: plugin_system.plugin_registry.get_plugin_states().await,
+        arguments: plugin_system.argument_manager.get_argument_stats().await,
+    };
+
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, snapshot.render())
+}
+
 // Health check endpoint
 #[get("/health")]
 async fn health_check(cpi_state: &rocket::State<CpiState>) -> ApiResult<serde_json::Value> {
     let features = cpi_state.plugin_system.get_available_features().await;
     let exec_stats = cpi_state.executor.get_execution_stats().await;
 
+    // Breakers that aren't closed, a provider action is failing
+    let tripped: HashMap<String, CircuitStats> = exec_stats.circuit_breakers.into_iter()
+        .filter(|(_, circuit)| circuit.state != CircuitState::Closed)
+        .collect();
+
     let health = serde_json::json!({
-        "status": "healthy",
+        "status": if tripped.is_empty() { "healthy" } else { "degraded" },
         "features_available": features.len(),
         "pending_requests": exec_stats.pending_requests,
+        "circuit_breakers": tripped,
         "timestamp": chrono::Utc::now().to_rfc3339()
     });
 
@@ -389,6 +809,8 @@ async fn get_provider_action_params_compat(
 pub async fn rocket(
     plugin_system: Arc<PluginSystem>,
     executor: Arc<PluginExecutor>,
+    jobs: Arc<JobManager>,
+    schedules: Arc<ScheduleManager>,
 ) -> rocket::Rocket<rocket::Build> {
     // Load environment variables
     let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
@@ -409,6 +831,8 @@ pub async fn rocket(
         .manage(CpiState {
             plugin_system,
             executor,
+            jobs,
+            schedules,
         })
         .mount(
             "/",
@@ -416,16 +840,36 @@ pub async fn rocket(
                 // TODO: Uncomment when index module is ready
                 //index::index,
                 execute_action,
+                submit_job,
+                list_jobs,
+                get_job,
+                cancel_job,
+                create_schedule,
+                list_schedules,
+                get_schedule,
+                update_schedule,
+                delete_schedule,
+                get_schedule_runs,
+                cancel_request,
+                get_cancellation,
+                stream_progress,
+                list_executions,
+                get_execution,
                 get_features,
                 get_feature_actions,
                 get_action_params,
                 get_action_params_detailed,
                 get_all_unique_actions,
                 execute_batch,
+                execute_workflow,
                 get_system_stats,
+                get_metrics,
                 health_check,
                 // Backward compatibility routes
                 get_providers,
+                get_provider_details,
+                get_provider_definition,
+                get_provider_mappings,
                 get_provider_actions_compat,
                 get_provider_action_params_compat,
             ],
@@ -435,6 +879,8 @@ pub async fn rocket(
 pub async fn launch_rocket(
     plugin_system: Arc<PluginSystem>,
     executor: Arc<PluginExecutor>,
+    jobs: Arc<JobManager>,
+    schedules: Arc<ScheduleManager>,
 ) {
     // Set up graceful shutdown handler
     let plugin_system_clone = Arc::clone(&plugin_system);
@@ -449,5 +895,5 @@ pub async fn launch_rocket(
         std::process::exit(0);
     });
 
-    rocket(plugin_system, executor).await.launch().await.unwrap();
+    rocket(plugin_system, executor, jobs, schedules).await.launch().await.unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+//! Command line subcommands that run instead of the API server.
+//!
+//! ```text
+//! omni-director cpi lint [--features <dir>] [--json] [paths...]
+//! ```
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use std::path::PathBuf;
+use crate::cpis::json_cpi::lint::{lint_paths, LintSeverity};
+
+const USAGE: &str = "Usage: omni-director cpi lint [--features <dir>] [--json] [paths...]";
+
+/// Run a subcommand if the arguments name one
+///
+/// Returns `None` when the server should start as usual, otherwise the
+/// process exit code.
+pub async fn run(args: &[String]) -> Option<Result<i32>> {
+    match args.first().map(String::as_str) {
+        Some("cpi") => Some(run_cpi(&args[1..]).await),
+        _ => None,
+    }
+}
+
+async fn run_cpi(args: &[String]) -> Result<i32> {
+    match args.first().map(String::as_str) {
+        Some("lint") => lint(&args[1..]).await,
+        _ => Err(anyhow!("{}", USAGE)),
+    }
+}
+
+async fn lint(args: &[String]) -> Result<i32> {
+    let mut features_dir = PathBuf::from("./features");
+    let mut json = false;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--features" => {
+                features_dir = args.next().map(PathBuf::from).ok_or_else(|| anyhow!("{}", USAGE))?;
+            }
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(0);
+            }
+            flag if flag.starts_with('-') => return Err(anyhow!("Unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("./CPIs"));
+    }
+
+    let report = lint_paths(&paths, &features_dir).await
+        .map_err(|e| anyhow!("Lint failed: {}", e))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for issue in &report.issues {
+            match issue.severity {
+                LintSeverity::Error => println!("{}", issue.to_string().red()),
+                LintSeverity::Warning => println!("{}", issue.to_string().yellow()),
+            }
+        }
+        println!(
+            "Checked {} CPI file(s): {} error(s), {} warning(s)",
+            report.files_checked, report.error_count(), report.warning_count()
+        );
+    }
+
+    Ok(if report.has_errors() { 1 } else { 0 })
+}
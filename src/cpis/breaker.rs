@@ -0,0 +1,319 @@
+//! # Circuit Breakers
+//!
+//! Stops sending actions to a provider that keeps failing, instead of
+//! letting every request wait for its full timeout. Each provider action has
+//! its own breaker:
+//!
+//! - **closed**: actions run; failures and timeouts within the window are
+//!   counted, and reaching the threshold opens the breaker
+//! - **open**: actions fail at once with `PluginError::CircuitOpen`
+//! - **half-open**: after the cooldown one probe runs; if it succeeds the
+//!   breaker closes, if it fails it opens again
+//!
+//! Thresholds are set through the `circuit_breaker` global argument, keyed by
+//! provider name with `"*"` as the fallback. A `failure_threshold` of 0 turns
+//! the breaker off:
+//!
+//! ```text
+//! OMNI_circuit_breaker={"hetzner": {"failure_threshold": 3, "cooldown_seconds": 60},
+//!                       "*": {"failure_threshold": 5, "window_seconds": 30}}
+//! ```
+//!
+//! Only failures that point at the provider count: timeouts, plugin failures,
+//! HTTP 5xx answers and undeliverable events.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::retry::ErrorClass;
+use super::PluginError;
+
+/// Global argument holding the per-provider breaker settings
+pub const CIRCUIT_BREAKER_ARGUMENT: &str = "circuit_breaker";
+
+/// When a breaker opens and for how long
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Failures within the window that open the breaker, 0 to never open
+    pub failure_threshold: u32,
+    /// How far back failures are counted
+    pub window_seconds: u64,
+    /// How long the breaker stays open before a probe is let through
+    pub cooldown_seconds: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self { failure_threshold: 5, window_seconds: 60, cooldown_seconds: 30 }
+    }
+}
+
+impl BreakerConfig {
+    /// Pick the settings for a provider from the `circuit_breaker` argument
+    pub fn for_provider(setting: &Value, provider: &str) -> Result<Self, PluginError> {
+        let selected = match setting.get(provider).or_else(|| setting.get("*")) {
+            Some(selected) => selected,
+            None => return Ok(Self::default()),
+        };
+        serde_json::from_value(selected.clone()).map_err(|e| PluginError::InvalidArgument(format!(
+            "{} for {} is not a valid breaker setting: {}", CIRCUIT_BREAKER_ARGUMENT, provider, e
+        )))
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds)
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.cooldown_seconds)
+    }
+}
+
+/// Whether a breaker lets actions through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// State of one provider action's breaker
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStats {
+    pub state: CircuitState,
+    /// Failures counted in the current window
+    pub recent_failures: usize,
+    /// How often the breaker opened so far
+    pub times_opened: u64,
+    pub opened_at: Option<DateTime<Utc>>,
+    /// When an open breaker lets a probe through
+    pub retry_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    failures: VecDeque<Instant>,
+    times_opened: u64,
+    opened_at: Option<(Instant, DateTime<Utc>)>,
+    cooldown: Duration,
+    /// When the half-open probe started, if one is running
+    probe_started: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            failures: VecDeque::new(),
+            times_opened: 0,
+            opened_at: None,
+            cooldown: Duration::ZERO,
+            probe_started: None,
+            last_error: None,
+        }
+    }
+
+    fn open(&mut self, config: &BreakerConfig) {
+        self.state = CircuitState::Open;
+        self.times_opened += 1;
+        self.opened_at = Some((Instant::now(), Utc::now()));
+        self.cooldown = config.cooldown();
+        self.probe_started = None;
+    }
+
+    fn forget_failures_before(&mut self, cutoff: Option<Instant>) {
+        while self.failures.front().is_some_and(|failure| cutoff.is_none_or(|cutoff| *failure < cutoff)) {
+            self.failures.pop_front();
+        }
+    }
+
+    fn stats(&self) -> CircuitStats {
+        let opened_at = self.opened_at.map(|(_, at)| at);
+        let retry_at = match (self.state, opened_at) {
+            (CircuitState::Open, Some(at)) => chrono::Duration::from_std(self.cooldown).ok().map(|cooldown| at + cooldown),
+            _ => None,
+        };
+        CircuitStats {
+            state: self.state,
+            recent_failures: self.failures.len(),
+            times_opened: self.times_opened,
+            opened_at,
+            retry_at,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+/// Breakers of every provider action that ran, keyed `provider/feature/action`
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an action may run now, fails fast while its breaker is open
+    ///
+    /// An action let through must report its outcome with `record`.
+    pub fn admit(&self, key: &str, config: &BreakerConfig) -> Result<(), PluginError> {
+        if config.failure_threshold == 0 {
+            return Ok(());
+        }
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(key.to_string()).or_insert_with(Circuit::new);
+
+        match circuit.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                let (opened, _) = circuit.opened_at.expect("open circuits have an opening time");
+                if opened.elapsed() < circuit.cooldown {
+                    return Err(open_error(key, circuit));
+                }
+                circuit.state = CircuitState::HalfOpen;
+                circuit.probe_started = Some(Instant::now());
+                Ok(())
+            }
+            CircuitState::HalfOpen => {
+                // A probe that never reported back doesn't block the breaker forever
+                let probing = circuit.probe_started.is_some_and(|started| started.elapsed() < circuit.cooldown);
+                if probing {
+                    return Err(open_error(key, circuit));
+                }
+                circuit.probe_started = Some(Instant::now());
+                Ok(())
+            }
+        }
+    }
+
+    /// Count the outcome of an action that was let through
+    pub fn record(&self, key: &str, config: &BreakerConfig, result: &Result<Value, PluginError>) {
+        if config.failure_threshold == 0 {
+            return;
+        }
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(key.to_string()).or_insert_with(Circuit::new);
+
+        let error = match result {
+            Ok(_) => None,
+            Err(e) if counts_as_failure(e) => Some(e),
+            // Cancelled or rejected requests say nothing about the provider
+            Err(_) => {
+                circuit.probe_started = None;
+                return;
+            }
+        };
+
+        match (circuit.state, error) {
+            (CircuitState::HalfOpen, None) => {
+                circuit.state = CircuitState::Closed;
+                circuit.failures.clear();
+                circuit.probe_started = None;
+            }
+            (CircuitState::HalfOpen, Some(error)) => {
+                circuit.last_error = Some(error.to_string());
+                circuit.open(config);
+            }
+            (_, None) => {}
+            (_, Some(error)) => {
+                let now = Instant::now();
+                circuit.forget_failures_before(now.checked_sub(config.window()));
+                circuit.failures.push_back(now);
+                circuit.last_error = Some(error.to_string());
+                if circuit.state == CircuitState::Closed && circuit.failures.len() >= config.failure_threshold as usize {
+                    circuit.open(config);
+                }
+            }
+        }
+    }
+
+    /// State of every breaker
+    pub fn stats(&self) -> HashMap<String, CircuitStats> {
+        let circuits = self.circuits.lock().unwrap();
+        circuits.iter().map(|(key, circuit)| (key.clone(), circuit.stats())).collect()
+    }
+}
+
+/// Key of a provider action's breaker
+pub fn breaker_key(provider: &str, feature: &str, action: &str) -> String {
+    format!("{}/{}/{}", provider, feature, action)
+}
+
+fn counts_as_failure(error: &PluginError) -> bool {
+    ErrorClass::of(error).is_some_and(|class| class != ErrorClass::RateLimited)
+}
+
+fn open_error(key: &str, circuit: &Circuit) -> PluginError {
+    PluginError::CircuitOpen(format!(
+        "{} is failing, not retried before its cooldown ends (last error: {})",
+        key,
+        circuit.last_error.as_deref().unwrap_or("unknown"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn failure() -> Result<Value, PluginError> {
+        Err(PluginError::Timeout("30s".to_string()))
+    }
+
+    #[test]
+    fn breakers_open_fail_fast_and_recover_through_a_probe() {
+        let breakers = CircuitBreakers::new();
+        let config = BreakerConfig { failure_threshold: 2, window_seconds: 60, cooldown_seconds: 0 };
+        let key = breaker_key("hetzner", "VM_Manage", "create_vm");
+
+        // Errors that don't point at the provider aren't counted
+        breakers.record(&key, &config, &Err(PluginError::InvalidArgument("name".to_string())));
+        breakers.record(&key, &config, &failure());
+        assert!(breakers.admit(&key, &config).is_ok());
+        breakers.record(&key, &config, &failure());
+        assert_eq!(breakers.stats()[&key].state, CircuitState::Open);
+
+        // The cooldown is over at once, a single probe goes through
+        assert!(breakers.admit(&key, &config).is_ok());
+        assert_eq!(breakers.stats()[&key].state, CircuitState::HalfOpen);
+        breakers.record(&key, &config, &failure());
+        assert_eq!(breakers.stats()[&key].state, CircuitState::Open);
+        assert_eq!(breakers.stats()[&key].times_opened, 2);
+
+        assert!(breakers.admit(&key, &config).is_ok());
+        breakers.record(&key, &config, &Ok(json!({})));
+        assert_eq!(breakers.stats()[&key].state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn open_breakers_fail_fast_until_the_cooldown_ends() {
+        let breakers = CircuitBreakers::new();
+        let config = BreakerConfig { failure_threshold: 1, window_seconds: 60, cooldown_seconds: 60 };
+        let key = breaker_key("linode", "VM_Manage", "list_vms");
+
+        breakers.record(&key, &config, &Err(PluginError::ExecutionFailed("503".to_string())));
+        assert!(matches!(breakers.admit(&key, &config), Err(PluginError::CircuitOpen(_))));
+        assert!(breakers.stats()[&key].retry_at.is_some());
+
+        let disabled = BreakerConfig { failure_threshold: 0, ..config };
+        assert!(breakers.admit(&key, &disabled).is_ok());
+    }
+
+    #[test]
+    fn settings_are_picked_per_provider() {
+        let setting = json!({ "hetzner": { "failure_threshold": 3 }, "*": { "cooldown_seconds": 5 } });
+        assert_eq!(BreakerConfig::for_provider(&setting, "hetzner").unwrap().failure_threshold, 3);
+        assert_eq!(BreakerConfig::for_provider(&setting, "linode").unwrap().cooldown_seconds, 5);
+        assert_eq!(BreakerConfig::for_provider(&json!({}), "linode").unwrap(), BreakerConfig::default());
+    }
+}
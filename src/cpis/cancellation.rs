@@ -0,0 +1,173 @@
+//! # Cancellation
+//!
+//! Cancelling a request tells the plugin handling it to stop. The executor
+//! emits a `FeatureActionCancelEvent` on the provider's
+//! `provider:<name>:cancel` key, and the plugin answers with a
+//! `FeatureActionCancelAckEvent` saying whether it still had the request
+//! running, and once that has ended, whether it was stopped before it
+//! finished.
+//!
+//! Plugins keep a `CancellationTokens` registry: they take a token when an
+//! action starts, poll it (or await `cancelled`) while working, and cancel it
+//! when the cancel event arrives.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Event key a provider receives cancellations on
+pub fn cancel_event_key(provider: &str) -> String {
+    format!("provider:{}:cancel", provider)
+}
+
+/// Event key plugins acknowledge cancellations on
+pub const CANCEL_ACK_EVENT_KEY: &str = "feature:action:cancel_ack";
+
+/// Signal a plugin polls to find out its action was cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.state.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.state.notify.notified();
+            tokio::pin!(notified);
+            // Register before checking, so a cancel in between isn't missed
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Tokens of the actions a plugin is running, by request id
+#[derive(Debug, Clone, Default)]
+pub struct CancellationTokens {
+    tokens: Arc<Mutex<HashMap<Uuid, CancellationToken>>>,
+}
+
+impl CancellationTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token for a request that starts running
+    ///
+    /// A retry gets a fresh token, even while its cancelled attempt is still
+    /// stopping.
+    pub fn start(&self, request_id: Uuid) -> CancellationToken {
+        let mut tokens = self.tokens.lock().unwrap();
+        let token = tokens.entry(request_id).or_default();
+        if token.is_cancelled() {
+            *token = CancellationToken::new();
+        }
+        token.clone()
+    }
+
+    /// Cancel a running request, returns whether it was running
+    pub fn cancel(&self, request_id: Uuid) -> bool {
+        let tokens = self.tokens.lock().unwrap();
+        match tokens.get(&request_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget a request that finished, unless a retry already took over
+    pub fn finish(&self, request_id: Uuid, token: &CancellationToken) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.get(&request_id).is_some_and(|current| Arc::ptr_eq(&current.state, &token.state)) {
+            tokens.remove(&request_id);
+        }
+    }
+}
+
+/// What the executor knows about a cancelled request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancellationStatus {
+    pub request_id: Uuid,
+    pub provider: String,
+    pub reason: String,
+    pub requested_at: DateTime<Utc>,
+    /// Whether the plugin still had the request running, `None` until it answers
+    pub acknowledged: Option<bool>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    /// Whether the running action was stopped before it finished; `false` when
+    /// it completed anyway, `None` until the plugin answers
+    #[serde(default)]
+    pub stopped: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn waiting_plugins_see_the_cancel() {
+        let tokens = CancellationTokens::new();
+        let request_id = Uuid::new_v4();
+        let token = tokens.start(request_id);
+
+        let waiting = {
+            let token = token.clone();
+            tokio::spawn(async move { token.cancelled().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!token.is_cancelled());
+
+        assert!(tokens.cancel(request_id));
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        assert!(token.is_cancelled());
+
+        tokens.finish(request_id, &token);
+        assert!(!tokens.cancel(request_id));
+    }
+
+    #[test]
+    fn retries_outlive_their_cancelled_attempt() {
+        let tokens = CancellationTokens::new();
+        let request_id = Uuid::new_v4();
+        let first = tokens.start(request_id);
+        assert!(tokens.cancel(request_id));
+
+        let retry = tokens.start(request_id);
+        assert!(!retry.is_cancelled());
+
+        // The first attempt finishing late leaves the retry cancellable
+        tokens.finish(request_id, &first);
+        assert!(tokens.cancel(request_id));
+        assert!(retry.is_cancelled());
+    }
+}
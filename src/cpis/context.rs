@@ -3,12 +3,17 @@
 //! Provides plugins with access to core system services and capabilities.
 //! This is the main interface plugins use to interact with the system.
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::fmt::Debug;
 use async_trait::async_trait;
 use serde_json::Value;
+use tokio::task::AbortHandle;
 use uuid::Uuid;
-use super::{EventSystem, FeatureRegistry, ArgumentManager, PluginError};
+use super::{EventSystem, FeatureRegistry, ArgumentManager, ScheduledTaskEvent};
+
+/// Event key scheduled tasks fire on when they are due
+pub const SCHEDULED_TASK_EVENT_KEY: &str = "system:task:due";
 
 /// Server context trait that provides plugins with system access
 #[async_trait]
@@ -50,6 +55,9 @@ pub trait ServerContext: Send + Sync + Debug {
     async fn get_system_metrics(&self) -> Result<SystemMetrics, ServerError>;
     
     /// Schedule a task to run later
+    ///
+    /// When the delay is over a `ScheduledTaskEvent` carrying `task_data` is
+    /// emitted on `SCHEDULED_TASK_EVENT_KEY`.
     async fn schedule_task(&self, delay_ms: u64, task_data: Value) -> Result<Uuid, ServerError>;
     
     /// Cancel a scheduled task
@@ -135,6 +143,8 @@ pub struct CpiServerContext {
     region_id: String,
     data_store: Arc<tokio::sync::RwLock<std::collections::HashMap<String, Value>>>,
     metrics: Arc<tokio::sync::RwLock<SystemMetrics>>,
+    /// Timers of the tasks that are scheduled and not yet due
+    tasks: Arc<Mutex<HashMap<Uuid, AbortHandle>>>,
 }
 
 impl CpiServerContext {
@@ -151,6 +161,7 @@ impl CpiServerContext {
             region_id,
             data_store: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
             metrics: Arc::new(tokio::sync::RwLock::new(SystemMetrics::default())),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -196,8 +207,10 @@ impl ServerContext for CpiServerContext {
         
         let start = Instant::now();
         
+        // Dropping the future, on cancel or timeout, kills the command
         let output = Command::new(command)
             .args(args)
+            .kill_on_drop(true)
             .output()
             .await
             .map_err(|e| ServerError::SystemCommandFailed(format!("Failed to execute command: {}", e)))?;
@@ -239,21 +252,33 @@ impl ServerContext for CpiServerContext {
     
     async fn schedule_task(&self, delay_ms: u64, task_data: Value) -> Result<Uuid, ServerError> {
         let task_id = Uuid::new_v4();
-        self.log(LogLevel::Debug, &format!("Scheduled task {} to run in {}ms", task_id, delay_ms));
-        
-        // In a real implementation, this would use a proper task scheduler
-        let task_id_clone = task_id;
-        tokio::spawn(async move {
+        let events = Arc::clone(&self.event_system);
+        let tasks = Arc::clone(&self.tasks);
+
+        // Hold the lock until the handle is stored, so the task can't fire
+        // and remove itself first
+        let mut scheduled = self.tasks.lock().unwrap();
+        let handle = tokio::spawn(async move {
             tokio::time::sleep(tokio::time::Duration::from_millis(delay_ms)).await;
-            println!("Executing scheduled task: {} with data: {}", task_id_clone, task_data);
+            tasks.lock().unwrap().remove(&task_id);
+
+            let event = ScheduledTaskEvent { task_id, data: task_data };
+            if let Err(e) = events.emit_event(SCHEDULED_TASK_EVENT_KEY, &event).await {
+                eprintln!("Failed to fire scheduled task {}: {}", task_id, e);
+            }
         });
-        
+        scheduled.insert(task_id, handle.abort_handle());
+        drop(scheduled);
+
+        self.log(LogLevel::Debug, &format!("Scheduled task {} to run in {}ms", task_id, delay_ms));
         Ok(task_id)
     }
     
     async fn cancel_task(&self, task_id: Uuid) -> Result<(), ServerError> {
+        let handle = self.tasks.lock().unwrap().remove(&task_id)
+            .ok_or_else(|| ServerError::ResourceUnavailable(format!("Task {} is not scheduled", task_id)))?;
+        handle.abort();
         self.log(LogLevel::Debug, &format!("Cancelled task {}", task_id));
-        // In a real implementation, this would cancel the actual task
         Ok(())
     }
 }
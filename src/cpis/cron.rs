@@ -0,0 +1,221 @@
+//! # Cron Expressions
+//!
+//! The five-field cron syntax schedules use, evaluated in UTC:
+//!
+//! ```text
+//! ┌ minute (0-59)
+//! │ ┌ hour (0-23)
+//! │ │ ┌ day of month (1-31)
+//! │ │ │ ┌ month (1-12 or jan-dec)
+//! │ │ │ │ ┌ day of week (0-7 or sun-sat, 0 and 7 are Sunday)
+//! 0 2 * * *
+//! ```
+//!
+//! Fields take `*`, single values, ranges (`1-5`), lists (`1,15`) and steps
+//! (`*/15`, `0-30/10`). `@hourly`, `@daily`, `@midnight`, `@weekly`,
+//! `@monthly`, `@yearly` and `@annually` are accepted as shorthands. As in
+//! classic cron, when both day fields are restricted a day matching either
+//! one fires.
+
+use std::fmt;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use super::PluginError;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Years searched for the next match, enough for February 29th
+const SEARCH_YEARS: i32 = 8;
+
+/// A parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month field is `*`
+    any_day: bool,
+    /// Whether the day of week field is `*`
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, PluginError> {
+        let expression = expression.trim();
+        let expanded = match expression {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid(expression, "expected minute, hour, day of month, month and day of week"));
+        }
+
+        let field = |index: usize, min: u32, max: u32, names: &[&str]| {
+            parse_field(fields[index], min, max, names).map_err(|reason| invalid(expression, &reason))
+        };
+        let weekdays = field(4, 0, 7, &WEEKDAYS)?;
+
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: field(0, 0, 59, &[])?,
+            hours: field(1, 0, 23, &[])?,
+            days: field(2, 1, 31, &[])?,
+            months: field(3, 1, 12, &MONTHS)?,
+            // Sunday can be written as 7
+            weekdays: (weekdays | (weekdays >> 7)) & 0x7f,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    /// The first time the expression matches, strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        let mut date = start.date_naive();
+        let (mut hour, mut minute) = (start.hour(), start.minute());
+        let last_year = date.year() + SEARCH_YEARS;
+
+        while date.year() <= last_year {
+            if has(self.months, date.month()) && self.matches_day(date) {
+                while hour < 24 {
+                    if has(self.hours, hour) {
+                        while minute < 60 {
+                            if has(self.minutes, minute) {
+                                return Some(Utc.from_utc_datetime(&date.and_hms_opt(hour, minute, 0)?));
+                            }
+                            minute += 1;
+                        }
+                    }
+                    hour += 1;
+                    minute = 0;
+                }
+            }
+            date = date.succ_opt()?;
+            hour = 0;
+            minute = 0;
+        }
+        None
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = PluginError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        Self::parse(&expression)
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expression
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn invalid(expression: &str, reason: &str) -> PluginError {
+    PluginError::InvalidArgument(format!("Invalid cron expression '{}': {}", expression, reason))
+}
+
+/// Values a field allows, as bits
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_ascii_lowercase();
+        match names.iter().position(|name| *name == lower) {
+            // Names count from the first value, jan is 1 and sun is 0
+            Some(index) => Ok(index as u32 + min),
+            None => text.parse().map_err(|_| format!("{} is not a number", text)),
+        }
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("{} is not a valid step", step)),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else {
+            // A single value with a step runs to the end of the range
+            let start = value(range)?;
+            (start, if step > 1 { max } else { start })
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!("{} is outside {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> String {
+        CronSchedule::parse(expression).unwrap().next_after(at(after)).unwrap().to_rfc3339()
+    }
+
+    #[test]
+    fn next_runs_follow_the_expression() {
+        assert_eq!(next("@hourly", "2025-03-01T10:00:00Z"), "2025-03-01T11:00:00+00:00");
+        assert_eq!(next("0 2 * * *", "2025-03-01T10:15:30Z"), "2025-03-02T02:00:00+00:00");
+        assert_eq!(next("*/15 9-17 * * mon-fri", "2025-03-01T10:00:00Z"), "2025-03-03T09:00:00+00:00");
+        assert_eq!(next("30 0 1 jan,jul *", "2025-03-01T00:00:00Z"), "2025-07-01T00:30:00+00:00");
+        assert_eq!(next("0 0 29 2 *", "2025-03-01T00:00:00Z"), "2028-02-29T00:00:00+00:00");
+        // Sunday as 7, and either day field matching when both are set
+        assert_eq!(next("0 0 * * 7", "2025-03-03T00:00:00Z"), "2025-03-09T00:00:00+00:00");
+        assert_eq!(next("0 0 13 * 5", "2025-03-01T00:00:00Z"), "2025-03-07T00:00:00+00:00");
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in ["* * * *", "60 * * * *", "* * 0 * *", "*/0 * * * *", "5-1 * * * *", "* * * foo *"] {
+            assert!(CronSchedule::parse(expression).is_err(), "{} should be rejected", expression);
+        }
+        let schedule: CronSchedule = serde_json::from_value(serde_json::json!("@daily")).unwrap();
+        assert_eq!(serde_json::to_value(&schedule).unwrap(), serde_json::json!("@daily"));
+    }
+}
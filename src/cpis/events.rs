@@ -13,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 use uuid::Uuid;
+use super::PluginError;
 
 /// Core event trait that all events must implement
 pub trait Event: Send + Sync + Any + Debug {
@@ -137,6 +138,7 @@ impl EventSystem {
         println!("[EventSystem] emit_event: key='{}' type='{}'", event_key, T::type_name());
         let data = event.serialize()?;
         let handlers = self.handlers.read().await;
+        let mut handler_failures = 0;
 
         if let Some(event_handlers) = handlers.get(event_key) {
             println!("[EventSystem] Found {} handler(s) for key '{}'", event_handlers.len(), event_key);
@@ -145,6 +147,7 @@ impl EventSystem {
                 if let Err(e) = handler.handle(&data).await {
                     // Log error but continue processing other handlers
                     eprintln!("Handler {} failed: {}", handler.handler_name(), e);
+                    handler_failures += 1;
                 }
             }
         } else {
@@ -154,10 +157,33 @@ impl EventSystem {
         // Update stats
         let mut stats = self.stats.write().await;
         stats.events_emitted += 1;
+        let key_stats = stats.by_key.entry(event_key.to_string()).or_default();
+        key_stats.emitted += 1;
+        key_stats.handler_failures += handler_failures;
         
         Ok(())
     }
 
+    /// Whether any handler is registered for a key
+    pub async fn has_handlers(&self, event_key: &str) -> bool {
+        self.handlers.read().await.get(event_key).is_some_and(|handlers| !handlers.is_empty())
+    }
+
+    /// Key to emit a provider's feature action on
+    ///
+    /// The provider's own `feature_event_key`, unless nothing listens there:
+    /// native plugins built before actions were scoped by provider subscribe
+    /// to `legacy_feature_event_key` instead. That key is shared by every
+    /// plugin with the feature, so they should skip events for other providers.
+    pub async fn feature_action_key(&self, provider: &str, feature: &str, action: &str) -> String {
+        let key = feature_event_key(provider, feature, action);
+        if self.has_handlers(&key).await {
+            key
+        } else {
+            legacy_feature_event_key(feature, action)
+        }
+    }
+
     /// Get current event system statistics
     pub async fn get_stats(&self) -> EventSystemStats {
         self.stats.read().await.clone()
@@ -169,6 +195,16 @@ impl EventSystem {
 pub struct EventSystemStats {
     pub total_handlers: usize,
     pub events_emitted: u64,
+    /// Emissions and handler failures of every event key emitted so far
+    pub by_key: HashMap<String, EventKeyStats>,
+}
+
+/// Statistics of one event key
+#[derive(Debug, Default, Clone)]
+pub struct EventKeyStats {
+    pub emitted: u64,
+    /// Handlers that returned an error
+    pub handler_failures: u64,
 }
 
 /// Event system errors
@@ -234,9 +270,26 @@ impl Event for PluginDisconnectedEvent {
     }
 }
 
+/// Event key a provider subscribes to for one of its feature actions
+///
+/// Actions are dispatched to a single provider, so the key is scoped by the
+/// plugin name rather than shared by every plugin declaring the feature.
+pub fn feature_event_key(provider: &str, feature: &str, action: &str) -> String {
+    format!("provider:{}:feature:{}:{}", provider, feature, action)
+}
+
+/// Event key of a feature action shared by every plugin with the feature,
+/// still used by native plugins written before `feature_event_key`
+pub fn legacy_feature_event_key(feature: &str, action: &str) -> String {
+    format!("feature:{}:{}", feature, action)
+}
+
 /// Event for feature action execution
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeatureActionEvent {
+    /// Plugin the action is dispatched to
+    #[serde(default)]
+    pub provider: String,
     pub feature: String,
     pub action: String,
     pub arguments: HashMap<String, Value>,
@@ -267,6 +320,55 @@ pub struct FeatureActionCompleteEvent {
     pub request_id: Uuid,
     pub result: Result<Value, String>,
     pub execution_time_ms: u64,
+    /// Kind of a failed result, so the executor can tell failures apart;
+    /// without one a failure counts as a plain execution failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<ActionFailure>,
+}
+
+/// Kind of failure a completion reports, beyond its message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActionFailure {
+    /// The provider answered with an HTTP error status
+    HttpStatus { status: u16 },
+    /// The action's arguments were refused before anything ran
+    InvalidArgument,
+    /// The action was stopped by a cancel
+    Cancelled,
+}
+
+impl ActionFailure {
+    /// Kind of a plugin error, `None` for plain execution failures
+    pub fn of(error: &PluginError) -> Option<Self> {
+        match error {
+            PluginError::HttpStatus { status, .. } => Some(ActionFailure::HttpStatus { status: *status }),
+            PluginError::InvalidArgument(_) => Some(ActionFailure::InvalidArgument),
+            PluginError::Cancelled(_) => Some(ActionFailure::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// Message sent along with the failure, without what the kind already says
+    pub fn message(error: &PluginError) -> String {
+        match error {
+            PluginError::ExecutionFailed(message)
+            | PluginError::InvalidArgument(message)
+            | PluginError::Cancelled(message) => message.clone(),
+            PluginError::HttpStatus { message, .. } => message.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    /// The plugin error a completion's failure stands for
+    pub fn into_error(failure: Option<Self>, message: String) -> PluginError {
+        match failure {
+            Some(ActionFailure::HttpStatus { status }) => PluginError::HttpStatus { status, message },
+            Some(ActionFailure::InvalidArgument) => PluginError::InvalidArgument(message),
+            Some(ActionFailure::Cancelled) => PluginError::Cancelled(message),
+            None => PluginError::ExecutionFailed(message),
+        }
+    }
 }
 
 impl Event for FeatureActionCompleteEvent {
@@ -287,6 +389,123 @@ impl Event for FeatureActionCompleteEvent {
     }
 }
 
+/// Event asking the handling plugin to stop a feature action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureActionCancelEvent {
+    pub request_id: Uuid,
+    pub provider: String,
+    pub reason: String,
+}
+
+impl Event for FeatureActionCancelEvent {
+    fn type_name() -> &'static str {
+        "FeatureActionCancelEvent"
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, EventError> {
+        serde_json::to_vec(self).map_err(EventError::Serialization)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, EventError> {
+        serde_json::from_slice(data).map_err(EventError::Serialization)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event a plugin answers a cancellation with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureActionCancelAckEvent {
+    pub request_id: Uuid,
+    pub provider: String,
+    /// Whether the plugin still had the action running
+    pub acknowledged: bool,
+    /// Whether the action's command or request was stopped before it finished
+    #[serde(default)]
+    pub stopped: bool,
+}
+
+impl Event for FeatureActionCancelAckEvent {
+    fn type_name() -> &'static str {
+        "FeatureActionCancelAckEvent"
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, EventError> {
+        serde_json::to_vec(self).map_err(EventError::Serialization)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, EventError> {
+        serde_json::from_slice(data).map_err(EventError::Serialization)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event a plugin reports the progress of a running action with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureActionProgressEvent {
+    pub request_id: Uuid,
+    /// Share of the action done, from 0 to 100
+    #[serde(default)]
+    pub percent: Option<u8>,
+    /// Short name of the current step, e.g. "booting"
+    #[serde(default)]
+    pub phase: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Partial result available so far
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl Event for FeatureActionProgressEvent {
+    fn type_name() -> &'static str {
+        "FeatureActionProgressEvent"
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, EventError> {
+        serde_json::to_vec(self).map_err(EventError::Serialization)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, EventError> {
+        serde_json::from_slice(data).map_err(EventError::Serialization)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event emitted when a task scheduled through the server context is due
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledTaskEvent {
+    pub task_id: Uuid,
+    /// Data the task was scheduled with
+    pub data: Value,
+}
+
+impl Event for ScheduledTaskEvent {
+    fn type_name() -> &'static str {
+        "ScheduledTaskEvent"
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, EventError> {
+        serde_json::to_vec(self).map_err(EventError::Serialization)
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, EventError> {
+        serde_json::from_slice(data).map_err(EventError::Serialization)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
 /// Event for argument registration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArgumentRegisteredEvent {
@@ -312,4 +531,20 @@ impl Event for ArgumentRegisteredEvent {
     fn as_any(&self) -> &dyn Any {
         self
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn native_plugins_keep_the_legacy_key() {
+        let events = EventSystem::new();
+        assert_eq!(events.feature_action_key("native", "VM_Manage", "create_vm").await, "feature:VM_Manage:create_vm");
+
+        let key = feature_event_key("dummy", "VM_Manage", "create_vm");
+        events.on_event::<FeatureActionEvent, _>(&key, |_| Ok(())).await.unwrap();
+        assert_eq!(events.feature_action_key("dummy", "VM_Manage", "create_vm").await, key);
+        assert_eq!(events.feature_action_key("native", "VM_Manage", "create_vm").await, "feature:VM_Manage:create_vm");
+    }
+}
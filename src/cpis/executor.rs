@@ -3,30 +3,61 @@
 //! Handles the execution of plugin actions through the event system.
 //! No case statements - everything is handled through event callbacks.
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use chrono::Utc;
 use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 use super::{
-    PluginError, FeatureActionEvent, FeatureActionCompleteEvent, 
-    EventSystem, FeatureRegistry, ArgumentManager, ServerContext
+    ActionDef, ActionFailure, PluginError, FeatureActionEvent, FeatureActionCompleteEvent, FeatureActionCancelEvent,
+    FeatureActionCancelAckEvent, FeatureActionProgressEvent, EventSystem,
+    FeatureRegistry, ArgumentManager, ArgumentResolution, PluginRegistry, ServerContext
 };
+use super::breaker::{breaker_key, BreakerConfig, CircuitBreakers, CircuitStats, CIRCUIT_BREAKER_ARGUMENT};
+use super::cancellation::{cancel_event_key, CancellationStatus, CANCEL_ACK_EVENT_KEY};
+use super::history::{ExecutionHistory, ExecutionPage, ExecutionQuery, ExecutionRecord, ExecutionStatus};
+use super::idempotency::{wait_for, Claim, IdempotencyStore, IDEMPOTENCY_TTL};
+use super::limits::{ActionLimit, ActionLimiter, ProviderQueueStats, ACTION_LIMITS_ARGUMENT};
+use super::metrics::{ActionLabels, ActionMetrics, ActionSeries};
+use super::plan::{ActionPlan, PlannedArgument};
+use super::progress::{ActionProgress, ProgressSubscription, ProgressTracker, PROGRESS_EVENT_KEY};
+use super::retry::{ErrorClass, RetryPolicy, RETRY_POLICY_ARGUMENT};
+use super::validation::{PluginViolations, ResultValidator};
+
+/// Timeout for actions that don't set their own
+pub const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the outcome of a cancellation, and the progress of a finished request, is kept
+const CANCELLATION_RETENTION: chrono::Duration = chrono::Duration::hours(1);
 
 /// Executes plugin actions through the event system
 #[derive(Debug)]
 pub struct PluginExecutor {
     event_system: Arc<EventSystem>,
+    plugin_registry: Arc<PluginRegistry>,
     feature_registry: Arc<FeatureRegistry>,
     argument_manager: Arc<ArgumentManager>,
     pending_requests: Arc<RwLock<HashMap<Uuid, PendingRequest>>>,
+    idempotency: Arc<IdempotencyStore>,
+    limiter: Arc<ActionLimiter>,
+    /// Provider of every request between its first and last attempt
+    active_requests: Mutex<HashMap<Uuid, String>>,
+    cancellations: Arc<RwLock<HashMap<Uuid, CancellationStatus>>>,
+    progress: Arc<ProgressTracker>,
+    history: Arc<ExecutionHistory>,
+    validator: Arc<ResultValidator>,
+    breakers: CircuitBreakers,
+    metrics: ActionMetrics,
 }
 
 /// Represents a pending action request
 #[derive(Debug)]
 pub struct PendingRequest {
     pub request_id: Uuid,
+    pub plugin_name: String,
     pub feature: String,
     pub action: String,
     pub arguments: HashMap<String, Value>,
@@ -46,6 +77,42 @@ pub struct ExecutionContext {
     pub timeout: Duration,
 }
 
+/// A feature action to execute on one provider
+#[derive(Debug, Clone)]
+pub struct ActionRequest {
+    pub request_id: Uuid,
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    pub arguments: HashMap<String, Value>,
+    pub timeout: Duration,
+    /// Marks a mutating action as safe to repeat
+    pub idempotency_key: Option<String>,
+    /// Who asked for the action, kept in the execution history
+    pub caller: Option<String>,
+}
+
+impl ActionRequest {
+    pub fn new(
+        provider: impl Into<String>,
+        feature: impl Into<String>,
+        action: impl Into<String>,
+        arguments: HashMap<String, Value>,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            request_id: Uuid::new_v4(),
+            provider: provider.into(),
+            feature: feature.into(),
+            action: action.into(),
+            arguments,
+            timeout: timeout.unwrap_or(DEFAULT_ACTION_TIMEOUT),
+            idempotency_key: None,
+            caller: None,
+        }
+    }
+}
+
 /// Execution result with timing information
 #[derive(Debug, Clone)]
 pub struct ExecutionResult {
@@ -53,39 +120,119 @@ pub struct ExecutionResult {
     pub result: Result<Value, PluginError>,
     pub execution_time: Duration,
     pub plugin_name: String,
+    /// Every attempt made, the last one produced `result`
+    pub attempts: Vec<ExecutionAttempt>,
+}
+
+impl ExecutionResult {
+    fn new(
+        request: &ActionRequest,
+        result: Result<Value, PluginError>,
+        execution_time: Duration,
+        attempts: Vec<ExecutionAttempt>,
+    ) -> Self {
+        Self {
+            request_id: request.request_id,
+            result,
+            execution_time,
+            plugin_name: request.provider.clone(),
+            attempts,
+        }
+    }
+}
+
+/// One attempt at running an action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionAttempt {
+    /// Attempt number, starting at 1
+    pub attempt: u32,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    /// Wait before the next attempt, if there is one
+    pub retry_delay_ms: Option<u64>,
 }
 
 impl PluginExecutor {
     pub fn new(
         event_system: Arc<EventSystem>,
+        plugin_registry: Arc<PluginRegistry>,
         feature_registry: Arc<FeatureRegistry>,
         argument_manager: Arc<ArgumentManager>,
     ) -> Self {
+        let validator = ResultValidator::new(Arc::clone(&feature_registry), Arc::clone(&argument_manager));
         Self {
             event_system,
+            plugin_registry,
             feature_registry,
             argument_manager,
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            idempotency: Arc::new(IdempotencyStore::new()),
+            limiter: Arc::new(ActionLimiter::new()),
+            active_requests: Mutex::new(HashMap::new()),
+            cancellations: Arc::new(RwLock::new(HashMap::new())),
+            progress: Arc::new(ProgressTracker::new()),
+            history: Arc::new(ExecutionHistory::in_memory()),
+            validator: Arc::new(validator),
+            breakers: CircuitBreakers::new(),
+            metrics: ActionMetrics::new(),
         }
     }
 
+    /// Keep the execution history in `history` instead of only in memory
+    pub fn with_history(mut self, history: ExecutionHistory) -> Self {
+        self.history = Arc::new(history);
+        self
+    }
+
     /// Initialize the executor by registering for completion events
     pub async fn initialize(&self) -> Result<(), PluginError> {
         let pending_requests = Arc::clone(&self.pending_requests);
+        let validator = Arc::clone(&self.validator);
         
         // Register handler for action completion events
         self.event_system.on_event::<FeatureActionCompleteEvent, _>(
             "feature:action:complete",
             move |event| {
                 let pending_requests = Arc::clone(&pending_requests);
+                let validator = Arc::clone(&validator);
                 tokio::spawn(async move {
-                    Self::handle_action_complete(pending_requests, event).await
+                    Self::handle_action_complete(pending_requests, validator, event).await
                 });
                 Ok(())
             }
         ).await
             .map_err(|e| PluginError::EventError(e.to_string()))?;
 
+        // Record whether plugins still had cancelled requests running
+        let cancellations = Arc::clone(&self.cancellations);
+        self.event_system.on_event::<FeatureActionCancelAckEvent, _>(
+            CANCEL_ACK_EVENT_KEY,
+            move |event| {
+                let cancellations = Arc::clone(&cancellations);
+                tokio::spawn(async move {
+                    let mut cancellations = cancellations.write().await;
+                    if let Some(status) = cancellations.get_mut(&event.request_id) {
+                        status.acknowledged = Some(event.acknowledged);
+                        status.acknowledged_at = Some(Utc::now());
+                        status.stopped = Some(event.stopped);
+                    }
+                });
+                Ok(())
+            }
+        ).await
+            .map_err(|e| PluginError::EventError(e.to_string()))?;
+
+        // Keep the progress plugins report for running requests
+        let progress = Arc::clone(&self.progress);
+        self.event_system.on_event::<FeatureActionProgressEvent, _>(
+            PROGRESS_EVENT_KEY,
+            move |event| {
+                progress.record(event);
+                Ok(())
+            }
+        ).await
+            .map_err(|e| PluginError::EventError(e.to_string()))?;
+
         Ok(())
     }
 
@@ -98,21 +245,201 @@ impl PluginExecutor {
         arguments: HashMap<String, Value>,
         timeout: Option<Duration>,
     ) -> Result<Value, PluginError> {
-        let request_id = Uuid::new_v4();
+        self.execute_request(Uuid::new_v4(), provider, feature, action, arguments, timeout).await
+    }
+
+    /// Execute a feature action under a request id chosen by the caller
+    ///
+    /// Lets callers that outlive the request, like jobs, cancel it later.
+    pub async fn execute_request(
+        &self,
+        request_id: Uuid,
+        provider: &str,
+        feature: &str,
+        action: &str,
+        arguments: HashMap<String, Value>,
+        timeout: Option<Duration>,
+    ) -> Result<Value, PluginError> {
+        let mut request = ActionRequest::new(provider, feature, action, arguments, timeout);
+        request.request_id = request_id;
+        self.execute(request).await.result
+    }
+
+    /// Execute an action, retrying failed attempts as its retry policy allows
+    ///
+    /// Every attempt is listed in the result, retries reuse the request id.
+    /// A request repeating the idempotency key of an earlier one gets that
+    /// request's result instead of running again. A request id that is
+    /// already in flight is refused, so callers can't cancel each other.
+    pub async fn execute(&self, request: ActionRequest) -> ExecutionResult {
+        let _active = match ActiveRequest::claim(self, &request) {
+            Ok(active) => active,
+            Err(e) => return ExecutionResult::new(&request, Err(e), Duration::ZERO, Vec::new()),
+        };
+
+        let key = match &request.idempotency_key {
+            Some(key) => key.clone(),
+            None => return self.execute_recorded(request).await,
+        };
+
+        match self.idempotency.claim(&key, &request) {
+            Claim::Owner(guard) => {
+                let mutating = self.feature_registry.get_action(&request.feature, &request.action).await
+                    .is_ok_and(|action| action.is_mutating);
+                let result = self.execute_recorded(request).await;
+                guard.complete(&result, mutating);
+                result
+            }
+            Claim::Completed(result) => result,
+            Claim::InFlight(outcome) => match wait_for(outcome).await {
+                Some(result) => result,
+                None => ExecutionResult::new(&request, Err(PluginError::ExecutionFailed(format!(
+                    "Request with idempotency key {} was abandoned", key
+                ))), Duration::ZERO, Vec::new()),
+            },
+            Claim::Conflict => ExecutionResult::new(&request, Err(PluginError::Conflict(format!(
+                "Idempotency key {} was already used for a different request", key
+            ))), Duration::ZERO, Vec::new()),
+        }
+    }
+
+    /// Run a request and add its outcome to the execution history
+    ///
+    /// Repeats answered from an idempotency key ran nothing and aren't recorded.
+    async fn execute_recorded(&self, request: ActionRequest) -> ExecutionResult {
+        let started_at = Utc::now();
+        let execution = self.execute_attempts(request.clone()).await;
+        self.metrics.record(
+            &request.provider,
+            &request.feature,
+            &request.action,
+            ExecutionStatus::of(&execution.result),
+            execution.execution_time,
+        );
+
+        let record = ExecutionRecord::new(&request, &execution, started_at);
+        if let Err(e) = self.history.record(record).await {
+            eprintln!("Failed to record execution of request {}: {}", request.request_id, e);
+        }
+        execution
+    }
+
+    async fn execute_attempts(&self, request: ActionRequest) -> ExecutionResult {
         let start_time = Instant::now();
-        let timeout = timeout.unwrap_or(Duration::from_secs(30));
+        let mut attempts = Vec::new();
+
+        let policy = match self.retry_policy(&request).await {
+            Ok(policy) => policy,
+            Err(e) => return ExecutionResult::new(&request, Err(e), start_time.elapsed(), attempts),
+        };
+
+        loop {
+            // A cancel between attempts stops the retries
+            if let Some(error) = self.cancellation_error(request.request_id).await {
+                return ExecutionResult::new(&request, Err(error), start_time.elapsed(), attempts);
+            }
+
+            let attempt_start = Instant::now();
+            let result = self.attempt(&request).await;
+            let attempt = attempts.len() as u32 + 1;
+
+            let retry_delay = match (&result, &policy) {
+                (Err(e), Some(policy)) if attempt < policy.max_attempts && policy.is_retryable(e) => {
+                    Some(policy.backoff(attempt))
+                }
+                _ => None,
+            };
+
+            attempts.push(ExecutionAttempt {
+                attempt,
+                duration_ms: attempt_start.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|e| e.to_string()),
+                retry_delay_ms: retry_delay.map(|delay| delay.as_millis() as u64),
+            });
+
+            match retry_delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return ExecutionResult::new(&request, result, start_time.elapsed(), attempts),
+            }
+        }
+    }
+
+    /// Retry policy for a request, if it may be retried at all
+    async fn retry_policy(&self, request: &ActionRequest) -> Result<Option<RetryPolicy>, PluginError> {
+        // Unknown actions fail on their first attempt
+        let action = match self.feature_registry.get_action(&request.feature, &request.action).await {
+            Ok(action) => action,
+            Err(_) => return Ok(None),
+        };
+
+        // Repeating a mutating action is only safe under an idempotency key
+        if action.is_mutating && request.idempotency_key.is_none() {
+            return Ok(None);
+        }
+
+        let setting = self.argument_manager
+            .get_argument(&request.provider, RETRY_POLICY_ARGUMENT, None, ArgumentResolution::GlobalOnly)
+            .await
+            .ok()
+            .map(|argument| argument.value);
+        let policy = RetryPolicy::select(setting.as_ref(), &request.provider, action.retry.as_ref())?;
+
+        // A timed out attempt may still be finishing on the provider, so a
+        // mutating action isn't started again next to it
+        Ok(policy.map(|mut policy| {
+            if action.is_mutating {
+                policy.retry_on.retain(|class| *class != ErrorClass::Timeout);
+            }
+            policy
+        }))
+    }
+
+    /// Run a single attempt of an action
+    async fn attempt(&self, request: &ActionRequest) -> Result<Value, PluginError> {
+        let provider = request.provider.as_str();
+        let feature = request.feature.as_str();
+        let action = request.action.as_str();
+
+        // Only the named provider handles the action, fail fast if it can't
+        self.resolve_provider(provider, feature).await?;
 
         // Validate that the feature and action exist for the provider
-        self.feature_registry.validate_action(feature, action, &arguments).await?;
+        self.feature_registry.validate_action(feature, action, &request.arguments).await?;
+
+        // Fail fast while the provider action keeps failing
+        let breaker = breaker_key(provider, feature, action);
+        let breaker_config = self.breaker_config(provider).await;
+        self.breakers.admit(&breaker, &breaker_config)?;
+
+        let result = self.dispatch(request).await;
+        self.breakers.record(&breaker, &breaker_config, &result);
+        result
+    }
+
+    /// Hand an admitted attempt to the provider and wait for its completion
+    async fn dispatch(&self, request: &ActionRequest) -> Result<Value, PluginError> {
+        let request_id = request.request_id;
+        let provider = request.provider.as_str();
+        let feature = request.feature.as_str();
+        let action = request.action.as_str();
+        let timeout = request.timeout;
+
+        // Queue behind the provider's limits, the timeout starts once admitted
+        let _permit = self.limiter.acquire(provider, feature).await;
+        if let Some(error) = self.cancellation_error(request_id).await {
+            return Err(error);
+        }
+        let start_time = Instant::now();
 
         // Create execution context
         let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
 
         let pending_request = PendingRequest {
             request_id,
+            plugin_name: provider.to_string(),
             feature: feature.to_string(),
             action: action.to_string(),
-            arguments: arguments.clone(),
+            arguments: request.arguments.clone(),
             start_time,
             timeout,
             response_sender,
@@ -125,17 +452,20 @@ impl PluginExecutor {
         }
 
 
-        // Emit the feature action event (compat: do NOT include provider in event key)
+        // Emit the feature action event on the provider's own key
         let event = FeatureActionEvent {
+            provider: provider.to_string(),
             feature: feature.to_string(),
             action: action.to_string(),
-            arguments,
+            arguments: request.arguments.clone(),
             request_id,
         };
 
-        let event_key = format!("feature:{}:{}", feature, action);
-        self.event_system.emit_event(&event_key, &event).await
-            .map_err(|e| PluginError::EventError(e.to_string()))?;
+        let event_key = self.event_system.feature_action_key(provider, feature, action).await;
+        if let Err(e) = self.event_system.emit_event(&event_key, &event).await {
+            self.cleanup_request(request_id).await;
+            return Err(PluginError::EventError(e.to_string()));
+        }
 
         // Wait for response with timeout
         match tokio::time::timeout(timeout, response_receiver).await {
@@ -146,26 +476,120 @@ impl PluginExecutor {
                 Err(PluginError::ExecutionFailed("Response channel closed".to_string()))
             },
             Err(_) => {
-                // Timeout occurred
+                // Timeout occurred; stop the provider's work, so a retry
+                // doesn't run next to it
                 self.cleanup_request(request_id).await;
-                Err(PluginError::ExecutionFailed(format!("Action timed out after {:?}", timeout)))
+                let event = FeatureActionCancelEvent {
+                    request_id,
+                    provider: provider.to_string(),
+                    reason: "Timed out".to_string(),
+                };
+                if let Err(e) = self.event_system.emit_event(&cancel_event_key(provider), &event).await {
+                    eprintln!("Failed to stop timed out request {}: {}", request_id, e);
+                }
+                Err(PluginError::Timeout(format!("Action timed out after {:?}", timeout)))
             }
         }
     }
 
+    /// Work out what executing a request would do, without executing it
+    ///
+    /// Runs the same provider and schema checks as `execute`, resolves the
+    /// action's arguments and asks the plugin to render its command or HTTP
+    /// request. No `FeatureActionEvent` is emitted.
+    pub async fn plan(&self, request: &ActionRequest) -> Result<ActionPlan, PluginError> {
+        let provider = request.provider.as_str();
+        let feature = request.feature.as_str();
+        let action = request.action.as_str();
+
+        self.resolve_provider(provider, feature).await?;
+        self.feature_registry.validate_action(feature, action, &request.arguments).await?;
+        let definition = self.feature_registry.get_action(feature, action).await?;
+
+        let resolved = self.argument_manager.resolve_action_arguments(
+            provider,
+            &definition.arguments,
+            &request.arguments,
+            Some(&request.request_id.to_string()),
+        ).await?;
+        let arguments = resolved.into_iter()
+            .map(|(name, value)| {
+                let planned = PlannedArgument::new(&name, value);
+                (name, planned)
+            })
+            .collect();
+
+        let plugin = self.plugin_registry.get_plugin(provider).await
+            .ok_or_else(|| PluginError::PluginNotFound(provider.to_string()))?;
+        let rendered = plugin.read().await.plugin()
+            .plan_action(feature, action, &request.arguments, request.request_id)
+            .await?;
+
+        Ok(ActionPlan {
+            request_id: request.request_id,
+            provider: provider.to_string(),
+            feature: feature.to_string(),
+            action: action.to_string(),
+            is_mutating: definition.is_mutating,
+            estimated_duration_ms: definition.estimated_duration_ms,
+            arguments,
+            rendered,
+        })
+    }
+
+    /// Breaker settings for a provider from the `circuit_breaker` argument
+    async fn breaker_config(&self, provider: &str) -> BreakerConfig {
+        let setting = self.argument_manager
+            .get_argument(provider, CIRCUIT_BREAKER_ARGUMENT, None, ArgumentResolution::GlobalOnly)
+            .await
+            .ok()
+            .map(|argument| argument.value);
+        match setting {
+            Some(setting) => BreakerConfig::for_provider(&setting, provider).unwrap_or_else(|e| {
+                eprintln!("⚠️ {}, using the default breaker", e);
+                BreakerConfig::default()
+            }),
+            None => BreakerConfig::default(),
+        }
+    }
+
+    /// Check that a provider is loaded and declares the feature
+    async fn resolve_provider(&self, provider: &str, feature: &str) -> Result<(), PluginError> {
+        if self.plugin_registry.get_plugin(provider).await.is_none() {
+            return Err(PluginError::PluginNotFound(provider.to_string()));
+        }
+
+        let providers = self.plugin_registry.get_plugins_by_feature(feature).await;
+        if !providers.iter().any(|name| name == provider) {
+            return Err(PluginError::PluginNotFound(format!(
+                "{} does not declare feature {}", provider, feature
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Load the concurrency and rate limits from the `action_limits` argument
+    pub async fn load_limits(&self) -> Result<usize, PluginError> {
+        match self.argument_manager
+            .get_argument("", ACTION_LIMITS_ARGUMENT, None, ArgumentResolution::GlobalOnly)
+            .await
+        {
+            Ok(setting) => self.limiter.load(&setting.value),
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// Limit a provider, or a feature as `<provider>/<feature>`
+    pub fn set_limit(&self, scope: &str, limit: ActionLimit) {
+        self.limiter.set_limit(scope, limit);
+    }
+
     /// Execute multiple actions in parallel, each with explicit provider
-    pub async fn execute_batch(
-        &self,
-        batch: Vec<(String, String, String, HashMap<String, Value>)>, // provider, feature, action, args
-        timeout: Option<Duration>,
-    ) -> Vec<Result<Value, PluginError>> {
-        let futures = batch.into_iter().map(|(provider, feature, action, args)| {
-            let executor = self;
-            let timeout = timeout;
-            async move {
-                executor.execute_action(&provider, &feature, &action, args, timeout).await
-            }
-        });
+    ///
+    /// Actions over their provider's limits queue until they may run.
+    pub async fn execute_batch(&self, batch: Vec<ActionRequest>) -> Vec<ExecutionResult> {
+        let futures = batch.into_iter().map(|request| self.execute(request));
 
         futures::future::join_all(futures).await
     }
@@ -173,14 +597,22 @@ impl PluginExecutor {
     /// Handle action completion events
     async fn handle_action_complete(
         pending_requests: Arc<RwLock<HashMap<Uuid, PendingRequest>>>,
+        validator: Arc<ResultValidator>,
         event: FeatureActionCompleteEvent,
     ) {
-        let mut pending = pending_requests.write().await;
-        
-        if let Some(pending_request) = pending.remove(&event.request_id) {
+        // Released before validating, other completions shouldn't wait on it
+        let pending_request = pending_requests.write().await.remove(&event.request_id);
+
+        if let Some(pending_request) = pending_request {
             let result = match event.result {
-                Ok(value) => Ok(value),
-                Err(error_msg) => Err(PluginError::ExecutionFailed(error_msg)),
+                Ok(value) => validator.check(
+                    event.request_id,
+                    &pending_request.plugin_name,
+                    &pending_request.feature,
+                    &pending_request.action,
+                    value,
+                ).await,
+                Err(error_msg) => Err(ActionFailure::into_error(event.failure, error_msg)),
             };
 
             // Send the result back to the waiting executor
@@ -221,19 +653,97 @@ impl PluginExecutor {
             pending_requests: pending_count,
             average_wait_time,
             oldest_request_age: oldest_request.unwrap_or(Duration::ZERO),
+            provider_queues: self.limiter.queue_stats(),
+            result_violations: self.validator.violations(),
+            circuit_breakers: self.breakers.stats(),
         }
     }
 
-    /// Cancel a pending request
-    pub async fn cancel_request(&self, request_id: Uuid) -> Result<(), PluginError> {
-        let mut pending = self.pending_requests.write().await;
-        
-        if let Some(request) = pending.remove(&request_id) {
-            let _ = request.response_sender.send(Err(PluginError::ExecutionFailed("Request cancelled".to_string())));
-            Ok(())
-        } else {
-            Err(PluginError::ExecutionFailed("Request not found".to_string()))
+    /// Counts and latencies of the actions that ran, ordered by their labels
+    pub fn action_metrics(&self) -> BTreeMap<ActionLabels, ActionSeries> {
+        self.metrics.snapshot()
+    }
+
+    /// Cancel a request, telling the plugin handling it to stop
+    ///
+    /// Requests waiting for a retry or a provider limit stop before their
+    /// next attempt.
+    pub async fn cancel_request(&self, request_id: Uuid) -> Result<CancellationStatus, PluginError> {
+        self.cancel(request_id, "Cancelled by client").await
+    }
+
+    async fn cancel(&self, request_id: Uuid, reason: &str) -> Result<CancellationStatus, PluginError> {
+        let pending = self.pending_requests.write().await.remove(&request_id);
+        let provider = match &pending {
+            Some(request) => request.plugin_name.clone(),
+            None => {
+                let active = self.active_requests.lock().unwrap();
+                active.get(&request_id).cloned()
+                    .ok_or_else(|| PluginError::NotFound(format!("Request {} is not running", request_id)))?
+            }
+        };
+
+        let status = CancellationStatus {
+            request_id,
+            provider: provider.clone(),
+            reason: reason.to_string(),
+            requested_at: Utc::now(),
+            acknowledged: None,
+            acknowledged_at: None,
+            stopped: None,
+        };
+        self.cancellations.write().await.insert(request_id, status.clone());
+
+        // Only a plugin that received the action has anything to stop
+        if let Some(request) = pending {
+            let _ = request.response_sender.send(Err(PluginError::Cancelled(reason.to_string())));
+
+            let event = FeatureActionCancelEvent {
+                request_id,
+                provider: provider.clone(),
+                reason: reason.to_string(),
+            };
+            self.event_system.emit_event(&cancel_event_key(&provider), &event).await
+                .map_err(|e| PluginError::EventError(e.to_string()))?;
         }
+
+        Ok(status)
+    }
+
+    /// Get the cancellation of a request and whether the plugin acknowledged it
+    pub async fn get_cancellation(&self, request_id: Uuid) -> Option<CancellationStatus> {
+        let cancellations = self.cancellations.read().await;
+        cancellations.get(&request_id).cloned()
+    }
+
+    /// Replay a request's progress updates and follow new ones
+    pub fn subscribe_progress(&self, request_id: Uuid) -> ProgressSubscription {
+        self.progress.subscribe(request_id)
+    }
+
+    /// Progress updates a request's plugin reported so far
+    pub fn get_progress(&self, request_id: Uuid) -> Vec<ActionProgress> {
+        self.progress.history(request_id)
+    }
+
+    /// Get the schema of a feature action
+    pub async fn get_action(&self, feature: &str, action: &str) -> Result<ActionDef, PluginError> {
+        self.feature_registry.get_action(feature, action).await
+    }
+
+    /// Query the execution history
+    pub async fn query_executions(&self, query: &ExecutionQuery) -> ExecutionPage {
+        self.history.query(query).await
+    }
+
+    /// Get the recorded execution of a request
+    pub async fn get_execution(&self, request_id: Uuid) -> Option<ExecutionRecord> {
+        self.history.get(request_id).await
+    }
+
+    async fn cancellation_error(&self, request_id: Uuid) -> Option<PluginError> {
+        let cancellations = self.cancellations.read().await;
+        cancellations.get(&request_id).map(|status| PluginError::Cancelled(status.reason.clone()))
     }
 
     /// Get all pending request IDs
@@ -244,11 +754,14 @@ impl PluginExecutor {
 
     /// Cancel all pending requests
     pub async fn cancel_all_requests(&self) -> Result<usize, PluginError> {
-        let mut pending = self.pending_requests.write().await;
-        let count = pending.len();
+        let request_ids = self.get_pending_requests().await;
+        let mut count = 0;
         
-        for (_, request) in pending.drain() {
-            let _ = request.response_sender.send(Err(PluginError::ExecutionFailed("System shutdown".to_string())));
+        for request_id in request_ids {
+            // Requests that completed in the meantime are skipped
+            if self.cancel(request_id, "System shutdown").await.is_ok() {
+                count += 1;
+            }
         }
         
         Ok(count)
@@ -299,6 +812,7 @@ impl PluginExecutor {
             request_id,
             result,
             execution_time_ms,
+            failure: None,
         };
 
         self.event_system.emit_event("feature:action:complete", &completion_event).await
@@ -313,7 +827,7 @@ impl PluginExecutor {
         pending.get(&request_id).map(|request| {
             ExecutionContext {
                 request_id,
-                plugin_name: "".to_string(), // Would need to track this separately
+                plugin_name: request.plugin_name.clone(),
                 feature: request.feature.clone(),
                 action: request.action.clone(),
                 start_time: request.start_time,
@@ -325,6 +839,9 @@ impl PluginExecutor {
     /// Set up cleanup task for expired requests
     pub async fn start_cleanup_task(&self) -> tokio::task::JoinHandle<()> {
         let pending_requests = Arc::clone(&self.pending_requests);
+        let idempotency = Arc::clone(&self.idempotency);
+        let cancellations = Arc::clone(&self.cancellations);
+        let progress = Arc::clone(&self.progress);
         
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(30));
@@ -345,20 +862,61 @@ impl PluginExecutor {
                 // Remove and notify expired requests
                 for id in expired_ids {
                     if let Some(request) = pending.remove(&id) {
-                        let _ = request.response_sender.send(Err(PluginError::ExecutionFailed("Request expired".to_string())));
+                        let _ = request.response_sender.send(Err(PluginError::Timeout("Request expired".to_string())));
                     }
                 }
+                drop(pending);
+                
+                // Forget the outcomes of old idempotency keys, cancellations and progress
+                idempotency.purge_expired(IDEMPOTENCY_TTL);
+                let cutoff = Utc::now() - CANCELLATION_RETENTION;
+                cancellations.write().await.retain(|_, status| status.requested_at > cutoff);
+                progress.purge(CANCELLATION_RETENTION);
             }
         })
     }
 }
 
+/// Marks a request as active until its last attempt ends
+struct ActiveRequest<'a> {
+    executor: &'a PluginExecutor,
+    request_id: Uuid,
+}
+
+impl<'a> ActiveRequest<'a> {
+    /// Take the request's id, unless another request is running under it
+    fn claim(executor: &'a PluginExecutor, request: &ActionRequest) -> Result<Self, PluginError> {
+        {
+            let mut active = executor.active_requests.lock().unwrap();
+            if active.contains_key(&request.request_id) {
+                return Err(PluginError::Conflict(format!("Request {} is already running", request.request_id)));
+            }
+            active.insert(request.request_id, request.provider.clone());
+        }
+        executor.progress.start(request.request_id);
+        Ok(Self { executor, request_id: request.request_id })
+    }
+}
+
+impl Drop for ActiveRequest<'_> {
+    fn drop(&mut self) {
+        self.executor.active_requests.lock().unwrap().remove(&self.request_id);
+        self.executor.progress.finish(self.request_id);
+    }
+}
+
 /// Statistics about plugin executions
 #[derive(Debug, Clone)]
 pub struct ExecutionStats {
     pub pending_requests: usize,
     pub average_wait_time: Duration,
     pub oldest_request_age: Duration,
+    /// Requests queued behind each provider's limits
+    pub provider_queues: HashMap<String, ProviderQueueStats>,
+    /// Results that didn't match their return type, per plugin
+    pub result_violations: HashMap<String, PluginViolations>,
+    /// Breaker of every provider action that ran, keyed `provider/feature/action`
+    pub circuit_breakers: HashMap<String, CircuitStats>,
 }
 
 /// Helper for building execution requests
@@ -369,6 +927,7 @@ pub struct ExecutionRequestBuilder {
     arguments: HashMap<String, Value>,
     timeout: Option<Duration>,
     plugin_name: Option<String>,
+    idempotency_key: Option<String>,
 }
 
 impl ExecutionRequestBuilder {
@@ -379,6 +938,7 @@ impl ExecutionRequestBuilder {
             arguments: HashMap::new(),
             timeout: None,
             plugin_name: None,
+            idempotency_key: None,
         }
     }
 
@@ -412,13 +972,20 @@ impl ExecutionRequestBuilder {
         self
     }
 
+    pub fn idempotency_key(mut self, idempotency_key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(idempotency_key.into());
+        self
+    }
+
     pub async fn execute(self, executor: &PluginExecutor) -> Result<Value, PluginError> {
         let feature = self.feature.ok_or_else(|| PluginError::InvalidArgument("Feature not specified".to_string()))?;
         let action = self.action.ok_or_else(|| PluginError::InvalidArgument("Action not specified".to_string()))?;
 
         // Use plugin_name if present, otherwise error
-        let provider = self.plugin_name.as_deref().ok_or_else(|| PluginError::InvalidArgument("Plugin name not specified".to_string()))?;
-        executor.execute_action(provider, &feature, &action, self.arguments, self.timeout).await
+        let provider = self.plugin_name.ok_or_else(|| PluginError::InvalidArgument("Plugin name not specified".to_string()))?;
+        let mut request = ActionRequest::new(provider, feature, action, self.arguments, self.timeout);
+        request.idempotency_key = self.idempotency_key;
+        executor.execute(request).await.result
     }
 
     pub async fn execute_with_context(
@@ -498,4 +1065,43 @@ macro_rules! execute_action_with_context {
         
         builder.execute_with_context($executor, $context).await
     }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executor() -> PluginExecutor {
+        PluginExecutor::new(
+            Arc::new(EventSystem::new()),
+            Arc::new(PluginRegistry::new()),
+            Arc::new(FeatureRegistry::new()),
+            Arc::new(ArgumentManager::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn request_ids_in_flight_are_refused() {
+        let executor = executor();
+        let running = ActionRequest::new("dummy", "VM_Manage", "list_vms", HashMap::new(), None);
+        let active = ActiveRequest::claim(&executor, &running).unwrap();
+
+        let mut duplicate = ActionRequest::new("other", "VM_Manage", "delete_vm", HashMap::new(), None);
+        duplicate.request_id = running.request_id;
+        match executor.execute(duplicate.clone()).await.result {
+            Err(PluginError::Conflict(message)) => assert!(message.contains("already running"), "{}", message),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        // The running request still belongs to its first caller
+        assert_eq!(executor.active_requests.lock().unwrap().get(&running.request_id), Some(&"dummy".to_string()));
+
+        drop(active);
+        assert!(!matches!(executor.execute(duplicate).await.result, Err(PluginError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn unknown_requests_cannot_be_cancelled() {
+        let result = executor().cancel_request(Uuid::new_v4()).await;
+        assert!(matches!(result, Err(PluginError::NotFound(_))), "{:?}", result);
+    }
 }
\ No newline at end of file
@@ -8,7 +8,7 @@ use std::path::Path;
 use tokio::sync::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use super::PluginError;
+use super::{Compensation, PluginError, RetryPolicy};
 
 /// Feature definition loaded from JSON schema
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +40,12 @@ pub struct ActionDef {
     pub is_mutating: bool,
     /// Estimated execution time in milliseconds
     pub estimated_duration_ms: Option<u64>,
+    /// Retry policy for failed attempts of this action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// Action undoing this one when a batch or workflow rolls back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compensation: Option<Compensation>,
 }
 
 /// Argument definition for actions
@@ -191,6 +197,13 @@ impl FeatureRegistry {
                     },
                     is_mutating: true,
                     estimated_duration_ms: Some(30000),
+                    retry: None,
+                    compensation: Some(Compensation {
+                        action: "delete_vm".to_string(),
+                        arguments: HashMap::from([
+                            ("vm_id".to_string(), Value::String("${result.vm_id}".to_string())),
+                        ]),
+                    }),
                 }),
                 ("delete_vm".to_string(), ActionDef {
                     name: "delete_vm".to_string(),
@@ -208,6 +221,8 @@ impl FeatureRegistry {
                     return_type: ReturnType::Boolean,
                     is_mutating: true,
                     estimated_duration_ms: Some(15000),
+                    retry: None,
+                    compensation: None,
                 }),
             ]),
             global_settings: Some(HashMap::from([
@@ -256,6 +271,8 @@ impl FeatureRegistry {
                     },
                     is_mutating: true,
                     estimated_duration_ms: Some(5000),
+                    retry: None,
+                    compensation: None,
                 }),
             ]),
             global_settings: None,
@@ -298,6 +315,17 @@ impl FeatureRegistry {
         Ok(feature.actions.keys().cloned().collect())
     }
 
+    /// Get the definition of a specific action
+    pub async fn get_action(&self, feature_name: &str, action_name: &str) -> Result<ActionDef, PluginError> {
+        let features = self.features.read().await;
+        let feature = features.get(feature_name)
+            .ok_or_else(|| PluginError::UnsupportedFeature(feature_name.to_string()))?;
+        
+        feature.actions.get(action_name)
+            .cloned()
+            .ok_or_else(|| PluginError::InvalidArgument(format!("Action {} not found in feature {}", action_name, feature_name)))
+    }
+
     /// Get arguments for a specific action
     pub async fn get_action_arguments(&self, feature_name: &str, action_name: &str) -> Result<Vec<ArgumentDef>, PluginError> {
         let features = self.features.read().await;
@@ -0,0 +1,447 @@
+//! # Execution History
+//!
+//! Keeps a record of every action the executor ran: who asked for it, with
+//! which arguments, what came back, how long it took and how often it was
+//! retried. Records are appended to a JSON Lines file, so the audit trail
+//! survives restarts and can be read with ordinary tools as well as through
+//! the `/executions` API.
+//!
+//! Values of sensitive fields (passwords, secrets, tokens and keys) are
+//! replaced before a record is stored, in the arguments as well as the result.
+//!
+//! Only the latest records are kept, [`DEFAULT_RETAINED_RECORDS`] unless
+//! configured otherwise. Older ones are dropped from memory as new ones come
+//! in, and the file is rewritten without them once it holds twice as many.
+//!
+//! The caller is whatever the API was told, nothing authenticates it. API
+//! callers are recorded as `unverified:<name>` from the `X-Caller` header, or
+//! `address:<ip>` of the client without one.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use super::json_cpi::cassette::is_sensitive_name;
+use super::{ActionRequest, ExecutionAttempt, ExecutionResult, PluginError};
+
+/// Default file for the execution history
+pub const DEFAULT_HISTORY_PATH: &str = "./data/executions.jsonl";
+
+/// Records returned by a query that doesn't set a limit
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Most records a single query returns
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Records kept unless configured otherwise
+pub const DEFAULT_RETAINED_RECORDS: usize = 10_000;
+
+/// Stored in place of sensitive argument values
+const REDACTED: &str = "[redacted]";
+
+/// How an execution ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionStatus {
+    Succeeded,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+impl ExecutionStatus {
+    pub fn of(result: &Result<Value, PluginError>) -> Self {
+        match result {
+            Ok(_) => ExecutionStatus::Succeeded,
+            Err(PluginError::Timeout(_)) => ExecutionStatus::TimedOut,
+            Err(PluginError::Cancelled(_)) => ExecutionStatus::Cancelled,
+            Err(_) => ExecutionStatus::Failed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Succeeded => "succeeded",
+            ExecutionStatus::Failed => "failed",
+            ExecutionStatus::TimedOut => "timed_out",
+            ExecutionStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for ExecutionStatus {
+    type Err = PluginError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "succeeded" => Ok(ExecutionStatus::Succeeded),
+            "failed" => Ok(ExecutionStatus::Failed),
+            "timed_out" => Ok(ExecutionStatus::TimedOut),
+            "cancelled" => Ok(ExecutionStatus::Cancelled),
+            other => Err(PluginError::InvalidArgument(format!(
+                "Unknown execution status {}, expected succeeded, failed, timed_out or cancelled", other
+            ))),
+        }
+    }
+}
+
+/// One executed action as kept in the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionRecord {
+    pub request_id: Uuid,
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    /// Arguments of the request, with sensitive values replaced
+    pub arguments: HashMap<String, Value>,
+    /// Who asked for the action, as claimed by the client, see the module docs
+    #[serde(default)]
+    pub caller: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    pub status: ExecutionStatus,
+    /// Result of the action, with sensitive values replaced
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub execution_time_ms: u64,
+    #[serde(default)]
+    pub attempts: Vec<ExecutionAttempt>,
+}
+
+impl ExecutionRecord {
+    pub fn new(request: &ActionRequest, execution: &ExecutionResult, started_at: DateTime<Utc>) -> Self {
+        let (result, error) = match &execution.result {
+            Ok(value) => (Some(redact("", value)), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            request_id: request.request_id,
+            provider: request.provider.clone(),
+            feature: request.feature.clone(),
+            action: request.action.clone(),
+            arguments: redact_arguments(&request.arguments),
+            caller: request.caller.clone(),
+            idempotency_key: request.idempotency_key.clone(),
+            status: ExecutionStatus::of(&execution.result),
+            result,
+            error,
+            started_at,
+            finished_at: Utc::now(),
+            execution_time_ms: execution.execution_time.as_millis() as u64,
+            attempts: execution.attempts.clone(),
+        }
+    }
+}
+
+/// Filters and page of a history query, unset filters match everything
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionQuery {
+    pub provider: Option<String>,
+    pub feature: Option<String>,
+    pub action: Option<String>,
+    pub status: Option<ExecutionStatus>,
+    pub caller: Option<String>,
+    /// Only executions that started at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only executions that started before this time
+    pub until: Option<DateTime<Utc>>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl ExecutionQuery {
+    fn matches(&self, record: &ExecutionRecord) -> bool {
+        let equals = |filter: &Option<String>, value: &str| filter.as_deref().is_none_or(|filter| filter == value);
+        equals(&self.provider, &record.provider)
+            && equals(&self.feature, &record.feature)
+            && equals(&self.action, &record.action)
+            && self.status.is_none_or(|status| status == record.status)
+            && self.caller.as_deref().is_none_or(|caller| record.caller.as_deref() == Some(caller))
+            && self.since.is_none_or(|since| record.started_at >= since)
+            && self.until.is_none_or(|until| record.started_at < until)
+    }
+}
+
+/// One page of matching records, newest first
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionPage {
+    /// Matching records across all pages
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub records: Vec<ExecutionRecord>,
+}
+
+/// Executed actions in memory, appended to a JSON Lines file
+#[derive(Debug)]
+pub struct ExecutionHistory {
+    path: Option<PathBuf>,
+    /// Most records kept
+    retained: usize,
+    records: RwLock<Records>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    /// The latest records, in the order the executions finished
+    latest: VecDeque<ExecutionRecord>,
+    /// Records in the file, including ones no longer kept
+    in_file: usize,
+}
+
+impl Default for ExecutionHistory {
+    fn default() -> Self {
+        Self { path: None, retained: DEFAULT_RETAINED_RECORDS, records: RwLock::default() }
+    }
+}
+
+impl ExecutionHistory {
+    /// History that is lost when the director stops
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `retained` records, at least one
+    pub fn with_retention(mut self, retained: usize) -> Self {
+        self.retained = retained.max(1);
+        self
+    }
+
+    /// Load the history from `path`, appending new records to it
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, PluginError> {
+        Self::open_retaining(path, DEFAULT_RETAINED_RECORDS).await
+    }
+
+    /// Load the latest `retained` records from `path`, appending new records to it
+    pub async fn open_retaining(path: impl Into<PathBuf>, retained: usize) -> Result<Self, PluginError> {
+        let path = path.into();
+        let retained = retained.max(1);
+        let mut records = Records::default();
+
+        if path.exists() {
+            let content = tokio::fs::read_to_string(&path).await?;
+            for (number, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // A crash mid-write leaves a partial last line, skip it
+                match serde_json::from_str(line) {
+                    Ok(record) => {
+                        records.latest.push_back(record);
+                        records.in_file += 1;
+                        if records.latest.len() > retained {
+                            records.latest.pop_front();
+                        }
+                    }
+                    Err(e) => eprintln!("Skipping line {} of {:?}: {}", number + 1, path, e),
+                }
+            }
+        }
+
+        let history = Self { path: Some(path), retained, records: RwLock::new(records) };
+        let mut records = history.records.write().await;
+        if records.in_file > retained {
+            history.compact(&mut records).await?;
+        }
+        drop(records);
+        Ok(history)
+    }
+
+    /// Add a finished execution, dropping the oldest one past the retention
+    pub async fn record(&self, record: ExecutionRecord) -> Result<(), PluginError> {
+        let mut records = self.records.write().await;
+
+        // Appended under the lock so the file keeps the same order
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            let mut line = serde_json::to_string(&record)?;
+            line.push('\n');
+            let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+            file.write_all(line.as_bytes()).await?;
+            records.in_file += 1;
+        }
+
+        records.latest.push_back(record);
+        if records.latest.len() > self.retained {
+            records.latest.pop_front();
+        }
+        if records.in_file >= self.retained.saturating_mul(2) {
+            self.compact(&mut records).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the file with only the kept records
+    async fn compact(&self, records: &mut Records) -> Result<(), PluginError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut content = String::new();
+        for record in &records.latest {
+            content.push_str(&serde_json::to_string(record)?);
+            content.push('\n');
+        }
+
+        // Written aside and renamed, so a crash leaves either file whole
+        let temporary = path.with_extension("jsonl.tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, path).await?;
+        records.in_file = records.latest.len();
+        Ok(())
+    }
+
+    /// The latest execution of a request
+    pub async fn get(&self, request_id: Uuid) -> Option<ExecutionRecord> {
+        let records = self.records.read().await;
+        records.latest.iter().rev().find(|record| record.request_id == request_id).cloned()
+    }
+
+    /// Matching executions, newest first
+    pub async fn query(&self, query: &ExecutionQuery) -> ExecutionPage {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let records = self.records.read().await;
+
+        let matching: Vec<&ExecutionRecord> = records.latest.iter().rev().filter(|record| query.matches(record)).collect();
+        ExecutionPage {
+            total: matching.len(),
+            offset: query.offset,
+            limit,
+            records: matching.into_iter().skip(query.offset).take(limit).cloned().collect(),
+        }
+    }
+
+    /// Number of kept executions
+    pub async fn len(&self) -> usize {
+        self.records.read().await.latest.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+/// Copy of the arguments with sensitive values replaced, also in nested objects
+pub fn redact_arguments(arguments: &HashMap<String, Value>) -> HashMap<String, Value> {
+    arguments.iter()
+        .map(|(name, value)| (name.clone(), redact(name, value)))
+        .collect()
+}
+
+fn redact(name: &str, value: &Value) -> Value {
+    if is_sensitive_name(name) {
+        return Value::String(REDACTED.to_string());
+    }
+    match value {
+        Value::Object(object) => Value::Object(
+            object.iter().map(|(name, value)| (name.clone(), redact(name, value))).collect()
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(|item| redact(name, item)).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn execution(provider: &str, action: &str, result: Result<Value, PluginError>) -> (ActionRequest, ExecutionResult) {
+        let mut request = ActionRequest::new(
+            provider, "Worker_Manage", action,
+            serde_json::from_value(json!({ "name": "w-1", "api_token": "abcd1234", "ssh": { "password": "hunter22" } })).unwrap(),
+            None,
+        );
+        request.caller = Some("ops@example.com".to_string());
+        let execution = ExecutionResult {
+            request_id: request.request_id,
+            result,
+            execution_time: Duration::from_millis(12),
+            plugin_name: provider.to_string(),
+            attempts: Vec::new(),
+        };
+        (request, execution)
+    }
+
+    #[test]
+    fn sensitive_values_are_redacted() {
+        let created = json!({
+            "id": 7, "root_password": "s3cret", "ssh_keys": ["id1", "id2"], "keys": [{ "private_key": "-----BEGIN" }]
+        });
+        let (request, result) = execution("hetzner", "create_worker", Ok(created));
+        let record = ExecutionRecord::new(&request, &result, Utc::now());
+
+        assert_eq!(record.arguments["name"], json!("w-1"));
+        assert_eq!(record.arguments["api_token"], json!(REDACTED));
+        assert_eq!(record.arguments["ssh"], json!({ "password": REDACTED }));
+        assert_eq!(record.result, Some(json!({
+            "id": 7, "root_password": REDACTED, "ssh_keys": ["id1", "id2"], "keys": [{ "private_key": REDACTED }]
+        })));
+        assert_eq!(record.caller.as_deref(), Some("ops@example.com"));
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_records_are_kept() {
+        let path = std::env::temp_dir().join(format!("omni-history-{}.jsonl", Uuid::new_v4()));
+        let history = ExecutionHistory::open_retaining(&path, 2).await.unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let (request, execution) = execution("hetzner", "delete_worker", Ok(json!({})));
+            ids.push(request.request_id);
+            history.record(ExecutionRecord::new(&request, &execution, Utc::now())).await.unwrap();
+        }
+        assert_eq!(history.len().await, 2);
+        assert!(history.get(ids[2]).await.is_none());
+        assert!(history.get(ids[4]).await.is_some());
+
+        // The file is compacted as it grows, and trimmed when opened with less
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < 4, "{} lines", lines);
+        let reopened = ExecutionHistory::open_retaining(&path, 1).await.unwrap();
+        assert_eq!(reopened.get(ids[4]).await.map(|record| record.request_id), Some(ids[4]));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn queries_filter_and_page_newest_first() {
+        let path = std::env::temp_dir().join(format!("omni-history-{}.jsonl", Uuid::new_v4()));
+        let history = ExecutionHistory::open(&path).await.unwrap();
+
+        for (provider, action, result) in [
+            ("hetzner", "create_worker", Ok(json!({ "id": 1 }))),
+            ("hetzner", "delete_worker", Ok(json!({}))),
+            ("linode", "delete_worker", Err(PluginError::Timeout("30s".into()))),
+            ("hetzner", "delete_worker", Err(PluginError::ExecutionFailed("exit 1".into()))),
+        ] {
+            let (request, execution) = execution(provider, action, result);
+            history.record(ExecutionRecord::new(&request, &execution, Utc::now())).await.unwrap();
+        }
+
+        let deletes = ExecutionQuery { action: Some("delete_worker".into()), limit: Some(2), ..Default::default() };
+        let page = history.query(&deletes).await;
+        assert_eq!(page.total, 3);
+        let statuses: Vec<ExecutionStatus> = page.records.iter().map(|record| record.status).collect();
+        assert_eq!(statuses, [ExecutionStatus::Failed, ExecutionStatus::TimedOut]);
+
+        let second = history.query(&ExecutionQuery { offset: 2, ..deletes }).await;
+        assert_eq!(second.records.len(), 1);
+        assert_eq!(second.records[0].status, ExecutionStatus::Succeeded);
+
+        // The records come back after a restart
+        let reopened = ExecutionHistory::open(&path).await.unwrap();
+        let hetzner = ExecutionQuery { provider: Some("hetzner".into()), ..Default::default() };
+        assert_eq!(reopened.query(&hetzner).await.total, 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
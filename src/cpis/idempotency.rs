@@ -0,0 +1,321 @@
+//! # Idempotency Keys
+//!
+//! Remembers the outcome of requests that carry an idempotency key, so a
+//! client repeating `create_vm` after a network blip gets the original
+//! result instead of a second VM.
+//!
+//! A key is stored with a fingerprint of the provider, feature, action and
+//! arguments. Repeating the same request attaches to the execution still in
+//! flight or returns its result, reusing the key for a different request is
+//! a conflict. Successes and failures that would come out the same again,
+//! like a refused argument, are kept for `IDEMPOTENCY_TTL`. Transient
+//! failures (timeouts, cancels, an open circuit, provider errors) are handed
+//! to the requests attached at the time and then release the key, so a
+//! client's retry runs again.
+//!
+//! A mutating action that timed out is the exception: it may still be
+//! finishing on the provider, so running it again could create a second VM.
+//! Its key is kept, and repeats get a conflict telling the client to check
+//! the provider before retrying under a new key.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::{Map, Value};
+use tokio::sync::watch;
+use super::retry::ErrorClass;
+use super::{ActionRequest, ExecutionResult, PluginError};
+
+/// How long a finished outcome is kept for its key
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+type Outcome = Option<ExecutionResult>;
+
+/// What a request finds under its idempotency key
+#[derive(Debug)]
+pub enum Claim {
+    /// The key is new, the caller runs the action and completes the guard
+    Owner(IdempotencyGuard),
+    /// The same request is still running
+    InFlight(watch::Receiver<Outcome>),
+    /// The same request already finished
+    Completed(ExecutionResult),
+    /// The key was used for a different request
+    Conflict,
+}
+
+/// Outcomes of the requests made under an idempotency key
+#[derive(Debug, Default)]
+pub struct IdempotencyStore {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    fingerprint: String,
+    created_at: Instant,
+    outcome: watch::Receiver<Outcome>,
+}
+
+impl IdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up a key for a request, claiming it if it is new
+    pub fn claim(&self, key: &str, request: &ActionRequest) -> Claim {
+        let fingerprint = fingerprint(request);
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(entry) = entries.get(key) {
+            if entry.fingerprint != fingerprint {
+                return Claim::Conflict;
+            }
+            let outcome = entry.outcome.borrow().clone();
+            return match outcome {
+                Some(result) => Claim::Completed(result),
+                None => Claim::InFlight(entry.outcome.clone()),
+            };
+        }
+
+        let (sender, outcome) = watch::channel(None);
+        entries.insert(key.to_string(), Entry { fingerprint, created_at: Instant::now(), outcome });
+        Claim::Owner(IdempotencyGuard {
+            key: key.to_string(),
+            sender: Some(sender),
+            entries: Arc::clone(&self.entries),
+        })
+    }
+
+    /// Drop the outcomes of keys older than `ttl`, returns how many
+    pub fn purge_expired(&self, ttl: Duration) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.created_at.elapsed() < ttl || entry.outcome.borrow().is_none());
+        before - entries.len()
+    }
+
+    /// Number of keys currently stored
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Claim on a new key, releases the key if dropped without completing
+#[derive(Debug)]
+pub struct IdempotencyGuard {
+    key: String,
+    sender: Option<watch::Sender<Outcome>>,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl IdempotencyGuard {
+    /// Hand the outcome to every attached request, and keep it for later
+    /// repeats unless the failure was transient
+    ///
+    /// `mutating` tells whether the action changes provider state.
+    pub fn complete(mut self, result: &ExecutionResult, mutating: bool) {
+        if let Some(sender) = self.sender.take() {
+            let indeterminate = mutating && matches!(result.result, Err(PluginError::Timeout(_)));
+            let outcome = if indeterminate {
+                ExecutionResult {
+                    result: Err(PluginError::Conflict(format!(
+                        "Request with idempotency key {} timed out and may still complete on the provider, \
+                         check its state before retrying under a new key", self.key
+                    ))),
+                    ..result.clone()
+                }
+            } else {
+                result.clone()
+            };
+            sender.send_replace(Some(outcome));
+            if !indeterminate && !is_final(&result.result) {
+                self.entries.lock().unwrap().remove(&self.key);
+            }
+        }
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        // The owner gave up, let the next request with this key run again
+        if self.sender.is_some() {
+            self.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+/// Wait for the execution a request attached to
+///
+/// Returns `None` when its owner was dropped before finishing.
+pub async fn wait_for(mut outcome: watch::Receiver<Outcome>) -> Option<ExecutionResult> {
+    loop {
+        let current = outcome.borrow().clone();
+        if current.is_some() {
+            return current;
+        }
+        if outcome.changed().await.is_err() {
+            return outcome.borrow().clone();
+        }
+    }
+}
+
+/// Whether running the request again would end the same way
+fn is_final(result: &Result<Value, PluginError>) -> bool {
+    match result {
+        Ok(_) => true,
+        Err(PluginError::Cancelled(_) | PluginError::CircuitOpen(_)) => false,
+        Err(PluginError::HttpStatus { status: 408, .. }) => false,
+        Err(error) => ErrorClass::of(error).is_none(),
+    }
+}
+
+/// Identity of a request for matching repeats, independent of argument order
+fn fingerprint(request: &ActionRequest) -> String {
+    let arguments: BTreeMap<&String, Value> = request.arguments.iter()
+        .map(|(name, value)| (name, canonical(value)))
+        .collect();
+    serde_json::json!([request.provider, request.feature, request.action, arguments]).to_string()
+}
+
+/// Copy of a value with every object's keys in sorted order
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let sorted: BTreeMap<&String, Value> = object.iter().map(|(k, v)| (k, canonical(v))).collect();
+            Value::Object(sorted.into_iter().map(|(k, v)| (k.clone(), v)).collect::<Map<String, Value>>())
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonical).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(arguments: Value) -> ActionRequest {
+        ActionRequest::new(
+            "dummy", "VM_Manage", "create_vm",
+            serde_json::from_value(arguments).unwrap(),
+            None,
+        )
+    }
+
+    fn finished(request: &ActionRequest) -> ExecutionResult {
+        ExecutionResult {
+            request_id: request.request_id,
+            result: Ok(json!({ "id": "7" })),
+            execution_time: Duration::from_millis(5),
+            plugin_name: request.provider.clone(),
+            attempts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeats_get_the_original_result() {
+        let store = IdempotencyStore::new();
+        let first = request(json!({ "name": "web-1", "tags": { "b": 1, "a": 2 } }));
+        let repeat = request(json!({ "tags": { "a": 2, "b": 1 }, "name": "web-1" }));
+
+        let guard = match store.claim("key-1", &first) {
+            Claim::Owner(guard) => guard,
+            other => panic!("expected to own the key, got {:?}", other),
+        };
+        let attached = match store.claim("key-1", &repeat) {
+            Claim::InFlight(outcome) => tokio::spawn(wait_for(outcome)),
+            other => panic!("expected the execution in flight, got {:?}", other),
+        };
+
+        guard.complete(&finished(&first), true);
+        let result = attached.await.unwrap().unwrap();
+        assert_eq!(result.request_id, first.request_id);
+
+        match store.claim("key-1", &repeat) {
+            Claim::Completed(result) => assert_eq!(result.request_id, first.request_id),
+            other => panic!("expected the stored result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn different_requests_conflict() {
+        let store = IdempotencyStore::new();
+        let _guard = store.claim("key-1", &request(json!({ "name": "web-1" })));
+
+        assert!(matches!(store.claim("key-1", &request(json!({ "name": "web-2" }))), Claim::Conflict));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_release_the_key() {
+        let store = IdempotencyStore::new();
+        let first = request(json!({ "name": "web-1" }));
+
+        for error in [
+            PluginError::Timeout("30s".into()),
+            PluginError::Cancelled("Cancelled by client".into()),
+            PluginError::CircuitOpen("dummy".into()),
+            PluginError::HttpStatus { status: 503, message: String::new() },
+        ] {
+            let guard = match store.claim("key-1", &first) {
+                Claim::Owner(guard) => guard,
+                other => panic!("expected to own the key after {}, got {:?}", error, other),
+            };
+            let attached = match store.claim("key-1", &first) {
+                Claim::InFlight(outcome) => outcome,
+                other => panic!("expected the execution in flight, got {:?}", other),
+            };
+            guard.complete(&ExecutionResult { result: Err(error), ..finished(&first) }, false);
+
+            // The request waiting at the time still gets the failure
+            assert!(wait_for(attached).await.unwrap().result.is_err());
+            assert!(store.is_empty());
+        }
+
+        let guard = match store.claim("key-1", &first) {
+            Claim::Owner(guard) => guard,
+            other => panic!("expected to own the key, got {:?}", other),
+        };
+        let refused = PluginError::InvalidArgument("memory_mb must be positive".into());
+        guard.complete(&ExecutionResult { result: Err(refused), ..finished(&first) }, true);
+        assert!(matches!(store.claim("key-1", &first), Claim::Completed(_)));
+    }
+
+    #[tokio::test]
+    async fn timed_out_mutations_hold_the_key() {
+        let store = IdempotencyStore::new();
+        let first = request(json!({ "name": "web-1" }));
+
+        let guard = match store.claim("key-1", &first) {
+            Claim::Owner(guard) => guard,
+            other => panic!("expected to own the key, got {:?}", other),
+        };
+        guard.complete(&ExecutionResult { result: Err(PluginError::Timeout("30s".into())), ..finished(&first) }, true);
+
+        match store.claim("key-1", &first) {
+            Claim::Completed(result) => assert!(matches!(result.result, Err(PluginError::Conflict(_)))),
+            other => panic!("expected the key to be held, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn abandoned_keys_are_released() {
+        let store = IdempotencyStore::new();
+        let first = request(json!({ "name": "web-1" }));
+
+        let guard = store.claim("key-1", &first);
+        let attached = match store.claim("key-1", &first) {
+            Claim::InFlight(outcome) => outcome,
+            other => panic!("expected the execution in flight, got {:?}", other),
+        };
+        drop(guard);
+
+        assert!(wait_for(attached).await.is_none());
+        assert!(store.is_empty());
+        assert!(matches!(store.claim("key-1", &first), Claim::Owner(_)));
+    }
+}
@@ -0,0 +1,501 @@
+//! # Jobs
+//!
+//! Runs feature actions in the background, so long operations like VM
+//! creation don't hold an HTTP request open until the plugin completes.
+//!
+//! Every job is written to `<store_dir>/<id>.json` whenever its state
+//! changes, so results and in-progress state survive a director restart.
+//! Jobs still queued at startup are run again. Jobs that were running are
+//! marked `interrupted` instead, the provider may already have acted on them.
+//!
+//! Finished jobs are kept for [`DEFAULT_JOB_RETENTION`] unless configured
+//! otherwise, older ones are removed when jobs are loaded or submitted.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use super::{ActionRequest, ExecutionAttempt, PluginError, PluginExecutor};
+
+/// Default directory for persisted jobs
+pub const DEFAULT_JOB_DIR: &str = "./data/jobs";
+
+/// How long finished jobs are kept unless configured otherwise
+pub const DEFAULT_JOB_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long cancelling a running job waits for its action to reach the executor
+const CANCEL_WAIT: Duration = Duration::from_secs(5);
+
+/// Lifecycle state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// The director stopped while the job was running
+    Interrupted,
+}
+
+impl JobStatus {
+    /// Whether the job reached a final state
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// The action a job runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRequest {
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+    pub timeout_seconds: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Who submitted the job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+}
+
+/// A submitted job with its status, result and timings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    /// Executor request the job runs as
+    pub request_id: Uuid,
+    #[serde(flatten)]
+    pub request: JobRequest,
+    pub status: JobStatus,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub execution_time_ms: Option<u64>,
+    #[serde(default)]
+    pub attempts: Vec<ExecutionAttempt>,
+}
+
+impl Job {
+    fn new(request: JobRequest) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            request_id: Uuid::new_v4(),
+            request,
+            status: JobStatus::Queued,
+            result: None,
+            error: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            execution_time_ms: None,
+            attempts: Vec::new(),
+        }
+    }
+}
+
+/// Runs jobs through the executor and keeps their persisted state current
+#[derive(Debug)]
+pub struct JobManager {
+    executor: Arc<PluginExecutor>,
+    store: Arc<JobStore>,
+    /// Tasks of the jobs that are queued or running
+    tasks: Arc<Mutex<HashMap<Uuid, JoinHandle<()>>>>,
+    /// How long finished jobs are kept
+    retention: Duration,
+}
+
+impl JobManager {
+    pub fn new(executor: Arc<PluginExecutor>, store_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            executor,
+            store: Arc::new(JobStore {
+                dir: store_dir.into(),
+                jobs: RwLock::new(HashMap::new()),
+            }),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            retention: DEFAULT_JOB_RETENTION,
+        }
+    }
+
+    /// Keep finished jobs for `retention` after they finish
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Load the persisted jobs, re-running the ones that never started
+    pub async fn load(&self) -> Result<usize, PluginError> {
+        let dir = self.store.dir.clone();
+
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let mut loaded = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                match read_job(&path).await {
+                    Ok(job) => loaded.push(job),
+                    Err(e) => eprintln!("Failed to load job from {:?}: {}", path, e),
+                }
+            }
+        }
+
+        let count = loaded.len();
+        let mut queued = Vec::new();
+        for mut job in loaded {
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Interrupted;
+                job.error = Some("Director restarted while the job was running".to_string());
+                job.finished_at = Some(Utc::now());
+            }
+            if job.status == JobStatus::Queued {
+                queued.push(job.clone());
+            }
+            self.store.insert(job).await?;
+        }
+
+        for job in queued {
+            self.start(job).await;
+        }
+
+        Ok(count.saturating_sub(self.prune().await))
+    }
+
+    /// Queue an action and return the job right away
+    pub async fn submit(&self, request: JobRequest) -> Result<Job, PluginError> {
+        self.prune().await;
+        let job = Job::new(request);
+        self.store.insert(job.clone()).await?;
+        self.start(job.clone()).await;
+        Ok(job)
+    }
+
+    /// Get a job by id
+    pub async fn get_job(&self, id: Uuid) -> Option<Job> {
+        let jobs = self.store.jobs.read().await;
+        jobs.get(&id).cloned()
+    }
+
+    /// List all known jobs, newest first
+    pub async fn list_jobs(&self) -> Vec<Job> {
+        let jobs = self.store.jobs.read().await;
+        let mut jobs: Vec<Job> = jobs.values().cloned().collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    /// Remove finished jobs past the retention, returning how many
+    async fn prune(&self) -> usize {
+        let Ok(retention) = chrono::Duration::from_std(self.retention) else {
+            return 0;
+        };
+        let cutoff = Utc::now() - retention;
+        let expired: Vec<Uuid> = {
+            let jobs = self.store.jobs.read().await;
+            jobs.values()
+                .filter(|job| job.status.is_finished() && job.finished_at.is_some_and(|finished| finished < cutoff))
+                .map(|job| job.id)
+                .collect()
+        };
+        for id in &expired {
+            if let Err(e) = self.store.remove(*id).await {
+                eprintln!("Failed to remove job {}: {}", id, e);
+            }
+        }
+        expired.len()
+    }
+
+    /// Cancel a job that has not finished yet
+    ///
+    /// Returns the job as it is afterwards, finished jobs are left unchanged.
+    /// A running job's request is cancelled rather than its task aborted, so
+    /// the executor still records how it ended.
+    pub async fn cancel_job(&self, id: Uuid) -> Option<Job> {
+        let mut was_running = false;
+        let cancelled = self.store.update(id, |job| {
+            if job.status.is_finished() {
+                return false;
+            }
+            was_running = job.status == JobStatus::Running;
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(Utc::now());
+            true
+        }).await;
+
+        match cancelled {
+            Some(job) => {
+                // A queued job sees the cancel when its task starts and skips the
+                // action. A running one may not have reached the executor yet.
+                if was_running {
+                    self.cancel_request_when_started(id, job.request_id).await;
+                }
+                Some(job)
+            }
+            None => self.get_job(id).await,
+        }
+    }
+
+    /// Cancel a running job's request, waiting up to `CANCEL_WAIT` for the
+    /// executor to pick it up
+    async fn cancel_request_when_started(&self, id: Uuid, request_id: Uuid) {
+        let deadline = tokio::time::Instant::now() + CANCEL_WAIT;
+        loop {
+            match self.executor.cancel_request(request_id).await {
+                Err(PluginError::NotFound(_)) if self.is_task_alive(id).await => {
+                    if tokio::time::Instant::now() >= deadline {
+                        eprintln!("Job {} did not reach the executor within {:?}, its action may still run", id, CANCEL_WAIT);
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Whether the job's task is still queued or running
+    async fn is_task_alive(&self, id: Uuid) -> bool {
+        self.tasks.lock().await.get(&id).is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Run a queued job in the background
+    async fn start(&self, job: Job) {
+        let executor = Arc::clone(&self.executor);
+        let store = Arc::clone(&self.store);
+        let tasks = Arc::clone(&self.tasks);
+        let id = job.id;
+
+        // Hold the lock until the handle is stored, so the task can't finish
+        // and remove itself first
+        let mut running = self.tasks.lock().await;
+        let handle = tokio::spawn(async move {
+            Self::run(executor, &store, job).await;
+            tasks.lock().await.remove(&id);
+        });
+        running.insert(id, handle);
+    }
+
+    async fn run(executor: Arc<PluginExecutor>, store: &JobStore, job: Job) {
+        let started = store.update(job.id, |job| {
+            if job.status != JobStatus::Queued {
+                return false;
+            }
+            job.status = JobStatus::Running;
+            job.started_at = Some(Utc::now());
+            true
+        }).await;
+        if started.is_none() {
+            return;
+        }
+
+        let request = &job.request;
+        let mut action_request = ActionRequest::new(
+            &request.provider,
+            &request.feature,
+            &request.action,
+            request.arguments.clone(),
+            Some(Duration::from_secs(request.timeout_seconds)),
+        );
+        action_request.request_id = job.request_id;
+        action_request.idempotency_key = request.idempotency_key.clone();
+        action_request.caller = request.caller.clone();
+        let execution = executor.execute(action_request).await;
+
+        store.update(job.id, |job| {
+            // A cancelled job keeps its cancelled state
+            if job.status != JobStatus::Running {
+                return false;
+            }
+            match execution.result {
+                Ok(value) => {
+                    job.status = JobStatus::Succeeded;
+                    job.result = Some(value);
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+            job.finished_at = Some(Utc::now());
+            job.execution_time_ms = Some(execution.execution_time.as_millis() as u64);
+            job.attempts = execution.attempts;
+            true
+        }).await;
+    }
+}
+
+/// Jobs in memory, mirrored to one file per job
+#[derive(Debug)]
+struct JobStore {
+    dir: PathBuf,
+    jobs: RwLock<HashMap<Uuid, Job>>,
+}
+
+impl JobStore {
+    async fn insert(&self, job: Job) -> Result<(), PluginError> {
+        let mut jobs = self.jobs.write().await;
+        self.save(&job).await?;
+        jobs.insert(job.id, job);
+        Ok(())
+    }
+
+    /// Apply a change to a job and persist it
+    ///
+    /// `change` returns whether it changed anything, the updated job is
+    /// returned only then.
+    async fn update<F>(&self, id: Uuid, change: F) -> Option<Job>
+    where
+        F: FnOnce(&mut Job) -> bool,
+    {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.get_mut(&id)?;
+        if !change(job) {
+            return None;
+        }
+
+        // Saved under the lock so the file never goes back to an older state
+        if let Err(e) = self.save(job).await {
+            eprintln!("Failed to persist job {}: {}", id, e);
+        }
+        Some(job.clone())
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<(), PluginError> {
+        let mut jobs = self.jobs.write().await;
+        jobs.remove(&id);
+        tokio::fs::remove_file(self.dir.join(format!("{}.json", id))).await?;
+        Ok(())
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), PluginError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{}.json", job.id));
+        let partial = path.with_extension("json.tmp");
+
+        // Replace the file in one step, a crash mid-write leaves the old state
+        tokio::fs::write(&partial, serde_json::to_string_pretty(job)?).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+}
+
+async fn read_job(path: &Path) -> Result<Job, PluginError> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ArgumentManager, EventSystem, FeatureRegistry, PluginRegistry};
+
+    fn executor() -> Arc<PluginExecutor> {
+        Arc::new(PluginExecutor::new(
+            Arc::new(EventSystem::new()),
+            Arc::new(PluginRegistry::new()),
+            Arc::new(FeatureRegistry::new()),
+            Arc::new(ArgumentManager::new()),
+        ))
+    }
+
+    fn job(status: JobStatus) -> Job {
+        let mut job = Job::new(JobRequest {
+            provider: "missing".to_string(),
+            feature: "VM_Manage".to_string(),
+            action: "list_vms".to_string(),
+            arguments: HashMap::new(),
+            timeout_seconds: 1,
+            idempotency_key: None,
+            caller: None,
+        });
+        job.status = status;
+        job
+    }
+
+    #[tokio::test]
+    async fn jobs_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("omni-jobs-{}", uuid::Uuid::new_v4()));
+        let before = JobManager::new(executor(), &dir);
+
+        let mut succeeded = job(JobStatus::Succeeded);
+        succeeded.result = Some(serde_json::json!({ "id": "7" }));
+        let running = job(JobStatus::Running);
+        let queued = job(JobStatus::Queued);
+        for job in [&succeeded, &running, &queued] {
+            before.store.insert(job.clone()).await.unwrap();
+        }
+
+        let after = JobManager::new(executor(), &dir);
+        assert_eq!(after.load().await.unwrap(), 3);
+
+        let reloaded = after.get_job(succeeded.id).await.unwrap();
+        assert_eq!(reloaded.status, JobStatus::Succeeded);
+        assert_eq!(reloaded.result, succeeded.result);
+        assert_eq!(after.get_job(running.id).await.unwrap().status, JobStatus::Interrupted);
+
+        // The queued job runs again, and fails as its provider isn't loaded
+        let mut rerun = after.get_job(queued.id).await.unwrap();
+        for _ in 0..50 {
+            if rerun.status.is_finished() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            rerun = after.get_job(queued.id).await.unwrap();
+        }
+        assert_eq!(rerun.status, JobStatus::Failed);
+        assert!(rerun.error.unwrap().contains("missing"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_cannot_be_cancelled() {
+        let dir = std::env::temp_dir().join(format!("omni-jobs-{}", uuid::Uuid::new_v4()));
+        let manager = JobManager::new(executor(), &dir);
+        let failed = job(JobStatus::Failed);
+        manager.store.insert(failed.clone()).await.unwrap();
+
+        assert_eq!(manager.cancel_job(failed.id).await.unwrap().status, JobStatus::Failed);
+        assert!(manager.cancel_job(Uuid::new_v4()).await.is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn old_finished_jobs_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("omni-jobs-{}", uuid::Uuid::new_v4()));
+        let manager = JobManager::new(executor(), &dir).with_retention(Duration::from_secs(60 * 60));
+
+        let mut old = job(JobStatus::Succeeded);
+        old.finished_at = Some(Utc::now() - chrono::Duration::hours(2));
+        let mut recent = job(JobStatus::Failed);
+        recent.finished_at = Some(Utc::now());
+        for job in [&old, &recent] {
+            manager.store.insert(job.clone()).await.unwrap();
+        }
+
+        assert_eq!(manager.prune().await, 1);
+        assert!(manager.get_job(old.id).await.is_none());
+        assert!(!dir.join(format!("{}.json", old.id)).exists());
+        assert!(manager.get_job(recent.id).await.is_some());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
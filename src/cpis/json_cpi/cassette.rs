@@ -0,0 +1,586 @@
+//! # Cassettes
+//!
+//! Records the commands and HTTP requests a CPI runs, together with their
+//! results, so the same flows can later be replayed without the provider's
+//! CLI or API.
+//!
+//! The mode is chosen through the `cassette_mode` global argument (e.g.
+//! `OMNI_cassette_mode`), either one mode for every provider or an object
+//! keyed by provider name with `"*"` as the fallback:
+//!
+//! ```text
+//! OMNI_cassette_mode=replay
+//! OMNI_cassette_mode={"hetzner": "record", "*": "replay"}
+//! ```
+//!
+//! Cassettes are stored as `<cassette_dir>/<provider>.json`, with
+//! `cassette_dir` defaulting to `./tests/cassettes`. Values of sensitive
+//! parameters (passwords, secrets, tokens and keys) are written as their
+//! `{name}` placeholder instead of the value itself, in requests and in the
+//! responses that echo them.
+//!
+//! Responses are scrubbed further before they are written, since providers
+//! also return secrets of their own: string fields of JSON output with a
+//! sensitive name become their `{name}` placeholder, and public IPv4
+//! addresses, in requests too, are swapped for stable stand-ins from the
+//! documentation ranges. The live result is passed on unchanged.
+//!
+//! Only `dummy.json` ships. Recording the other providers needs their real
+//! accounts, so their cassettes are left to whoever maintains them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use super::definition::HttpMethod;
+use super::http::{HttpReply, RenderedRequest};
+use super::render::value_to_text;
+use super::super::{PluginError, SystemCommandResult};
+
+/// Default directory for cassette files
+pub const DEFAULT_CASSETTE_DIR: &str = "./tests/cassettes";
+
+/// Words in parameter names whose values are kept out of cassettes
+const SENSITIVE_WORDS: &[&str] = &["password", "passwd", "pass", "passphrase", "secret", "token", "key"];
+
+/// Whether a parameter's value has to be kept out of recordings and logs
+///
+/// Whole words are matched, so `api_key` and `rootPassword` are sensitive
+/// while `ssh_keys`, which lists key ids, is not.
+pub fn is_sensitive_name(name: &str) -> bool {
+    name_words(name).iter().any(|word| SENSITIVE_WORDS.contains(&word.as_str()))
+}
+
+/// Lowercase words of a `snake_case`, `kebab-case` or `camelCase` name
+fn name_words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        // A capital starts a word after a lowercase letter, and ends a run of
+        // capitals when a lowercase letter follows, as in `APIKey`
+        let previous = i.checked_sub(1).map(|p| chars[p]);
+        let starts_word = c.is_uppercase() && previous.is_some_and(|p| {
+            p.is_lowercase() || (p.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase()))
+        });
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Values shorter than this are not redacted, they would match too much text
+const MIN_REDACTED_LENGTH: usize = 4;
+
+/// Whether executions are run live, recorded or replayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    Off,
+    Record,
+    Replay,
+}
+
+impl CassetteMode {
+    /// Pick the mode for a provider from the `cassette_mode` setting
+    pub fn for_provider(setting: &Value, provider: &str) -> Result<Self, PluginError> {
+        let selected = match setting {
+            Value::Object(modes) => match modes.get(provider).or_else(|| modes.get("*")) {
+                Some(mode) => mode,
+                None => return Ok(CassetteMode::Off),
+            },
+            mode => mode,
+        };
+
+        serde_json::from_value(selected.clone()).map_err(|_| PluginError::InvalidArgument(format!(
+            "cassette_mode for {} must be one of off, record or replay, got {}", provider, selected
+        )))
+    }
+}
+
+/// A recorded execution request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CassetteRequest {
+    /// A local command, program first
+    Command { argv: Vec<String> },
+    /// A script run inside a worker over SSH
+    Ssh { target: String, script: String },
+    /// An HTTP request to a provider API
+    Http {
+        method: HttpMethod,
+        url: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        headers: Vec<(String, String)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+    },
+}
+
+impl CassetteRequest {
+    pub fn http(request: &RenderedRequest) -> Self {
+        CassetteRequest::Http {
+            method: request.method,
+            url: request.url.clone(),
+            headers: request.headers.clone(),
+            body: request.body.clone(),
+        }
+    }
+
+    /// Replace sensitive values with their placeholders
+    pub fn redacted(&self, redactions: &Redactions) -> Self {
+        let redact = |text: &String| redactions.apply(text);
+        match self {
+            CassetteRequest::Command { argv } => CassetteRequest::Command {
+                argv: argv.iter().map(redact).collect(),
+            },
+            CassetteRequest::Ssh { target, script } => CassetteRequest::Ssh {
+                target: target.clone(),
+                script: redact(script),
+            },
+            CassetteRequest::Http { method, url, headers, body } => CassetteRequest::Http {
+                method: *method,
+                url: redact(url),
+                headers: headers.iter().map(|(name, value)| (name.clone(), redact(value))).collect(),
+                body: body.as_ref().map(redact),
+            },
+        }
+    }
+
+    /// Swap public addresses for their stand-ins, as in recorded responses
+    fn with_masked_addresses(self) -> Self {
+        let mask = |text: String| mask_addresses(&text);
+        match self {
+            CassetteRequest::Command { argv } => CassetteRequest::Command {
+                argv: argv.into_iter().map(mask).collect(),
+            },
+            CassetteRequest::Ssh { target, script } => CassetteRequest::Ssh {
+                target: mask(target),
+                script: mask(script),
+            },
+            CassetteRequest::Http { method, url, headers, body } => CassetteRequest::Http {
+                method,
+                url: mask(url),
+                headers: headers.into_iter().map(|(name, value)| (name, mask(value))).collect(),
+                body: body.map(mask),
+            },
+        }
+    }
+}
+
+impl fmt::Display for CassetteRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CassetteRequest::Command { argv } => write!(f, "command {:?}", argv),
+            CassetteRequest::Ssh { target, script } => write!(f, "ssh {} {:?}", target, script),
+            CassetteRequest::Http { method, url, .. } => write!(f, "{:?} {}", method, url),
+        }
+    }
+}
+
+/// A recorded execution result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CassetteResponse {
+    Command { exit_code: i32, stdout: String, stderr: String },
+    Http { status: u16, body: String },
+    /// The execution failed before producing a result
+    Failure { error: String },
+}
+
+impl CassetteResponse {
+    pub fn command(result: SystemCommandResult) -> Self {
+        CassetteResponse::Command { exit_code: result.exit_code, stdout: result.stdout, stderr: result.stderr }
+    }
+
+    pub fn http(reply: HttpReply) -> Self {
+        CassetteResponse::Http { status: reply.status, body: reply.body }
+    }
+
+    /// Copy fit for a cassette file, see the module docs
+    pub fn redacted(&self, redactions: &Redactions) -> Self {
+        let scrub = |text: &String| mask_addresses(&redact_secret_fields(&redactions.apply(text)));
+        match self {
+            CassetteResponse::Command { exit_code, stdout, stderr } => CassetteResponse::Command {
+                exit_code: *exit_code,
+                stdout: scrub(stdout),
+                stderr: scrub(stderr),
+            },
+            CassetteResponse::Http { status, body } => CassetteResponse::Http { status: *status, body: scrub(body) },
+            CassetteResponse::Failure { error } => CassetteResponse::Failure { error: scrub(error) },
+        }
+    }
+
+    /// Turn the response back into a command result
+    pub fn into_command(self) -> Result<SystemCommandResult, PluginError> {
+        match self {
+            CassetteResponse::Command { exit_code, stdout, stderr } => {
+                Ok(SystemCommandResult { exit_code, stdout, stderr, execution_time_ms: 0 })
+            }
+            other => other.mismatch("a command result"),
+        }
+    }
+
+    /// Turn the response back into an HTTP reply
+    pub fn into_http(self) -> Result<HttpReply, PluginError> {
+        match self {
+            CassetteResponse::Http { status, body } => Ok(HttpReply { status, body }),
+            other => other.mismatch("an HTTP response"),
+        }
+    }
+
+    fn mismatch<T>(self, expected: &str) -> Result<T, PluginError> {
+        match self {
+            CassetteResponse::Failure { error } => Err(PluginError::ExecutionFailed(error)),
+            other => Err(PluginError::ExecutionFailed(format!(
+                "Cassette holds {:?} where {} was expected", other, expected
+            ))),
+        }
+    }
+}
+
+/// One recorded request and its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub action: String,
+    pub request: CassetteRequest,
+    pub response: CassetteResponse,
+}
+
+/// The recorded interactions of one provider
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub provider: String,
+    pub interactions: Vec<Interaction>,
+}
+
+/// Sensitive values and the placeholders they are replaced with
+#[derive(Debug, Clone, Default)]
+pub struct Redactions(Vec<(String, String)>);
+
+impl Redactions {
+    /// Collect the values of parameters with sensitive names
+    pub fn from_values(values: &HashMap<String, Value>) -> Self {
+        let mut redactions: Vec<(String, String)> = values.iter()
+            .filter(|(name, _)| is_sensitive_name(name))
+            .map(|(name, value)| (value_to_text(value), format!("{{{}}}", name)))
+            .filter(|(value, _)| value.len() >= MIN_REDACTED_LENGTH)
+            .collect();
+        // Longest first, so a value containing another one is replaced whole
+        redactions.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.1.cmp(&b.1)));
+        Self(redactions)
+    }
+
+    fn apply(&self, text: &str) -> String {
+        self.0.iter().fold(text.to_string(), |text, (value, placeholder)| text.replace(value, placeholder))
+    }
+}
+
+/// Replace string values of JSON fields with sensitive names by their
+/// placeholder, leaving the rest of the text as it was formatted
+fn redact_secret_fields(text: &str) -> String {
+    static FIELD: OnceLock<Regex> = OnceLock::new();
+    let field = FIELD.get_or_init(|| Regex::new(r#""([^"\\]+)"(\s*:\s*)"(?:[^"\\]|\\.)+""#).unwrap());
+    field.replace_all(text, |captures: &Captures| {
+        if is_sensitive_name(&captures[1]) {
+            format!("\"{}\"{}\"{{{}}}\"", &captures[1], &captures[2], &captures[1])
+        } else {
+            captures[0].to_string()
+        }
+    }).into_owned()
+}
+
+/// Ranges reserved for documentation that public addresses are mapped into
+const DOCUMENTATION_NETWORKS: [[u8; 3]; 3] = [[192, 0, 2], [198, 51, 100], [203, 0, 113]];
+
+/// Swap public IPv4 addresses for stand-ins in the documentation ranges
+///
+/// The same address always gets the same stand-in, so a recorded address
+/// used by a later request of the flow still matches on replay.
+fn mask_addresses(text: &str) -> String {
+    static ADDRESS: OnceLock<Regex> = OnceLock::new();
+    let address = ADDRESS.get_or_init(|| Regex::new(r"\b\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}\b").unwrap());
+    address.replace_all(text, |captures: &Captures| {
+        match captures[0].parse::<Ipv4Addr>() {
+            Ok(ip) if !is_public(ip) => captures[0].to_string(),
+            Ok(ip) => stand_in(ip).to_string(),
+            Err(_) => captures[0].to_string(),
+        }
+    }).into_owned()
+}
+
+fn is_public(ip: Ipv4Addr) -> bool {
+    let documentation = DOCUMENTATION_NETWORKS.iter().any(|network| ip.octets()[..3] == network[..]);
+    !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        || ip.is_broadcast() || ip.is_multicast() || documentation)
+}
+
+fn stand_in(ip: Ipv4Addr) -> Ipv4Addr {
+    // FNV-1a, stable across runs unlike the std hasher
+    let hash = ip.octets().iter().fold(0x811c9dc5u32, |hash, &octet| (hash ^ octet as u32).wrapping_mul(0x01000193));
+    let slot = hash % (DOCUMENTATION_NETWORKS.len() as u32 * 254);
+    let network = DOCUMENTATION_NETWORKS[(slot / 254) as usize];
+    Ipv4Addr::new(network[0], network[1], network[2], (slot % 254) as u8 + 1)
+}
+
+/// A loaded cassette and which of its interactions were replayed already
+#[derive(Debug)]
+struct Tape {
+    cassette: Cassette,
+    played: Vec<bool>,
+}
+
+/// Shared access to the cassette files of a provider
+#[derive(Debug, Clone)]
+pub struct CassetteStore {
+    provider: String,
+    tapes: Arc<Mutex<HashMap<PathBuf, Tape>>>,
+}
+
+impl CassetteStore {
+    pub fn new(provider: &str) -> Self {
+        Self { provider: provider.to_string(), tapes: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Path of this provider's cassette in a directory
+    pub fn path_in(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.json", self.provider))
+    }
+
+    /// Run `live` according to the mode, recording or replaying its response
+    ///
+    /// Identical requests are replayed in the order they were recorded; once
+    /// all of them were played the last one keeps being served.
+    pub async fn exchange<F, Fut>(
+        &self,
+        mode: CassetteMode,
+        dir: &Path,
+        action: &str,
+        request: CassetteRequest,
+        redactions: &Redactions,
+        live: F,
+    ) -> Result<CassetteResponse, PluginError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CassetteResponse, PluginError>>,
+    {
+        let request = request.redacted(redactions).with_masked_addresses();
+        match mode {
+            CassetteMode::Off => live().await,
+            CassetteMode::Replay => self.replay(dir, action, &request).await,
+            CassetteMode::Record => {
+                let response = match live().await {
+                    Ok(response) => response,
+                    Err(e) => CassetteResponse::Failure { error: failure_text(&e) },
+                };
+                let recorded = response.redacted(redactions);
+                self.record(dir, Interaction { action: action.to_string(), request, response: recorded }).await?;
+                Ok(response)
+            }
+        }
+    }
+
+    async fn replay(&self, dir: &Path, action: &str, request: &CassetteRequest) -> Result<CassetteResponse, PluginError> {
+        let path = self.path_in(dir);
+        let mut tapes = self.tapes.lock().await;
+        let tape = match tapes.get_mut(&path) {
+            Some(tape) => tape,
+            None => {
+                let cassette = load(&path).await?.ok_or_else(|| PluginError::ExecutionFailed(format!(
+                    "No cassette recorded for {} at {}", self.provider, path.display()
+                )))?;
+                let played = vec![false; cassette.interactions.len()];
+                tapes.entry(path.clone()).or_insert(Tape { cassette, played })
+            }
+        };
+
+        let matches: Vec<usize> = tape.cassette.interactions.iter().enumerate()
+            .filter(|(_, interaction)| interaction.action == action && &interaction.request == request)
+            .map(|(index, _)| index)
+            .collect();
+
+        let index = match matches.iter().find(|index| !tape.played[**index]).or(matches.last()) {
+            Some(index) => *index,
+            None => {
+                return Err(PluginError::ExecutionFailed(format!(
+                    "Cassette {} has no recording of {}/{} for {}", path.display(), self.provider, action, request
+                )));
+            }
+        };
+        tape.played[index] = true;
+        Ok(tape.cassette.interactions[index].response.clone())
+    }
+
+    /// Store an interaction, replacing an earlier recording of the same request
+    async fn record(&self, dir: &Path, interaction: Interaction) -> Result<(), PluginError> {
+        let path = self.path_in(dir);
+        let mut tapes = self.tapes.lock().await;
+        let tape = match tapes.get_mut(&path) {
+            Some(tape) => tape,
+            None => {
+                let cassette = load(&path).await?.unwrap_or_else(|| Cassette {
+                    provider: self.provider.clone(),
+                    interactions: Vec::new(),
+                });
+                let played = vec![false; cassette.interactions.len()];
+                tapes.entry(path.clone()).or_insert(Tape { cassette, played })
+            }
+        };
+
+        // Re-recording replaces the old cassette entry on first use only, so
+        // repeated requests within one recording session all stay on tape
+        let previous = (0..tape.cassette.interactions.len()).find(|&index| {
+            let old = &tape.cassette.interactions[index];
+            !tape.played[index] && old.action == interaction.action && old.request == interaction.request
+        });
+        match previous {
+            Some(index) => {
+                tape.cassette.interactions[index] = interaction;
+                tape.played[index] = true;
+            }
+            None => {
+                tape.cassette.interactions.push(interaction);
+                tape.played.push(true);
+            }
+        }
+
+        tokio::fs::create_dir_all(dir).await?;
+        let json = serde_json::to_string_pretty(&tape.cassette)?;
+        tokio::fs::write(&path, json).await?;
+        Ok(())
+    }
+}
+
+async fn load(path: &Path) -> Result<Option<Cassette>, PluginError> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| PluginError::ExecutionFailed(
+            format!("Cassette {} is not valid: {}", path.display(), e)
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn failure_text(error: &PluginError) -> String {
+    match error {
+        PluginError::ExecutionFailed(message) => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn modes_are_chosen_per_provider() {
+        let setting = json!({ "hetzner": "record", "*": "replay" });
+        assert_eq!(CassetteMode::for_provider(&setting, "hetzner").unwrap(), CassetteMode::Record);
+        assert_eq!(CassetteMode::for_provider(&setting, "linode").unwrap(), CassetteMode::Replay);
+        assert_eq!(CassetteMode::for_provider(&json!({ "linode": "off" }), "dummy").unwrap(), CassetteMode::Off);
+        assert_eq!(CassetteMode::for_provider(&json!("replay"), "dummy").unwrap(), CassetteMode::Replay);
+        assert!(CassetteMode::for_provider(&json!("rewind"), "dummy").is_err());
+    }
+
+    #[test]
+    fn sensitive_names_match_whole_words() {
+        for name in ["api_key", "root_pass", "rootPassword", "X-Auth-Token", "APIKey", "client_secret"] {
+            assert!(is_sensitive_name(name), "{} should be sensitive", name);
+        }
+        for name in ["ssh_keys", "keypair_name", "passenger", "tokenizer", "monkey"] {
+            assert!(!is_sensitive_name(name), "{} should not be sensitive", name);
+        }
+    }
+
+    #[test]
+    fn sensitive_values_become_placeholders() {
+        let redactions = Redactions::from_values(&HashMap::from([
+            ("root_pass".to_string(), json!("hunter22")),
+            ("api_key".to_string(), json!("abc")),
+            ("label".to_string(), json!("web-1")),
+        ]));
+        let request = CassetteRequest::Command {
+            argv: vec!["cli".into(), "--label".into(), "web-1".into(), "--root-pass".into(), "hunter22".into()],
+        };
+
+        assert_eq!(request.redacted(&redactions), CassetteRequest::Command {
+            argv: vec!["cli".into(), "--label".into(), "web-1".into(), "--root-pass".into(), "{root_pass}".into()],
+        });
+    }
+
+    #[tokio::test]
+    async fn recorded_responses_are_scrubbed() {
+        let dir = std::env::temp_dir().join(format!("omni-cassettes-{}", uuid::Uuid::new_v4()));
+        let store = CassetteStore::new("test");
+        let redactions = Redactions::from_values(&HashMap::from([("api_token".to_string(), json!("tok-1234"))]));
+        let request = CassetteRequest::Command { argv: vec!["create".into(), "--token".into(), "tok-1234".into()] };
+        let stdout = "{\n  \"id\": 7,\n  \"ip\": \"203.0.114.9\",\n  \"private_ip\": \"10.0.0.5\",\n  \
+                      \"root_password\": \"Xy9!pq\",\n  \"ssh_keys\": [\"id1\"],\n  \"echo\": \"tok-1234\"\n}";
+
+        let live = store.exchange(CassetteMode::Record, &dir, "create_worker", request.clone(), &redactions, || async {
+            Ok(CassetteResponse::Command { exit_code: 0, stdout: stdout.to_string(), stderr: String::new() })
+        }).await.unwrap();
+        assert_eq!(live.into_command().unwrap().stdout, stdout);
+
+        let stand_in = stand_in("203.0.114.9".parse().unwrap()).to_string();
+        let cassette = load(&store.path_in(&dir)).await.unwrap().unwrap();
+        let recorded = cassette.interactions[0].response.clone().into_command().unwrap().stdout;
+        assert_eq!(recorded, stdout
+            .replace("203.0.114.9", &stand_in)
+            .replace("Xy9!pq", "{root_password}")
+            .replace("\"tok-1234\"", "\"{api_token}\""));
+        assert_eq!(mask_addresses(&stand_in), stand_in);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recordings_replay_in_order() {
+        let dir = std::env::temp_dir().join(format!("omni-cassettes-{}", uuid::Uuid::new_v4()));
+        let recorder = CassetteStore::new("test");
+        let request = CassetteRequest::Command { argv: vec!["list".into()] };
+        let none = Redactions::default();
+
+        for output in ["first", "second"] {
+            recorder.exchange(CassetteMode::Record, &dir, "list_workers", request.clone(), &none, || async move {
+                Ok(CassetteResponse::Command { exit_code: 0, stdout: output.to_string(), stderr: String::new() })
+            }).await.unwrap();
+        }
+
+        let player = CassetteStore::new("test");
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            let response = player.exchange(CassetteMode::Replay, &dir, "list_workers", request.clone(), &none, || async {
+                panic!("replay must not run anything")
+            }).await.unwrap();
+            outputs.push(response.into_command().unwrap().stdout);
+        }
+        assert_eq!(outputs, ["first", "second", "second"]);
+
+        let missing = player.exchange(CassetteMode::Replay, &dir, "get_worker", request, &none, || async {
+            panic!("replay must not run anything")
+        }).await;
+        assert!(matches!(missing, Err(PluginError::ExecutionFailed(_))));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+//! # CPI Definitions
+//!
+//! Serde model for the declarative `CPIs/*.json` provider files.
+//! A definition names a provider, its default settings and the actions it
+//! implements, each backed by either a shell command or an HTTP endpoint.
+//! Commands can have per-OS variants next to a fallback `command`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::mapping::ActionMapping;
+use super::ssh::SSH_ARGUMENTS;
+use super::super::PluginError;
+
+/// A provider definition loaded from a CPI JSON file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpiDefinition {
+    /// Provider name, used as the plugin name (e.g. "hetzner")
+    pub name: String,
+    /// Provider kind ("command" or "endpoint")
+    #[serde(rename = "type")]
+    pub cpi_type: CpiType,
+    /// Optional definition version, defaults to "1.0.0"
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// Provider this definition inherits actions and settings from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Settings merged into every action's parameters
+    #[serde(default)]
+    pub default_settings: HashMap<String, Value>,
+    /// Actions implemented by this provider
+    pub actions: HashMap<String, CpiActionDef>,
+    /// Provider overrides of the feature action mappings, keyed by feature
+    /// and then feature action
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub mappings: HashMap<String, HashMap<String, ActionMapping>>,
+}
+
+fn default_version() -> String {
+    "1.0.0".to_string()
+}
+
+/// Provider kind declared at the top of a CPI file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpiType {
+    Command,
+    Endpoint,
+}
+
+/// A single provider action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpiActionDef {
+    /// What to run for this action
+    pub target: CpiTarget,
+    /// Parameters the target templates may reference
+    #[serde(default)]
+    pub params: Vec<CpiParam>,
+    /// Rules for turning raw output into structured data
+    pub parse_rules: ParseRules,
+    /// JSON path into the response (e.g. `$.server.id`), used instead of
+    /// `parse_rules` when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>,
+    /// Action-level settings that override the provider defaults
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub default_settings: HashMap<String, Value>,
+    /// Follow-up steps run after the main target succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_exec: Vec<CpiActionDef>,
+}
+
+impl CpiActionDef {
+    /// Look up a declared parameter by name
+    pub fn param(&self, name: &str) -> Option<&CpiParam> {
+        self.params.iter().find(|param| param.name() == name)
+    }
+
+    /// Whether a request may pass the argument: a declared parameter, or a
+    /// connection setting of a command that runs inside a worker
+    pub fn takes_argument(&self, name: &str) -> bool {
+        let in_vm = matches!(&self.target, CpiTarget::Command(target) if target.in_vm);
+        self.param(name).is_some() || (in_vm && SSH_ARGUMENTS.contains(&name))
+    }
+
+    /// Whether the action can run on the platform the director runs on
+    pub fn is_available(&self) -> bool {
+        match &self.target {
+            CpiTarget::Command(target) => target.template().is_some(),
+            CpiTarget::Endpoint(_) => true,
+        }
+    }
+}
+
+/// A declared action parameter, either a bare name or `{ "name", "optional" }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CpiParam {
+    Name(String),
+    Detailed {
+        name: String,
+        #[serde(default)]
+        optional: bool,
+    },
+}
+
+impl CpiParam {
+    pub fn name(&self) -> &str {
+        match self {
+            CpiParam::Name(name) => name,
+            CpiParam::Detailed { name, .. } => name,
+        }
+    }
+
+    pub fn is_optional(&self) -> bool {
+        matches!(self, CpiParam::Detailed { optional: true, .. })
+    }
+}
+
+/// Execution target of an action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CpiTarget {
+    Command(CommandTarget),
+    Endpoint(EndpointTarget),
+}
+
+/// Shell command target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandTarget {
+    /// Command template with `{param}` placeholders, used on every OS
+    /// without its own variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// OS-specific command templates that take precedence over `command`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<TargetOs, String>,
+    /// Whether the command runs inside the worker instead of on the director
+    #[serde(default)]
+    pub in_vm: bool,
+}
+
+impl CommandTarget {
+    /// Template to run on an OS: its variant, otherwise the fallback
+    pub fn template_for(&self, os: Option<TargetOs>) -> Option<&str> {
+        os.and_then(|os| self.variants.get(&os))
+            .or(self.command.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Template to run here; `in_vm` commands run in Linux workers
+    pub fn template(&self) -> Option<&str> {
+        let os = if self.in_vm { Some(TargetOs::Linux) } else { TargetOs::current() };
+        self.template_for(os)
+    }
+
+    /// Every template with its field name (`command` or `variants.<os>`)
+    pub fn templates(&self) -> Vec<(String, &str)> {
+        let fallback = self.command.iter().map(|command| ("command".to_string(), command.as_str()));
+        let variants = self.variants.iter()
+            .map(|(os, command)| (format!("variants.{}", os.as_str()), command.as_str()));
+        fallback.chain(variants).collect()
+    }
+}
+
+/// Operating systems a command variant can target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetOs {
+    Linux,
+    Windows,
+    Macos,
+}
+
+impl TargetOs {
+    /// The OS the director was built for, if it is one of the known ones
+    pub fn current() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            Some(TargetOs::Linux)
+        } else if cfg!(target_os = "windows") {
+            Some(TargetOs::Windows)
+        } else if cfg!(target_os = "macos") {
+            Some(TargetOs::Macos)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetOs::Linux => "linux",
+            TargetOs::Windows => "windows",
+            TargetOs::Macos => "macos",
+        }
+    }
+}
+
+/// HTTP endpoint target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointTarget {
+    /// URL template with `{param}` placeholders
+    pub url: String,
+    /// HTTP method
+    pub method: HttpMethod,
+    /// Header templates
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Request body template
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+/// HTTP methods used by endpoint targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HttpMethod {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+/// Output parsing rules attached to an action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseRules {
+    /// Shape of the parsed result
+    #[serde(rename = "type")]
+    pub rule_type: ParseRuleType,
+    /// Record separator for array results (escaped, e.g. "\\n")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub separator: Option<String>,
+    /// Named extraction patterns
+    #[serde(default)]
+    pub patterns: HashMap<String, PatternRule>,
+}
+
+/// Shape of a parsed result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseRuleType {
+    Object,
+    Array,
+    Properties,
+}
+
+/// A single named extraction pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternRule {
+    /// Regular expression applied to the output
+    pub regex: String,
+    /// Capture group to extract, the whole match when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<usize>,
+    /// Value transform ("boolean" or "number")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    /// Whether a missing match is acceptable
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+}
+
+impl CpiDefinition {
+    /// Load a definition from a JSON file
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PluginError> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let definition: CpiDefinition = serde_json::from_str(&content)?;
+        Ok(definition)
+    }
+
+    /// Get an action by name
+    pub fn action(&self, name: &str) -> Option<&CpiActionDef> {
+        self.actions.get(name)
+    }
+
+    /// Names of the actions that cannot run on this platform
+    pub fn unavailable_actions(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.actions.iter()
+            .filter(|(_, action)| !action.is_available())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Provider defaults overlaid with the action's own defaults
+    pub fn settings_for(&self, action: &CpiActionDef) -> HashMap<String, Value> {
+        let mut settings = self.default_settings.clone();
+        settings.extend(action.default_settings.clone());
+        settings
+    }
+}
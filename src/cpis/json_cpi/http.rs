@@ -0,0 +1,472 @@
+//! # Endpoint Execution
+//!
+//! Renders `Endpoint` targets into HTTP requests and sends them with
+//! `reqwest`. Placeholder values are escaped for where they appear:
+//!
+//! - URL: percent-encoded, `/` included, so a value stays within its path
+//!   segment (a placeholder at the very start of the template, such as
+//!   `{api_url}`, is the base URL and is inserted as-is). The rendered URL
+//!   must keep the scheme and host of its base
+//! - headers: inserted as text, rejected if they contain line breaks
+//! - JSON bodies: escaped as string content inside quotes, inserted as a
+//!   JSON value outside them
+//!
+//! Responses outside the 2xx range become `PluginError::HttpStatus`, bodies
+//! larger than `MAX_RESPONSE_BODY` fail the request.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use serde_json::Value;
+use super::definition::{EndpointTarget, HttpMethod};
+use super::render::{lookup, placeholder_at, placeholders, value_to_text};
+use super::super::PluginError;
+
+/// Default time allowed for a single provider request
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response body read from a provider
+pub const MAX_RESPONSE_BODY: usize = 10 * 1024 * 1024;
+
+/// Longest response excerpt kept in an error message
+const MAX_ERROR_BODY: usize = 512;
+
+/// A rendered HTTP request ready to be sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+impl fmt::Display for RenderedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}", self.method, self.url)
+    }
+}
+
+/// Names of all placeholders used by an endpoint target
+pub fn endpoint_placeholders(target: &EndpointTarget) -> Vec<String> {
+    let mut names = placeholders(&target.url);
+    let mut headers: Vec<_> = target.headers.iter().collect();
+    headers.sort();
+    let templates = headers.into_iter()
+        .flat_map(|(name, value)| [name.as_str(), value.as_str()])
+        .chain(target.body.as_deref());
+
+    for template in templates {
+        for name in placeholders(template) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Placeholders that decide where a request goes and how it authenticates:
+/// the base of the URL and everything in the headers
+///
+/// Their values have to come from the provider's settings, never from the
+/// request, or a caller could send the provider's credentials elsewhere.
+pub fn endpoint_setting_placeholders(target: &EndpointTarget) -> Vec<String> {
+    let chars: Vec<char> = target.url.chars().collect();
+    let mut names: Vec<String> = placeholder_at(&chars, 0).map(|(name, _)| name).into_iter().collect();
+    let mut headers: Vec<_> = target.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        for name in placeholders(name).into_iter().chain(placeholders(value)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Render an endpoint target into a request
+pub fn render_request(target: &EndpointTarget, values: &HashMap<String, Value>) -> Result<RenderedRequest, PluginError> {
+    let url = render_url(&target.url, values)?;
+    check_origin(&target.url, values, &url)?;
+
+    let mut headers = Vec::with_capacity(target.headers.len());
+    for (name, template) in &target.headers {
+        let name = render_text(name, values)?;
+        let value = render_text(template, values)?;
+        if name.contains(['\r', '\n']) || value.contains(['\r', '\n']) {
+            return Err(PluginError::InvalidArgument(format!("Header {} contains a line break", name)));
+        }
+        headers.push((name, value));
+    }
+    headers.sort();
+
+    let is_json = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-type") && value.to_ascii_lowercase().contains("json")
+    });
+
+    let body = match &target.body {
+        Some(template) if is_json => Some(render_json_body(template, values)?),
+        Some(template) => Some(render_text(template, values)?),
+        None => None,
+    };
+
+    Ok(RenderedRequest { method: target.method, url, headers, body })
+}
+
+/// Status and body of a provider response
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpReply {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpReply {
+    /// The body of a 2xx response, otherwise an `HttpStatus` error
+    pub fn into_result(self) -> Result<String, PluginError> {
+        if !(200..300).contains(&self.status) {
+            return Err(PluginError::HttpStatus { status: self.status, message: error_message(&self.body) });
+        }
+        Ok(self.body)
+    }
+}
+
+/// Sends rendered requests to provider APIs
+#[derive(Debug, Clone)]
+pub struct EndpointExecutor {
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+impl Default for EndpointExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EndpointExecutor {
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        Self { client, timeout }
+    }
+
+    /// Send a request and return its response whatever the status
+    pub async fn fetch(&self, request: &RenderedRequest) -> Result<HttpReply, PluginError> {
+        let mut builder = self.client.request(reqwest_method(request.method), &request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+
+        let mut response = builder.send().await.map_err(|e| self.transport_error(request, e))?;
+        let status = response.status().as_u16();
+        if response.content_length().is_some_and(|length| length > MAX_RESPONSE_BODY as u64) {
+            return Err(too_large(request));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| self.transport_error(request, e))? {
+            if body.len() + chunk.len() > MAX_RESPONSE_BODY {
+                return Err(too_large(request));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(HttpReply { status, body: String::from_utf8_lossy(&body).into_owned() })
+    }
+
+    fn transport_error(&self, request: &RenderedRequest, error: reqwest::Error) -> PluginError {
+        if error.is_timeout() {
+            PluginError::ExecutionFailed(format!(
+                "Request {} hit the timeout of {}s", request, self.timeout.as_secs()
+            ))
+        } else {
+            PluginError::ExecutionFailed(format!("Request {} failed: {}", request, error))
+        }
+    }
+}
+
+fn too_large(request: &RenderedRequest) -> PluginError {
+    PluginError::ExecutionFailed(format!(
+        "Response to {} is larger than {} bytes", request, MAX_RESPONSE_BODY
+    ))
+}
+
+fn reqwest_method(method: HttpMethod) -> reqwest::Method {
+    match method {
+        HttpMethod::Get => reqwest::Method::GET,
+        HttpMethod::Post => reqwest::Method::POST,
+        HttpMethod::Put => reqwest::Method::PUT,
+        HttpMethod::Patch => reqwest::Method::PATCH,
+        HttpMethod::Delete => reqwest::Method::DELETE,
+    }
+}
+
+/// Pull a readable message out of an error response
+fn error_message(body: &str) -> String {
+    if let Ok(json) = serde_json::from_str::<Value>(body) {
+        let candidates = [
+            json.pointer("/error/message"),
+            json.pointer("/message"),
+            json.pointer("/error"),
+            json.pointer("/detail"),
+        ];
+        if let Some(message) = candidates.into_iter().flatten().find_map(Value::as_str) {
+            return message.to_string();
+        }
+    }
+
+    let body = body.trim();
+    match body.char_indices().nth(MAX_ERROR_BODY) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+/// Substitute placeholders without any escaping
+fn render_text(template: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    substitute(template, values, |_, _, text| Ok(text))
+}
+
+fn render_url(template: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    substitute(template, values, |rendered, start, text| {
+        if start == 0 {
+            return Ok(text);
+        }
+        // URL parsers resolve a `.` or `..` segment even when it is encoded
+        if !rendered.contains('?') && matches!(text.as_str(), "." | "..") {
+            return Err(PluginError::InvalidArgument(format!("{} is not a valid path segment", text)));
+        }
+        Ok(percent_encode(&text))
+    })
+}
+
+/// The rendered URL has to be HTTP(S) and stay on the scheme, host and port
+/// of its base
+fn check_origin(template: &str, values: &HashMap<String, Value>, url: &str) -> Result<(), PluginError> {
+    let parse = |url: &str| reqwest::Url::parse(url)
+        .map_err(|e| PluginError::InvalidArgument(format!("{} is not a valid URL: {}", url, e)));
+    let rendered = parse(url)?;
+    if !matches!(rendered.scheme(), "http" | "https") {
+        return Err(PluginError::InvalidArgument(format!("{} is not an HTTP URL", url)));
+    }
+
+    let chars: Vec<char> = template.chars().collect();
+    if let Some((name, _)) = placeholder_at(&chars, 0) {
+        let base = parse(&lookup(&name, values)?)?;
+        if base.origin() != rendered.origin() {
+            return Err(PluginError::InvalidArgument(format!(
+                "{} does not stay on {}", url, base.origin().ascii_serialization()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn render_json_body(template: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut body = String::new();
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some((name, next)) = placeholder_at(&chars, i) {
+            let value = values.get(&name)
+                .ok_or_else(|| PluginError::InvalidArgument(format!("No value for placeholder {{{}}}", name)))?;
+            if in_string {
+                let quoted = Value::String(value_to_text(value)).to_string();
+                body.push_str(&quoted[1..quoted.len() - 1]);
+            } else {
+                body.push_str(&json_literal(value));
+            }
+            i = next;
+            continue;
+        }
+
+        let c = chars[i];
+        body.push(c);
+        if in_string && c == '\\' {
+            if let Some(&escaped) = chars.get(i + 1) {
+                body.push(escaped);
+                i += 1;
+            }
+        } else if c == '"' {
+            in_string = !in_string;
+        }
+        i += 1;
+    }
+
+    Ok(body)
+}
+
+/// JSON text for a value placed outside quotes, e.g. `"size": {size_gb}`
+///
+/// Strings holding a JSON literal (`"20"`, `"true"`, `"[\"a\"]"`) are
+/// inserted as that literal; any other string becomes a quoted JSON string.
+fn json_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::String(text) => match serde_json::from_str::<Value>(text) {
+            Ok(parsed) if !parsed.is_string() => parsed.to_string(),
+            _ => value.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+fn substitute<F>(template: &str, values: &HashMap<String, Value>, mut insert: F) -> Result<String, PluginError>
+where
+    F: FnMut(&str, usize, String) -> Result<String, PluginError>,
+{
+    let chars: Vec<char> = template.chars().collect();
+    let mut rendered = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some((name, next)) = placeholder_at(&chars, i) {
+            let text = insert(&rendered, i, lookup(&name, values)?)?;
+            rendered.push_str(&text);
+            i = next;
+        } else {
+            rendered.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Percent-encode everything but unreserved characters
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        let keep = byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~');
+        if keep {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(url: &str, body: Option<&str>) -> EndpointTarget {
+        EndpointTarget {
+            url: url.to_string(),
+            method: HttpMethod::Post,
+            headers: HashMap::from([
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("Authorization".to_string(), "Bearer {api_key}".to_string()),
+            ]),
+            body: body.map(str::to_string),
+        }
+    }
+
+    fn values(pairs: &[(&str, Value)]) -> HashMap<String, Value> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn url_values_are_encoded_except_the_base() {
+        let request = render_request(
+            &target("{api_url}/{resource_id}/servers?name={name}", None),
+            &values(&[
+                ("api_url", json!("https://api.example.com/v1")),
+                ("resource_id", json!("groups/a b")),
+                ("name", json!("web&admin=1")),
+                ("api_key", json!("k")),
+            ]),
+        ).unwrap();
+
+        assert_eq!(request.url, "https://api.example.com/v1/groups%2Fa%20b/servers?name=web%26admin%3D1");
+    }
+
+    #[test]
+    fn url_values_cannot_walk_the_path() {
+        let request = render_request(
+            &target("{api_url}/vm/id/{resource_id}", None),
+            &values(&[("api_url", json!("https://nas.local/api/v2.0")), ("resource_id", json!("../../admin")), ("api_key", json!("k"))]),
+        ).unwrap();
+
+        assert_eq!(request.url, "https://nas.local/api/v2.0/vm/id/..%2F..%2Fadmin");
+
+        let result = render_request(
+            &target("{api_url}/vm/id/{resource_id}", None),
+            &values(&[("api_url", json!("https://nas.local/api/v2.0")), ("resource_id", json!("..")), ("api_key", json!("k"))]),
+        );
+        assert!(matches!(result, Err(PluginError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn json_body_values_cannot_break_out_of_strings() {
+        let request = render_request(
+            &target("{api_url}", Some("{\"name\":\"{name}\",\"size\":{size},\"disks\":{disks}}")),
+            &values(&[
+                ("api_url", json!("http://localhost")),
+                ("api_key", json!("k")),
+                ("name", json!("a\",\"admin\":true,\"x\":\"")),
+                ("size", json!("20")),
+                ("disks", json!(["sda", "sdb"])),
+            ]),
+        ).unwrap();
+
+        let body: Value = serde_json::from_str(request.body.as_deref().unwrap()).unwrap();
+        assert_eq!(body, json!({
+            "name": "a\",\"admin\":true,\"x\":\"",
+            "size": 20,
+            "disks": ["sda", "sdb"]
+        }));
+    }
+
+    #[test]
+    fn header_line_breaks_are_rejected() {
+        let result = render_request(
+            &target("{api_url}", None),
+            &values(&[("api_url", json!("http://localhost")), ("api_key", json!("k\r\nX-Admin: 1"))]),
+        );
+        assert!(matches!(result, Err(PluginError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn settings_cover_the_url_base_and_headers() {
+        let templated = target("{api_url}/{resource_id}", Some("{\"name\":\"{name}\"}"));
+        assert_eq!(endpoint_setting_placeholders(&templated), ["api_url", "api_key"]);
+        let fixed = target("https://api.example.com/{resource_id}", None);
+        assert_eq!(endpoint_setting_placeholders(&fixed), ["api_key"]);
+    }
+
+    #[test]
+    fn urls_must_stay_on_their_base() {
+        let result = render_request(
+            &target("{api_url}{path}", None),
+            &values(&[("api_url", json!("https://api.example.com")), ("path", json!(".evil.example")), ("api_key", json!("k"))]),
+        );
+        assert!(matches!(result, Err(PluginError::InvalidArgument(_))));
+
+        let result = render_request(
+            &target("{api_url}/servers", None),
+            &values(&[("api_url", json!("file:///etc")), ("api_key", json!("k"))]),
+        );
+        assert!(matches!(result, Err(PluginError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn error_messages_prefer_structured_fields() {
+        assert_eq!(error_message(r#"{"error":{"message":"server not found"}}"#), "server not found");
+        assert_eq!(error_message("plain failure\n"), "plain failure");
+    }
+}
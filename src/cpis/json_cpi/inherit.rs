@@ -0,0 +1,179 @@
+//! # Definition Inheritance
+//!
+//! A CPI file can name another provider in `extends` to start from that
+//! provider's definition and override only what differs:
+//!
+//! ```json
+//! { "name": "gcp-europe", "extends": "gcp", "default_settings": { "zone": "europe-west1-b" } }
+//! ```
+//!
+//! Definitions are merged as JSON before they are parsed:
+//!
+//! - `default_settings` are merged key by key
+//! - `actions` are merged by action name; an overridden action replaces the
+//!   base action as a whole
+//! - `mappings` are merged by feature and then by feature action
+//! - every other field (`name`, `type`, `version`, ...) replaces the base's
+//!
+//! Bases may extend other definitions. Missing bases and cycles are errors.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde_json::{Map, Value};
+use super::super::PluginError;
+
+/// Fields merged key by key instead of being replaced
+const MERGED_FIELDS: &[&str] = &["default_settings", "actions"];
+
+/// A definition file as read from disk, before inheritance is applied
+#[derive(Debug, Clone)]
+pub struct DefinitionSource {
+    pub path: PathBuf,
+    pub json: Value,
+}
+
+impl DefinitionSource {
+    fn name(&self) -> Option<&str> {
+        self.json.get("name").and_then(Value::as_str)
+    }
+}
+
+/// Name of the provider a definition extends, if any
+pub fn base_name(json: &Value) -> Option<&str> {
+    json.get("extends").and_then(Value::as_str)
+}
+
+/// Apply inheritance to every source
+///
+/// Results are in the order of `sources`. A source fails when its chain of
+/// bases has a missing provider or loops back on itself.
+pub fn resolve_sources(sources: &[DefinitionSource]) -> Vec<(PathBuf, Result<Value, PluginError>)> {
+    let by_name: HashMap<&str, &DefinitionSource> = sources.iter()
+        .filter_map(|source| source.name().map(|name| (name, source)))
+        .collect();
+
+    sources.iter()
+        .map(|source| (source.path.clone(), resolve(source, &by_name)))
+        .collect()
+}
+
+fn resolve(source: &DefinitionSource, by_name: &HashMap<&str, &DefinitionSource>) -> Result<Value, PluginError> {
+    // Walk up to the root first, then merge back down
+    let mut chain = vec![source];
+    let mut names = vec![source.name().unwrap_or("<unnamed>").to_string()];
+
+    while let Some(base) = base_name(&chain[chain.len() - 1].json) {
+        if names.iter().any(|name| name == base) {
+            names.push(base.to_string());
+            return Err(PluginError::InitializationFailed(format!(
+                "CPI inheritance cycle: {}", names.join(" -> ")
+            )));
+        }
+        let parent = by_name.get(base).ok_or_else(|| PluginError::InitializationFailed(format!(
+            "CPI '{}' extends '{}', which is not defined", names[names.len() - 1], base
+        )))?;
+        names.push(base.to_string());
+        chain.push(parent);
+    }
+
+    let mut resolved = chain.pop().map(|root| root.json.clone()).unwrap_or(Value::Null);
+    while let Some(child) = chain.pop() {
+        resolved = merge(&resolved, &child.json);
+    }
+    Ok(resolved)
+}
+
+/// Merge a derived definition onto its base
+pub fn merge(base: &Value, child: &Value) -> Value {
+    let (Some(base), Some(child)) = (base.as_object(), child.as_object()) else {
+        return child.clone();
+    };
+
+    let mut merged = base.clone();
+    for (key, value) in child {
+        let combined = match (key.as_str(), merged.get(key)) {
+            ("mappings", Some(existing)) => merge_objects(existing, value, 2),
+            (field, Some(existing)) if MERGED_FIELDS.contains(&field) => merge_objects(existing, value, 1),
+            _ => value.clone(),
+        };
+        merged.insert(key.clone(), combined);
+    }
+    Value::Object(merged)
+}
+
+/// Merge objects `depth` levels deep, the child's entries winning below that
+fn merge_objects(base: &Value, child: &Value, depth: usize) -> Value {
+    let (Some(base), Some(child)) = (base.as_object(), child.as_object()) else {
+        return child.clone();
+    };
+
+    let mut merged: Map<String, Value> = base.clone();
+    for (key, value) in child {
+        let combined = match merged.get(key) {
+            Some(existing) if depth > 1 => merge_objects(existing, value, depth - 1),
+            _ => value.clone(),
+        };
+        merged.insert(key.clone(), combined);
+    }
+    Value::Object(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source(json: Value) -> DefinitionSource {
+        let name = json["name"].as_str().unwrap_or("unnamed").to_string();
+        DefinitionSource { path: PathBuf::from(format!("cpi-{}.json", name)), json }
+    }
+
+    #[test]
+    fn children_override_actions_and_settings() {
+        let sources = [
+            source(json!({
+                "name": "base", "type": "command",
+                "default_settings": { "zone": "us", "image": "debian" },
+                "actions": { "list_workers": { "v": 1 }, "get_worker": { "v": 1 } },
+                "mappings": { "VM_Manage": { "start_vm": { "action": "boot" }, "stop_vm": { "action": "halt" } } }
+            })),
+            source(json!({
+                "name": "middle", "extends": "base",
+                "default_settings": { "zone": "eu" },
+                "actions": { "get_worker": { "v": 2 } }
+            })),
+            source(json!({
+                "name": "leaf", "extends": "middle", "version": "2.0.0",
+                "actions": { "create_worker": { "v": 3 } },
+                "mappings": { "VM_Manage": { "stop_vm": { "action": "power_off" } } }
+            })),
+        ];
+
+        let resolved = resolve_sources(&sources);
+        let leaf = resolved[2].1.as_ref().unwrap();
+        assert_eq!(leaf, &json!({
+            "name": "leaf", "extends": "middle", "type": "command", "version": "2.0.0",
+            "default_settings": { "zone": "eu", "image": "debian" },
+            "actions": { "list_workers": { "v": 1 }, "get_worker": { "v": 2 }, "create_worker": { "v": 3 } },
+            "mappings": { "VM_Manage": { "start_vm": { "action": "boot" }, "stop_vm": { "action": "power_off" } } }
+        }));
+    }
+
+    #[test]
+    fn missing_bases_and_cycles_are_errors() {
+        let sources = [
+            source(json!({ "name": "orphan", "extends": "nobody" })),
+            source(json!({ "name": "a", "extends": "b" })),
+            source(json!({ "name": "b", "extends": "a" })),
+            source(json!({ "name": "self", "extends": "self" })),
+        ];
+
+        let messages: Vec<String> = resolve_sources(&sources).into_iter()
+            .map(|(_, result)| result.unwrap_err().to_string())
+            .collect();
+        assert!(messages[0].ends_with("CPI 'orphan' extends 'nobody', which is not defined"), "{}", messages[0]);
+        assert!(messages[1].ends_with("CPI inheritance cycle: a -> b -> a"), "{}", messages[1]);
+        assert!(messages[2].ends_with("CPI inheritance cycle: b -> a -> b"), "{}", messages[2]);
+        assert!(messages[3].ends_with("CPI inheritance cycle: self -> self"), "{}", messages[3]);
+    }
+}
@@ -0,0 +1,636 @@
+//! # CPI Linter
+//!
+//! Static checks for `CPIs/*.json` files, so mistakes in hand-written
+//! definitions show up before an action is run:
+//!
+//! - the file parses and matches the definition model
+//! - every placeholder in a command, URL, header, body or flag slot template
+//!   is declared in `params` or provided by `default_settings`, and no
+//!   parameter takes a flag slot's `*_param` name
+//! - command and URL templates have no stray braces and commands have
+//!   balanced quotes; JSON bodies are still valid JSON once rendered
+//! - every pattern regex compiles, its `group` exists and its `transform`
+//!   is known; array rules don't use an empty `separator`
+//! - the required canonical actions are present
+//! - command targets have a `command` or OS `variants`, each checked on
+//!   its own
+//! - `mappings` overrides are for actions their feature defines and only
+//!   point at actions the CPI implements
+//! - `extends` names a known provider without forming a cycle; derived
+//!   definitions are checked after inheritance is applied
+//!
+//! Each issue names the file and the JSON path of the offending field.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use regex::RegexBuilder;
+use serde::Serialize;
+use serde_json::Value;
+use super::definition::{CpiActionDef, CpiDefinition, CpiTarget, ParseRuleType};
+use super::http::render_request;
+use super::inherit::{base_name, resolve_sources, DefinitionSource};
+use super::parse::KNOWN_TRANSFORMS;
+use super::render::{is_flag_slot, placeholder_at, placeholders, render_command};
+use super::super::{FeatureRegistry, PluginError};
+
+/// Actions every CPI must implement
+pub const REQUIRED_ACTIONS: &[&str] = &["test_install", "list_workers"];
+
+/// Stand-in for a URL base placeholder, which is checked as a URL
+const SAMPLE_BASE_URL: &str = "https://example.invalid";
+
+/// Action names of each known feature
+pub type FeatureActions = HashMap<String, HashSet<String>>;
+
+/// How serious a lint issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    /// The definition is broken or an action will fail
+    Error,
+    /// The definition works but something is likely unintended
+    Warning,
+}
+
+/// A single problem found in a CPI file
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub severity: LintSeverity,
+    pub file: String,
+    /// JSON path of the field, e.g. `actions.get_worker.parse_rules.patterns.id.group`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            LintSeverity::Error => "error",
+            LintSeverity::Warning => "warning",
+        };
+        if self.path.is_empty() {
+            write!(f, "{}: {}: {}", self.file, severity, self.message)
+        } else {
+            write!(f, "{}: {}: {}: {}", self.file, self.path, severity, self.message)
+        }
+    }
+}
+
+/// Result of linting a set of CPI files
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LintReport {
+    pub files_checked: usize,
+    pub issues: Vec<LintIssue>,
+}
+
+impl LintReport {
+    pub fn error_count(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == LintSeverity::Error).count()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == LintSeverity::Warning).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+}
+
+/// Lint CPI files and directories of CPI files
+///
+/// Mapped action names are checked against the feature schemas found in
+/// `features_dir`; when there are none that check is skipped with a warning.
+pub async fn lint_paths<P: AsRef<Path>>(paths: &[PathBuf], features_dir: P) -> Result<LintReport, PluginError> {
+    let mut report = LintReport::default();
+
+    // load_schemas creates missing directories, which a lint run should not do
+    let features = FeatureRegistry::new();
+    let mut feature_actions = None;
+    if features_dir.as_ref().is_dir() && features.load_schemas(features_dir.as_ref()).await.is_ok() {
+        let mut known = FeatureActions::new();
+        for feature in features.list_features().await {
+            if let Ok(actions) = features.get_feature_actions(&feature).await {
+                known.insert(feature, actions.into_iter().collect());
+            }
+        }
+        feature_actions = Some(known).filter(|known| !known.is_empty());
+    }
+
+    if feature_actions.is_none() {
+        report.issues.push(LintIssue {
+            severity: LintSeverity::Warning,
+            file: features_dir.as_ref().display().to_string(),
+            path: String::new(),
+            message: "No feature schemas found, mapped action names were not checked".to_string(),
+        });
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = tokio::fs::read_dir(path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let entry_path = entry.path();
+                if entry_path.extension().and_then(|s| s.to_str()) == Some("json") {
+                    files.push(entry_path);
+                }
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+
+    let mut contents = Vec::new();
+    for file in files {
+        report.files_checked += 1;
+        match tokio::fs::read_to_string(&file).await {
+            Ok(content) => contents.push((file, content)),
+            Err(e) => {
+                let label = file.display().to_string();
+                report.issues.push(issue(LintSeverity::Error, &label, "", format!("Cannot read file: {}", e)));
+            }
+        }
+    }
+
+    // Derived definitions are checked in their resolved form
+    let sources: Vec<DefinitionSource> = contents.iter()
+        .filter_map(|(file, content)| {
+            serde_json::from_str(content).ok().map(|json| DefinitionSource { path: file.clone(), json })
+        })
+        .collect();
+    let resolved: HashMap<PathBuf, Result<Value, PluginError>> = resolve_sources(&sources).into_iter().collect();
+
+    let mut providers: HashMap<String, String> = HashMap::new();
+    for (file, content) in contents {
+        let label = file.display().to_string();
+
+        let extends = serde_json::from_str::<Value>(&content).ok()
+            .is_some_and(|json| base_name(&json).is_some());
+        match resolved.get(&file) {
+            Some(Ok(json)) if extends => {
+                report.issues.extend(lint_source(&label, &json.to_string(), feature_actions.as_ref()));
+            }
+            Some(Err(e)) => {
+                report.issues.push(issue(LintSeverity::Error, &label, "extends", failure_text(e)));
+            }
+            _ => report.issues.extend(lint_source(&label, &content, feature_actions.as_ref())),
+        }
+
+        if let Some(name) = serde_json::from_str::<Value>(&content).ok()
+            .and_then(|json| json.get("name").and_then(Value::as_str).map(str::to_string))
+        {
+            if let Some(first) = providers.get(&name) {
+                report.issues.push(issue(
+                    LintSeverity::Error, &label, "name",
+                    format!("Provider name '{}' is already used by {}", name, first),
+                ));
+            } else {
+                providers.insert(name, label);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Lint the contents of one CPI file
+pub fn lint_source(file: &str, content: &str, feature_actions: Option<&FeatureActions>) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    if let Err(e) = serde_json::from_str::<Value>(content) {
+        issues.push(issue(LintSeverity::Error, file, "", format!("Invalid JSON: {}", e)));
+        return issues;
+    }
+    let definition: CpiDefinition = match serde_json::from_str(content) {
+        Ok(definition) => definition,
+        Err(e) => {
+            issues.push(issue(LintSeverity::Error, file, "", format!("Not a valid CPI definition: {}", e)));
+            return issues;
+        }
+    };
+
+    let mut linter = Linter { file, definition: &definition, issues };
+
+    for required in REQUIRED_ACTIONS {
+        if definition.action(required).is_none() {
+            linter.error("actions", format!("Required action '{}' is missing", required));
+        }
+    }
+
+    let mut action_names: Vec<_> = definition.actions.keys().collect();
+    action_names.sort();
+    for name in action_names {
+        let action = &definition.actions[name];
+        linter.action(&format!("actions.{}", name), action, &[]);
+    }
+
+    let mut features: Vec<_> = definition.mappings.iter().collect();
+    features.sort_by_key(|(feature, _)| feature.as_str());
+    for (feature, actions) in features {
+        let known = feature_actions.map(|known| known.get(feature));
+        if let Some(None) = known {
+            linter.warning(&format!("mappings.{}", feature), format!(
+                "'{}' is not a known feature, so its mappings are never used", feature
+            ));
+        }
+
+        let mut actions: Vec<_> = actions.iter().collect();
+        actions.sort_by_key(|(name, _)| name.as_str());
+        for (feature_action, mapping) in actions {
+            if let Some(Some(known)) = known {
+                if !known.contains(feature_action) {
+                    linter.warning(&format!("mappings.{}.{}", feature, feature_action), format!(
+                        "'{}' is not an action of feature {}, so the mapping is never used", feature_action, feature
+                    ));
+                }
+            }
+            let target = mapping.action.as_deref().unwrap_or(feature_action);
+            if definition.action(target).is_none() {
+                linter.error(
+                    &format!("mappings.{}.{}", feature, feature_action),
+                    format!("Mapped action '{}' is not implemented by this CPI", target),
+                );
+            }
+        }
+    }
+
+    linter.issues
+}
+
+fn failure_text(error: &PluginError) -> String {
+    match error {
+        PluginError::InitializationFailed(message) => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn issue(severity: LintSeverity, file: &str, path: &str, message: String) -> LintIssue {
+    LintIssue { severity, file: file.to_string(), path: path.to_string(), message }
+}
+
+struct Linter<'a> {
+    file: &'a str,
+    definition: &'a CpiDefinition,
+    issues: Vec<LintIssue>,
+}
+
+impl Linter<'_> {
+    fn error(&mut self, path: &str, message: String) {
+        self.issues.push(issue(LintSeverity::Error, self.file, path, message));
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.issues.push(issue(LintSeverity::Warning, self.file, path, message));
+    }
+
+    /// Check one action; `inherited` holds the parameters of enclosing
+    /// actions, which `post_exec` steps may also use
+    fn action(&mut self, path: &str, action: &CpiActionDef, inherited: &[&CpiActionDef]) {
+        let declared = |name: &str| {
+            std::iter::once(action).chain(inherited.iter().copied()).any(|scope| {
+                scope.param(name).is_some() || scope.default_settings.contains_key(name)
+            }) || self.definition.default_settings.contains_key(name)
+        };
+        let mut undeclared = Vec::new();
+
+        for param in &action.params {
+            if is_flag_slot(param.name()) {
+                self.error(&format!("{}.params", path), format!(
+                    "Parameter '{}' has a flag slot name, slots are filled from default_settings and can't be set by a request",
+                    param.name()
+                ));
+            }
+        }
+        let mut slots: Vec<&String> = action.default_settings.keys().filter(|name| is_flag_slot(name)).collect();
+        slots.sort();
+        for name in slots {
+            if let Some(Value::String(template)) = action.default_settings.get(name) {
+                let field = format!("{}.default_settings.{}", path, name);
+                undeclared.extend(unknown_placeholders(&field, template, &declared));
+            }
+        }
+
+        match &action.target {
+            CpiTarget::Command(target) => {
+                let templates = target.templates();
+                if templates.is_empty() {
+                    self.error(
+                        &format!("{}.target.Command", path),
+                        "Command target has neither a command nor OS variants".to_string(),
+                    );
+                }
+
+                for (name, template) in templates {
+                    let field = format!("{}.target.Command.{}", path, name);
+                    undeclared.extend(unknown_placeholders(&field, template, &declared));
+                    self.braces(&field, template);
+
+                    let values = sample_values(template);
+                    if let Err(e) = render_command(template, &values) {
+                        self.error(&field, e.to_string());
+                    }
+                }
+            }
+            CpiTarget::Endpoint(target) => {
+                let field = format!("{}.target.Endpoint", path);
+                undeclared.extend(unknown_placeholders(&format!("{}.url", field), &target.url, &declared));
+                self.braces(&format!("{}.url", field), &target.url);
+
+                let mut headers: Vec<_> = target.headers.iter().collect();
+                headers.sort();
+                for (name, value) in headers {
+                    let header_field = format!("{}.headers.{}", field, name);
+                    undeclared.extend(unknown_placeholders(&header_field, name, &declared));
+                    undeclared.extend(unknown_placeholders(&header_field, value, &declared));
+                }
+
+                if let Some(body) = &target.body {
+                    undeclared.extend(unknown_placeholders(&format!("{}.body", field), body, &declared));
+                }
+
+                let mut values = sample_values(&target.url);
+                if let Some(body) = &target.body {
+                    values.extend(sample_values(body));
+                }
+                for (name, value) in &target.headers {
+                    values.extend(sample_values(name));
+                    values.extend(sample_values(value));
+                }
+                let url: Vec<char> = target.url.chars().collect();
+                if let Some((base, _)) = placeholder_at(&url, 0) {
+                    values.insert(base, Value::String(SAMPLE_BASE_URL.to_string()));
+                }
+                match render_request(target, &values) {
+                    Ok(request) => {
+                        let is_json = request.headers.iter().any(|(name, value)| {
+                            name.eq_ignore_ascii_case("content-type") && value.contains("json")
+                        });
+                        if let (true, Some(body)) = (is_json, &request.body) {
+                            if let Err(e) = serde_json::from_str::<Value>(body) {
+                                self.error(&format!("{}.body", field), format!("Body is not valid JSON once rendered: {}", e));
+                            }
+                        }
+                    }
+                    Err(e) => self.error(&field, e.to_string()),
+                }
+            }
+        }
+
+        for (field, name) in undeclared {
+            self.error(&field, format!("Placeholder {{{}}} is not declared in params or default_settings", name));
+        }
+
+        self.parse_rules(path, action);
+
+        let mut scope = vec![action];
+        scope.extend_from_slice(inherited);
+        for (index, step) in action.post_exec.iter().enumerate() {
+            self.action(&format!("{}.post_exec[{}]", path, index), step, &scope);
+        }
+    }
+
+    fn parse_rules(&mut self, path: &str, action: &CpiActionDef) {
+        let rules = &action.parse_rules;
+
+        if rules.separator.is_some() && rules.rule_type != ParseRuleType::Array {
+            self.warning(&format!("{}.parse_rules.separator", path), "Separator is only used by array rules".to_string());
+        }
+        if rules.rule_type == ParseRuleType::Array && rules.separator.as_deref() == Some("") {
+            self.error(&format!("{}.parse_rules.separator", path), "Separator is empty, so the output can't be split into records".to_string());
+        }
+
+        let mut fields: Vec<_> = rules.patterns.iter().collect();
+        fields.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, pattern) in fields {
+            let field = format!("{}.parse_rules.patterns.{}", path, name);
+
+            match RegexBuilder::new(&pattern.regex).multi_line(true).build() {
+                Ok(regex) => {
+                    if let Some(group) = pattern.group {
+                        if group >= regex.captures_len() {
+                            self.error(&format!("{}.group", field), format!(
+                                "Group {} does not exist, the regex has {} capture group(s)",
+                                group, regex.captures_len() - 1
+                            ));
+                        }
+                    }
+                }
+                Err(e) => self.error(&format!("{}.regex", field), format!("Regex does not compile: {}", e)),
+            }
+
+            if let Some(transform) = &pattern.transform {
+                if !KNOWN_TRANSFORMS.contains(&transform.as_str()) {
+                    self.error(&format!("{}.transform", field), format!(
+                        "Unknown transform '{}', expected one of: {}", transform, KNOWN_TRANSFORMS.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Flag braces that do not belong to a placeholder and are not balanced,
+    /// such as the `"}` left behind by a bad search and replace
+    fn braces(&mut self, field: &str, template: &str) {
+        let chars: Vec<char> = template.chars().collect();
+        let mut depth = 0usize;
+        let mut i = 0;
+
+        while i < chars.len() {
+            if let Some((_, next)) = placeholder_at(&chars, i) {
+                i = next;
+                continue;
+            }
+            match chars[i] {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.error(field, format!("Stray '}}' at position {} that does not close a placeholder", i));
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+        }
+
+        if depth > 0 {
+            self.error(field, "Unclosed '{' in template".to_string());
+        }
+    }
+}
+
+/// Placeholders in a template that are not declared, paired with the field
+fn unknown_placeholders(field: &str, template: &str, declared: &dyn Fn(&str) -> bool) -> Vec<(String, String)> {
+    placeholders(template).into_iter()
+        .filter(|name| !declared(name))
+        .map(|name| (field.to_string(), name))
+        .collect()
+}
+
+/// Stand-in values used to render templates
+fn sample_values(template: &str) -> HashMap<String, Value> {
+    placeholders(template).into_iter()
+        .map(|name| (name, Value::String("1".to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(content: &str) -> Vec<String> {
+        let known: FeatureActions = [
+            ("Worker_Manage", &["test_install", "list_workers", "get_worker", "start_worker"][..]),
+            ("VM_Manage", &["start_vm", "stop_vm"][..]),
+        ].iter()
+            .map(|(feature, actions)| (feature.to_string(), actions.iter().map(|s| s.to_string()).collect()))
+            .collect();
+        lint_source("cpi-test.json", content, Some(&known)).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn clean_definitions_have_no_issues() {
+        let issues = lint(r#"{
+            "name": "test", "type": "command",
+            "default_settings": { "region": "eu" },
+            "actions": {
+                "test_install": {
+                    "target": { "Command": { "command": "tool --version" } },
+                    "parse_rules": { "type": "object", "patterns": {
+                        "version": { "regex": "([0-9.]+)", "group": 1 }
+                    } }
+                },
+                "list_workers": {
+                    "target": { "Command": { "command": "tool list --region {region} | awk '{print $1}'" } },
+                    "parse_rules": { "type": "array", "separator": "\\n", "patterns": {
+                        "id": { "regex": "^(\\d+)", "group": 1, "transform": "number" }
+                    } }
+                }
+            }
+        }"#);
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_path() {
+        let issues = lint(r#"{
+            "name": "test", "type": "command",
+            "actions": {
+                "test_install": {
+                    "target": { "Command": { "command": "cmd /c \"}echo {version}\"" } },
+                    "parse_rules": { "type": "object", "patterns": {
+                        "version": { "regex": "([0-9.]+", "group": 1 },
+                        "ok": { "regex": "ok", "group": 1, "transform": "bool" }
+                    } }
+                },
+                "make_coffee": {
+                    "target": { "Command": { "command": "coffee {size_param} {milk_param}" } },
+                    "params": ["milk_param"],
+                    "default_settings": { "size_param": "--size={size}" },
+                    "parse_rules": { "type": "array", "separator": "", "patterns": {} }
+                }
+            },
+            "mappings": {
+                "VM_Manage": { "start_vm": { "action": "boot_worker" }, "explode_vm": { "action": "test_install" } },
+                "Coffee_Manage": { "brew": { "action": "make_coffee" } }
+            }
+        }"#);
+
+        let expected = [
+            "cpi-test.json: actions: error: Required action 'list_workers' is missing",
+            "cpi-test.json: actions.test_install.target.Command.command: error: Stray '}' at position 8 that does not close a placeholder",
+            "cpi-test.json: actions.test_install.target.Command.command: error: Placeholder {version} is not declared in params or default_settings",
+            "cpi-test.json: actions.test_install.parse_rules.patterns.ok.group: error: Group 1 does not exist, the regex has 0 capture group(s)",
+            "cpi-test.json: actions.test_install.parse_rules.patterns.ok.transform: error: Unknown transform 'bool', expected one of: boolean, number",
+            "cpi-test.json: actions.make_coffee.params: error: Parameter 'milk_param' has a flag slot name, slots are filled from default_settings and can't be set by a request",
+            "cpi-test.json: actions.make_coffee.default_settings.size_param: error: Placeholder {size} is not declared in params or default_settings",
+            "cpi-test.json: actions.make_coffee.parse_rules.separator: error: Separator is empty, so the output can't be split into records",
+            "cpi-test.json: mappings.VM_Manage.start_vm: error: Mapped action 'boot_worker' is not implemented by this CPI",
+            "cpi-test.json: mappings.VM_Manage.explode_vm: warning: 'explode_vm' is not an action of feature VM_Manage, so the mapping is never used",
+            "cpi-test.json: mappings.Coffee_Manage: warning: 'Coffee_Manage' is not a known feature, so its mappings are never used",
+        ];
+        for line in expected {
+            assert!(issues.iter().any(|issue| issue == line), "missing '{}' in {:#?}", line, issues);
+        }
+        assert!(issues.iter().any(|issue| issue.starts_with(
+            "cpi-test.json: actions.test_install.parse_rules.patterns.version.regex: error: Regex does not compile"
+        )));
+    }
+
+    #[test]
+    fn endpoint_bodies_must_render_to_json() {
+        let issues = lint(r#"{
+            "name": "test", "type": "endpoint",
+            "default_settings": { "api_url": "http://localhost" },
+            "actions": {
+                "test_install": {
+                    "target": { "Endpoint": {
+                        "url": "{api_url}/servers", "method": "Post",
+                        "headers": { "Content-Type": "application/json", "Authorization": "Bearer {token}" },
+                        "body": "{...template, \"name\": \"{name}\"}"
+                    } },
+                    "params": ["name"],
+                    "parse_rules": { "type": "object", "patterns": {} }
+                },
+                "list_workers": {
+                    "target": { "Endpoint": { "url": "{api_url}/servers", "method": "Get" } },
+                    "parse_rules": { "type": "array", "patterns": {} }
+                }
+            }
+        }"#);
+
+        assert_eq!(issues.len(), 2, "{:#?}", issues);
+        assert_eq!(issues[1], "cpi-test.json: actions.test_install.target.Endpoint.headers.Authorization: error: Placeholder {token} is not declared in params or default_settings");
+        assert!(issues[0].starts_with("cpi-test.json: actions.test_install.target.Endpoint.body: error: Body is not valid JSON once rendered"));
+    }
+
+    #[test]
+    fn invalid_files_are_reported_once() {
+        let issues = lint(r#"{ "name": "test", "type": "command", "actions": { "#);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].starts_with("cpi-test.json: error: Invalid JSON"));
+    }
+
+    #[tokio::test]
+    async fn derived_definitions_are_checked_resolved() {
+        let dir = std::env::temp_dir().join(format!("omni-lint-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let files = [
+            ("cpi-base.json", r#"{ "name": "base", "type": "command", "actions": {
+                "test_install": { "target": { "Command": { "command": "tool --version" } },
+                                  "parse_rules": { "type": "object", "patterns": {} } },
+                "list_workers": { "target": { "Command": { "command": "tool list" } },
+                                  "parse_rules": { "type": "object", "patterns": {} } }
+            } }"#),
+            ("cpi-derived.json", r#"{ "name": "derived", "extends": "base", "actions": {
+                "list_workers": { "target": { "Command": { "command": "tool list {zone}" } },
+                                  "parse_rules": { "type": "object", "patterns": {} } }
+            } }"#),
+            ("cpi-orphan.json", r#"{ "name": "orphan", "extends": "missing" }"#),
+        ];
+        for (name, content) in files {
+            tokio::fs::write(dir.join(name), content).await.unwrap();
+        }
+
+        let report = lint_paths(std::slice::from_ref(&dir), dir.join("no-features")).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        let issues: Vec<String> = report.issues.iter()
+            .map(|issue| issue.to_string().replace(&format!("{}/", dir.display()), ""))
+            .filter(|issue| !issue.contains("no-features"))
+            .collect();
+        assert_eq!(issues, [
+            "cpi-derived.json: actions.list_workers.target.Command.command: error: Placeholder {zone} is not declared in params or default_settings",
+            "cpi-orphan.json: extends: error: CPI 'orphan' extends 'missing', which is not defined",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn shipped_cpis_have_no_issues() {
+        let report = lint_paths(&[PathBuf::from("CPIs")], "features").await.unwrap();
+        let issues: Vec<String> = report.issues.iter().map(ToString::to_string).collect();
+        assert!(report.files_checked > 0);
+        assert!(issues.is_empty(), "{:#?}", issues);
+    }
+}
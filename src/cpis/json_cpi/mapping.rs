@@ -0,0 +1,302 @@
+//! # Feature Action Mapping
+//!
+//! Translates generic feature actions (e.g. `VM_Manage/create_vm` with
+//! `name` and `cpu_count`) into provider CPI actions (e.g. `create_worker`
+//! with `worker_name` and `vcpus`).
+//!
+//! Mappings are declared at two levels:
+//!
+//! - per feature, in `features/mappings/*.json`: the CPI action each feature
+//!   action runs and, for every argument, a list of candidate param names.
+//!   The first candidate the CPI action accepts is used, so one file covers
+//!   providers that call the same thing `worker_id`, `server_id` or `vmid`.
+//! - per CPI, in the definition's `mappings` object: overrides for a single
+//!   provider, keyed by feature and feature action.
+//!
+//! Arguments without a mapping are passed through under their own name.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::definition::{CpiActionDef, CpiDefinition, CpiTarget};
+use super::http::endpoint_placeholders;
+use super::render::placeholders;
+use super::super::PluginError;
+
+/// Mapping of one feature's actions onto CPI actions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureMapping {
+    /// Feature the mapping applies to (e.g. "VM_Manage")
+    pub feature: String,
+    /// Feature action name to CPI action mapping
+    pub actions: HashMap<String, ActionMapping>,
+}
+
+impl FeatureMapping {
+    /// Load a feature mapping from a JSON file
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PluginError> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mapping: FeatureMapping = serde_json::from_str(&content)?;
+        Ok(mapping)
+    }
+}
+
+/// How one feature action maps onto a CPI action
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionMapping {
+    /// CPI action to run, the feature action's own name when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Feature argument name to CPI param name(s)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub params: HashMap<String, ParamTarget>,
+}
+
+/// A single CPI param name or a list of candidates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamTarget {
+    Name(String),
+    Candidates(Vec<String>),
+}
+
+impl ParamTarget {
+    fn candidates(&self) -> Vec<&str> {
+        match self {
+            ParamTarget::Name(name) => vec![name.as_str()],
+            ParamTarget::Candidates(names) => names.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// Where a resolved mapping came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MappingSource {
+    /// The feature-level mapping file
+    Feature,
+    /// An override in the CPI definition
+    Cpi,
+}
+
+/// The effective mapping of a feature action for one provider
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedMapping {
+    pub feature: String,
+    pub feature_action: String,
+    pub cpi_action: String,
+    /// Feature argument to CPI param renames; other arguments pass through
+    pub params: BTreeMap<String, String>,
+    pub source: MappingSource,
+}
+
+impl ResolvedMapping {
+    /// Rename request arguments to the CPI's param names
+    ///
+    /// An argument that already uses the CPI name wins over a renamed one.
+    pub fn translate_arguments(&self, arguments: &HashMap<String, Value>) -> HashMap<String, Value> {
+        let mut translated: HashMap<String, Value> = arguments.iter()
+            .filter(|(name, _)| !self.params.contains_key(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        for (from, to) in &self.params {
+            if let Some(value) = arguments.get(from) {
+                translated.entry(to.clone()).or_insert_with(|| value.clone());
+            }
+        }
+
+        translated
+    }
+}
+
+/// Resolve every feature action mapping that a CPI can serve
+///
+/// Feature actions whose target CPI action the definition does not
+/// implement are left out.
+pub fn resolve_mappings(
+    definition: &CpiDefinition,
+    feature_mappings: &HashMap<String, FeatureMapping>,
+) -> Vec<ResolvedMapping> {
+    let mut features: Vec<&String> = feature_mappings.keys().chain(definition.mappings.keys()).collect();
+    features.sort();
+    features.dedup();
+
+    let mut resolved = Vec::new();
+    for feature in features {
+        let defaults = feature_mappings.get(feature).map(|mapping| &mapping.actions);
+        let overrides = definition.mappings.get(feature);
+
+        let mut actions: Vec<&String> = defaults.into_iter().flat_map(HashMap::keys)
+            .chain(overrides.into_iter().flat_map(HashMap::keys))
+            .collect();
+        actions.sort();
+        actions.dedup();
+
+        for feature_action in actions {
+            let default = defaults.and_then(|actions| actions.get(feature_action));
+            let cpi_override = overrides.and_then(|actions| actions.get(feature_action));
+            if let Some(mapping) = resolve_action(definition, feature, feature_action, default, cpi_override) {
+                resolved.push(mapping);
+            }
+        }
+    }
+    resolved
+}
+
+fn resolve_action(
+    definition: &CpiDefinition,
+    feature: &str,
+    feature_action: &str,
+    default: Option<&ActionMapping>,
+    cpi_override: Option<&ActionMapping>,
+) -> Option<ResolvedMapping> {
+    let cpi_action = cpi_override.and_then(|mapping| mapping.action.clone())
+        .or_else(|| default.and_then(|mapping| mapping.action.clone()))
+        .unwrap_or_else(|| feature_action.to_string());
+    let action = definition.action(&cpi_action)?;
+    let accepted = accepted_params(action);
+
+    let mut params = BTreeMap::new();
+    let mut arguments: Vec<&String> = default.into_iter().flat_map(|mapping| mapping.params.keys())
+        .chain(cpi_override.into_iter().flat_map(|mapping| mapping.params.keys()))
+        .collect();
+    arguments.sort();
+    arguments.dedup();
+
+    for argument in arguments {
+        let target = match cpi_override.and_then(|mapping| mapping.params.get(argument)) {
+            // Overrides are explicit, fall back to their first candidate
+            Some(target) => {
+                let candidates = target.candidates();
+                candidates.iter().find(|name| accepted.contains(&name.to_string()))
+                    .or(candidates.first())
+                    .map(|name| name.to_string())
+            }
+            None => default.and_then(|mapping| mapping.params.get(argument))
+                .and_then(|target| {
+                    target.candidates().into_iter()
+                        .find(|name| accepted.contains(&name.to_string()))
+                        .map(str::to_string)
+                }),
+        };
+
+        if let Some(target) = target {
+            if &target != argument {
+                params.insert(argument.clone(), target);
+            }
+        }
+    }
+
+    Some(ResolvedMapping {
+        feature: feature.to_string(),
+        feature_action: feature_action.to_string(),
+        cpi_action,
+        params,
+        source: if cpi_override.is_some() { MappingSource::Cpi } else { MappingSource::Feature },
+    })
+}
+
+/// Names a CPI action accepts: its declared params and target placeholders
+fn accepted_params(action: &CpiActionDef) -> Vec<String> {
+    let mut names: Vec<String> = action.params.iter().map(|param| param.name().to_string()).collect();
+    let used = match &action.target {
+        CpiTarget::Command(target) => target.templates().into_iter()
+            .flat_map(|(_, template)| placeholders(template))
+            .collect(),
+        CpiTarget::Endpoint(target) => endpoint_placeholders(target),
+    };
+    for name in used {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn definition(json: Value) -> CpiDefinition {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn vm_manage() -> HashMap<String, FeatureMapping> {
+        let mapping: FeatureMapping = serde_json::from_value(json!({
+            "feature": "VM_Manage",
+            "actions": {
+                "create_vm": {
+                    "action": "create_worker",
+                    "params": { "name": ["worker_name", "name"], "cpu_count": ["vcpus", "cpus"] }
+                },
+                "start_vm": { "action": "start_worker", "params": { "vm_id": ["worker_id", "vmid"] } }
+            }
+        })).unwrap();
+        HashMap::from([("VM_Manage".to_string(), mapping)])
+    }
+
+    #[test]
+    fn candidates_follow_the_cpi_params() {
+        let kvm = definition(json!({
+            "name": "kvm", "type": "command",
+            "actions": {
+                "create_worker": {
+                    "target": { "Command": { "command": "virt-install --name {worker_name} --vcpus {vcpus}" } },
+                    "params": ["worker_name", "vcpus"],
+                    "parse_rules": { "type": "object", "patterns": {} }
+                }
+            }
+        }));
+
+        let resolved = resolve_mappings(&kvm, &vm_manage());
+        assert_eq!(resolved.len(), 1, "start_vm has no start_worker to map to");
+        assert_eq!(resolved[0].cpi_action, "create_worker");
+        assert_eq!(resolved[0].source, MappingSource::Feature);
+
+        let arguments = HashMap::from([
+            ("name".to_string(), json!("web-1")),
+            ("cpu_count".to_string(), json!(2)),
+            ("memory_mb".to_string(), json!(2048)),
+        ]);
+        let translated = resolved[0].translate_arguments(&arguments);
+        assert_eq!(translated, HashMap::from([
+            ("worker_name".to_string(), json!("web-1")),
+            ("vcpus".to_string(), json!(2)),
+            ("memory_mb".to_string(), json!(2048)),
+        ]));
+    }
+
+    #[test]
+    fn cpi_overrides_replace_the_feature_defaults() {
+        let esxi = definition(json!({
+            "name": "esxi", "type": "command",
+            "mappings": {
+                "VM_Manage": { "start_vm": { "action": "power_on_worker" } }
+            },
+            "actions": {
+                "power_on_worker": {
+                    "target": { "Command": { "command": "vim-cmd vmsvc/power.on {vmid}" } },
+                    "params": ["vmid"],
+                    "parse_rules": { "type": "object", "patterns": {} }
+                },
+                "create_worker": {
+                    "target": { "Command": { "command": "esxcli create {name}" } },
+                    "params": ["name"],
+                    "parse_rules": { "type": "object", "patterns": {} }
+                }
+            }
+        }));
+
+        let resolved = resolve_mappings(&esxi, &vm_manage());
+        let start = resolved.iter().find(|m| m.feature_action == "start_vm").unwrap();
+        assert_eq!(start.cpi_action, "power_on_worker");
+        assert_eq!(start.source, MappingSource::Cpi);
+        assert_eq!(start.params, BTreeMap::from([("vm_id".to_string(), "vmid".to_string())]));
+
+        let create = resolved.iter().find(|m| m.feature_action == "create_vm").unwrap();
+        assert!(create.params.is_empty(), "name is already the CPI's param name");
+    }
+}
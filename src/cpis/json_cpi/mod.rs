@@ -0,0 +1,235 @@
+//! # JSON CPI Host
+//!
+//! Loads the declarative provider definitions from `CPIs/*.json` and exposes
+//! each one as a regular plugin. Every definition becomes a `CpiPlugin` that is
+//! registered with the `PluginRegistry` and subscribed to its own
+//! `provider:<name>:feature:Worker_Manage:<action>` events for the actions it
+//! implements, plus the events of every feature action mapped onto one of them.
+//! Definitions that `extends` another provider are resolved before loading.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use super::{PluginError, PluginRegistry, ServerContext};
+
+pub mod cassette;
+pub mod definition;
+pub mod http;
+pub mod inherit;
+pub mod lint;
+pub mod mapping;
+pub mod parse;
+pub mod plugin;
+pub mod render;
+pub mod runner;
+pub mod ssh;
+
+pub use definition::*;
+pub use mapping::{FeatureMapping, ResolvedMapping};
+pub use plugin::CpiPlugin;
+
+/// Feature declared by every JSON CPI for its native actions
+pub const CPI_FEATURE: &str = "Worker_Manage";
+
+/// Host that owns the loaded CPI definitions
+#[derive(Debug)]
+pub struct CpiHost {
+    /// Map of provider name to its definition
+    definitions: RwLock<HashMap<String, Arc<CpiDefinition>>>,
+    /// Map of feature name to its action mapping
+    feature_mappings: RwLock<HashMap<String, FeatureMapping>>,
+}
+
+impl CpiHost {
+    pub fn new() -> Self {
+        Self {
+            definitions: RwLock::new(HashMap::new()),
+            feature_mappings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Load the feature action mappings from a directory
+    ///
+    /// Must run before `load_definitions`, mappings are resolved per CPI
+    /// when it is registered.
+    pub async fn load_mappings<P: AsRef<Path>>(&self, mappings_dir: P) -> Result<usize, PluginError> {
+        let mappings_dir = mappings_dir.as_ref();
+
+        if !mappings_dir.exists() {
+            return Ok(0);
+        }
+
+        let mut loaded = HashMap::new();
+        let mut read_dir = tokio::fs::read_dir(mappings_dir).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                match FeatureMapping::from_file(&path).await {
+                    Ok(mapping) => {
+                        loaded.insert(mapping.feature.clone(), mapping);
+                    }
+                    Err(e) => eprintln!("Failed to load feature mapping from {:?}: {}", path, e),
+                }
+            }
+        }
+
+        let count = loaded.len();
+        self.feature_mappings.write().await.extend(loaded);
+        Ok(count)
+    }
+
+    /// Load every CPI definition in a directory and register it as a plugin
+    pub async fn load_definitions<P: AsRef<Path>>(
+        &self,
+        cpis_dir: P,
+        registry: &PluginRegistry,
+        context: Arc<dyn ServerContext>,
+    ) -> Result<usize, PluginError> {
+        let cpis_dir = cpis_dir.as_ref();
+
+        if !cpis_dir.exists() {
+            return Ok(0);
+        }
+
+        // Read every file first, bases can be defined in any of them
+        let mut sources = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(cpis_dir).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                match read_source(&path).await {
+                    Ok(json) => sources.push(inherit::DefinitionSource { path, json }),
+                    Err(e) => eprintln!("Failed to load CPI from {:?}: {}", path, e),
+                }
+            }
+        }
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut loaded_count = 0;
+        for (path, resolved) in inherit::resolve_sources(&sources) {
+            let result = match resolved {
+                Ok(json) => match serde_json::from_value::<CpiDefinition>(json) {
+                    Ok(definition) => self.load_definition(definition, registry, Arc::clone(&context)).await,
+                    Err(e) => Err(e.into()),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => loaded_count += 1,
+                Err(e) => eprintln!("Failed to load CPI from {:?}: {}", path, e),
+            }
+        }
+
+        Ok(loaded_count)
+    }
+
+    /// Register a single resolved CPI definition as a plugin
+    async fn load_definition(
+        &self,
+        definition: CpiDefinition,
+        registry: &PluginRegistry,
+        context: Arc<dyn ServerContext>,
+    ) -> Result<(), PluginError> {
+        let definition = Arc::new(definition);
+        let name = definition.name.clone();
+
+        {
+            let definitions = self.definitions.read().await;
+            if definitions.contains_key(&name) {
+                return Err(PluginError::InitializationFailed(format!(
+                    "CPI with name '{}' already loaded",
+                    name
+                )));
+            }
+        }
+
+        let mappings = mapping::resolve_mappings(&definition, &*self.feature_mappings.read().await);
+        registry.register_plugin(Box::new(CpiPlugin::new(Arc::clone(&definition), mappings))).await?;
+        registry.initialize_plugin(&name, context).await?;
+
+        let mut definitions = self.definitions.write().await;
+        definitions.insert(name, definition);
+        Ok(())
+    }
+
+    /// Get a loaded definition by provider name
+    pub async fn get_definition(&self, name: &str) -> Option<Arc<CpiDefinition>> {
+        let definitions = self.definitions.read().await;
+        definitions.get(name).cloned()
+    }
+
+    /// Feature action mappings in effect for a loaded provider
+    pub async fn effective_mappings(&self, name: &str) -> Option<Vec<ResolvedMapping>> {
+        let definition = self.get_definition(name).await?;
+        let feature_mappings = self.feature_mappings.read().await;
+        Some(mapping::resolve_mappings(&definition, &feature_mappings))
+    }
+
+    /// List the names of all loaded CPI providers
+    pub async fn list_definitions(&self) -> Vec<String> {
+        let definitions = self.definitions.read().await;
+        definitions.keys().cloned().collect()
+    }
+}
+
+async fn read_source(path: &Path) -> Result<serde_json::Value, PluginError> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+impl Default for CpiHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shipped_definitions_parse() {
+        let mut read_dir = tokio::fs::read_dir("./CPIs").await.expect("CPIs directory missing");
+        let mut count = 0;
+
+        while let Some(entry) = read_dir.next_entry().await.unwrap() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                let definition = CpiDefinition::from_file(&path).await
+                    .unwrap_or_else(|e| panic!("{:?} failed to parse: {}", path, e));
+                assert!(!definition.actions.is_empty(), "{:?} has no actions", path);
+                count += 1;
+            }
+        }
+
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn command_variants_fall_back_to_the_default() {
+        let target: CommandTarget = serde_json::from_value(serde_json::json!({
+            "command": "echo posix",
+            "variants": { "windows": "cmd /c echo windows" }
+        })).unwrap();
+
+        assert_eq!(target.template_for(Some(TargetOs::Windows)), Some("cmd /c echo windows"));
+        assert_eq!(target.template_for(Some(TargetOs::Macos)), Some("echo posix"));
+        assert_eq!(target.template_for(None), Some("echo posix"));
+
+        let windows_only: CommandTarget = serde_json::from_value(serde_json::json!({
+            "variants": { "windows": "cmd /c echo windows" }
+        })).unwrap();
+        assert_eq!(windows_only.template_for(Some(TargetOs::Linux)), None);
+    }
+
+    #[tokio::test]
+    async fn dummy_runs_on_every_platform() {
+        let dummy = CpiDefinition::from_file("./CPIs/cpi-dummy.json").await.unwrap();
+        assert!(dummy.unavailable_actions().is_empty(), "{:?}", dummy.unavailable_actions());
+    }
+}
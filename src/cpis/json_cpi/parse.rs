@@ -0,0 +1,283 @@
+//! # Parse Rules Engine
+//!
+//! Applies a CPI action's `parse_rules` to raw command or HTTP output and
+//! returns structured JSON.
+//!
+//! - `object` / `properties`: every pattern is matched against the whole
+//!   output (multi-line mode, so `^` and `$` match at line boundaries) and
+//!   the results form a single object.
+//! - `array`: the output is split on `separator`, which may not be empty;
+//!   each non-empty record becomes one object. Text that matches none of the
+//!   required patterns (headers, rulers) is skipped, but a record that
+//!   matches some of them and misses another fails the parse, so a changed
+//!   output format can't silently drop rows.
+//!
+//! A pattern with `transform: "boolean"` yields whether it matched and never
+//! fails. Other patterns fail the parse when they do not match, unless they
+//! are marked `optional`, in which case the field is left out.
+//!
+//! Actions that set `json_path` skip the patterns: the output is parsed as
+//! JSON and the value at the path (`$.servers[0].id`, `items[*].name`) is
+//! the result.
+
+use regex::{Regex, RegexBuilder};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+use super::definition::{CpiActionDef, ParseRuleType, ParseRules, PatternRule};
+use super::super::PluginError;
+
+/// Errors produced while applying parse rules
+#[derive(Error, Debug, Clone)]
+pub enum ParseError {
+    #[error("Pattern '{field}' has an invalid regex: {message}")]
+    InvalidRegex { field: String, message: String },
+
+    #[error("Required pattern '{field}' did not match the output")]
+    NoMatch { field: String },
+
+    #[error("Required pattern '{field}' did not match record {record}: {text}")]
+    IncompleteRecord { field: String, record: usize, text: String },
+
+    #[error("Array rules need a non-empty separator")]
+    EmptySeparator,
+
+    #[error("Pattern '{field}' has no capture group {group}")]
+    MissingGroup { field: String, group: usize },
+
+    #[error("Pattern '{field}' captured '{value}', which is not a number")]
+    InvalidNumber { field: String, value: String },
+
+    #[error("Pattern '{field}' uses unknown transform '{transform}'")]
+    UnknownTransform { field: String, transform: String },
+
+    #[error("Output is not valid JSON: {message}")]
+    InvalidJson { message: String },
+
+    #[error("JSON path '{path}' is invalid")]
+    InvalidPath { path: String },
+
+    #[error("JSON path '{path}' did not match the output")]
+    PathNotFound { path: String },
+}
+
+impl From<ParseError> for PluginError {
+    fn from(error: ParseError) -> Self {
+        PluginError::ExecutionFailed(format!("Failed to parse output: {}", error))
+    }
+}
+
+/// Value transforms supported by patterns
+pub const KNOWN_TRANSFORMS: &[&str] = &["boolean", "number"];
+
+/// A pattern with its regex compiled
+struct CompiledPattern<'a> {
+    field: &'a str,
+    rule: &'a PatternRule,
+    regex: Regex,
+}
+
+/// Parse raw output according to a set of parse rules
+pub fn parse_output(output: &str, rules: &ParseRules) -> Result<Value, ParseError> {
+    let patterns = compile_patterns(rules)?;
+    let output = output.replace("\r\n", "\n");
+
+    match rules.rule_type {
+        ParseRuleType::Object | ParseRuleType::Properties => {
+            Ok(Value::Object(extract_record(&output, &patterns)?))
+        }
+        ParseRuleType::Array => {
+            let separator = unescape(rules.separator.as_deref().unwrap_or("\\n"));
+            if separator.is_empty() {
+                return Err(ParseError::EmptySeparator);
+            }
+
+            let mut records = Vec::new();
+            let texts = output.split(separator.as_str()).map(str::trim).filter(|text| !text.is_empty());
+            for (index, text) in texts.enumerate() {
+                match extract_record(text, &patterns) {
+                    Ok(fields) => records.push(Value::Object(fields)),
+                    Err(ParseError::NoMatch { .. }) if !matches_any_required(text, &patterns) => {}
+                    Err(ParseError::NoMatch { field }) => {
+                        return Err(ParseError::IncompleteRecord { field, record: index + 1, text: text.to_string() });
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(Value::Array(records))
+        }
+    }
+}
+
+/// Parse an action's output, using its `json_path` when set and its
+/// `parse_rules` otherwise
+pub fn parse_response(output: &str, action: &CpiActionDef) -> Result<Value, ParseError> {
+    match &action.json_path {
+        Some(path) => {
+            let json: Value = serde_json::from_str(output)
+                .map_err(|e| ParseError::InvalidJson { message: e.to_string() })?;
+            extract_json_path(&json, path)
+        }
+        None => parse_output(output, &action.parse_rules),
+    }
+}
+
+/// Segment of a JSON path
+#[derive(Debug, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+    Each,
+}
+
+/// Select the value at a JSON path
+///
+/// Supports `.key`, `[index]` and `[*]`, optionally starting with `$`.
+/// `[*]` maps the rest of the path over every element of an array.
+pub fn extract_json_path(value: &Value, path: &str) -> Result<Value, ParseError> {
+    let segments = parse_path(path).ok_or_else(|| ParseError::InvalidPath { path: path.to_string() })?;
+    select(value, &segments).ok_or_else(|| ParseError::PathNotFound { path: path.to_string() })
+}
+
+fn parse_path(path: &str) -> Option<Vec<PathSegment>> {
+    let path = path.trim();
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let inner = &after[..end];
+            segments.push(match inner {
+                "*" => PathSegment::Each,
+                _ => PathSegment::Index(inner.parse().ok()?),
+            });
+            rest = &after[end + 1..];
+        } else {
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return None;
+            }
+            segments.push(PathSegment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        }
+    }
+
+    Some(segments)
+}
+
+fn select(value: &Value, segments: &[PathSegment]) -> Option<Value> {
+    let Some((segment, rest)) = segments.split_first() else {
+        return Some(value.clone());
+    };
+
+    match segment {
+        PathSegment::Key(key) => select(value.get(key)?, rest),
+        PathSegment::Index(index) => select(value.get(*index)?, rest),
+        PathSegment::Each => value.as_array()?
+            .iter()
+            .map(|item| select(item, rest))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+    }
+}
+
+fn compile_patterns(rules: &ParseRules) -> Result<Vec<CompiledPattern<'_>>, ParseError> {
+    let mut patterns: Vec<CompiledPattern<'_>> = rules.patterns.iter()
+        .map(|(field, rule)| {
+            if let Some(transform) = &rule.transform {
+                if !KNOWN_TRANSFORMS.contains(&transform.as_str()) {
+                    return Err(ParseError::UnknownTransform {
+                        field: field.clone(),
+                        transform: transform.clone(),
+                    });
+                }
+            }
+            let regex = RegexBuilder::new(&rule.regex)
+                .multi_line(true)
+                .build()
+                .map_err(|e| ParseError::InvalidRegex { field: field.clone(), message: e.to_string() })?;
+            Ok(CompiledPattern { field, rule, regex })
+        })
+        .collect::<Result<_, _>>()?;
+
+    // Stable field order keeps error messages deterministic
+    patterns.sort_by(|a, b| a.field.cmp(b.field));
+    Ok(patterns)
+}
+
+/// Whether any pattern a record must match matches the text, which tells a
+/// record from a header or ruler
+fn matches_any_required(text: &str, patterns: &[CompiledPattern<'_>]) -> bool {
+    patterns.iter()
+        .filter(|pattern| !pattern.rule.optional && pattern.rule.transform.as_deref() != Some("boolean"))
+        .any(|pattern| pattern.regex.is_match(text))
+}
+
+/// Apply every pattern to one piece of text
+fn extract_record(text: &str, patterns: &[CompiledPattern<'_>]) -> Result<Map<String, Value>, ParseError> {
+    let mut fields = Map::new();
+
+    for pattern in patterns {
+        let captures = pattern.regex.captures(text);
+
+        if pattern.rule.transform.as_deref() == Some("boolean") {
+            fields.insert(pattern.field.to_string(), Value::Bool(captures.is_some()));
+            continue;
+        }
+
+        let captures = match captures {
+            Some(captures) => captures,
+            None if pattern.rule.optional => continue,
+            None => return Err(ParseError::NoMatch { field: pattern.field.to_string() }),
+        };
+
+        let group = pattern.rule.group.unwrap_or(0);
+        let text = match captures.get(group) {
+            Some(m) => m.as_str().trim(),
+            None if group < pattern.regex.captures_len() && pattern.rule.optional => continue,
+            None if group < pattern.regex.captures_len() => {
+                return Err(ParseError::NoMatch { field: pattern.field.to_string() });
+            }
+            None => {
+                return Err(ParseError::MissingGroup { field: pattern.field.to_string(), group });
+            }
+        };
+
+        let value = match pattern.rule.transform.as_deref() {
+            Some("number") => parse_number(text)
+                .ok_or_else(|| ParseError::InvalidNumber { field: pattern.field.to_string(), value: text.to_string() })?,
+            _ => Value::String(text.to_string()),
+        };
+        fields.insert(pattern.field.to_string(), value);
+    }
+
+    Ok(fields)
+}
+
+fn parse_number(text: &str) -> Option<Value> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Some(Value::Number(n.into()));
+    }
+    text.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number)
+}
+
+/// Turn an escaped separator such as `\n` into the real characters
+fn unescape(separator: &str) -> String {
+    let mut result = String::new();
+    let mut chars = separator.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
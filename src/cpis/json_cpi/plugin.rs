@@ -0,0 +1,275 @@
+//! # CPI Plugin
+//!
+//! Adapts a declarative CPI definition to the `Plugin` trait so JSON
+//! providers go through the same registry and event flow as native plugins.
+//! Besides its own `Worker_Manage` actions, a CPI serves every feature action
+//! that resolves to one of them through the mapping layer.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use async_trait::async_trait;
+use serde_json::Value;
+use uuid::Uuid;
+use super::definition::CpiDefinition;
+use super::mapping::ResolvedMapping;
+use super::runner::CpiActionRunner;
+use super::CPI_FEATURE;
+use super::super::cancellation::{cancel_event_key, CANCEL_ACK_EVENT_KEY};
+use super::super::{
+    feature_event_key, ActionFailure, ArgumentDef, ArgumentType, CancellationTokens, EventError, EventSystem,
+    FeatureActionCancelAckEvent, FeatureActionCancelEvent, FeatureActionCompleteEvent,
+    FeatureActionEvent, FeatureActionProgressEvent, LogLevel, Plugin, PluginError, ServerContext,
+    PROGRESS_EVENT_KEY,
+};
+
+/// Plugin backed by a CPI JSON definition
+pub struct CpiPlugin {
+    definition: Arc<CpiDefinition>,
+    /// Feature actions this provider serves through one of its own actions
+    mappings: Vec<ResolvedMapping>,
+    /// Actions currently running, so cancel events can stop them
+    running: CancellationTokens,
+    /// Set up in `pre_init`, once the server context is known
+    runner: Option<CpiActionRunner>,
+}
+
+impl CpiPlugin {
+    pub fn new(definition: Arc<CpiDefinition>, mappings: Vec<ResolvedMapping>) -> Self {
+        Self { definition, mappings, running: CancellationTokens::new(), runner: None }
+    }
+
+    /// Argument definitions for the provider's default settings
+    fn setting_arguments(&self) -> Vec<ArgumentDef> {
+        self.definition.default_settings.iter()
+            .map(|(name, value)| ArgumentDef {
+                name: name.clone(),
+                description: format!("Default setting of CPI {}", self.definition.name),
+                arg_type: argument_type_of(value),
+                required: false,
+                default_value: Some(value.clone()),
+                constraints: None,
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Plugin for CpiPlugin {
+    fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    fn version(&self) -> &str {
+        &self.definition.version
+    }
+
+    fn declared_features(&self) -> Vec<String> {
+        let mut features = vec![CPI_FEATURE.to_string()];
+        for mapping in &self.mappings {
+            if !features.contains(&mapping.feature) {
+                features.push(mapping.feature.clone());
+            }
+        }
+        features
+    }
+
+    async fn pre_init(&mut self, context: Arc<dyn ServerContext>) -> Result<(), PluginError> {
+        let events = context.events();
+        let runner = CpiActionRunner::new(Arc::clone(&self.definition), Arc::clone(&context));
+
+        let provider = &self.definition.name;
+        let running = &self.running;
+
+        for action_name in self.definition.actions.keys() {
+            let event_key = feature_event_key(provider, CPI_FEATURE, action_name);
+            subscribe(&events, &event_key, runner.clone(), running.clone(), None).await?;
+        }
+
+        for mapping in &self.mappings {
+            // A native Worker_Manage action of the same name is already subscribed
+            if mapping.feature == CPI_FEATURE && self.definition.actions.contains_key(&mapping.feature_action) {
+                continue;
+            }
+            let event_key = feature_event_key(provider, &mapping.feature, &mapping.feature_action);
+            subscribe(&events, &event_key, runner.clone(), running.clone(), Some(mapping.clone())).await?;
+        }
+
+        subscribe_cancel(&events, provider, running.clone()).await?;
+        self.runner = Some(runner);
+        Ok(())
+    }
+
+    async fn init(&mut self, context: Arc<dyn ServerContext>) -> Result<(), PluginError> {
+        context.arguments()
+            .register_plugin_arguments(&self.definition.name, self.setting_arguments())
+            .await?;
+
+        context.log(LogLevel::Info, &format!(
+            "CPI {} ready with {} actions", self.definition.name, self.definition.actions.len()
+        ));
+        let unavailable = self.definition.unavailable_actions();
+        if !unavailable.is_empty() {
+            context.log(LogLevel::Warn, &format!(
+                "CPI {} has no {} command for: {}",
+                self.definition.name, std::env::consts::OS, unavailable.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&mut self, _context: Arc<dyn ServerContext>) -> Result<(), PluginError> {
+        Ok(())
+    }
+
+    async fn plan_action(
+        &self,
+        feature: &str,
+        action: &str,
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+    ) -> Result<Option<Value>, PluginError> {
+        let runner = self.runner.as_ref().ok_or_else(|| PluginError::ExecutionFailed(format!(
+            "CPI {} is not initialized", self.definition.name
+        )))?;
+
+        // Same choice as the subscriptions: a native action wins over a mapping
+        let (cpi_action, arguments) = if feature == CPI_FEATURE && self.definition.actions.contains_key(action) {
+            (action.to_string(), arguments.clone())
+        } else {
+            let mapping = self.mappings.iter()
+                .find(|mapping| mapping.feature == feature && mapping.feature_action == action)
+                .ok_or_else(|| PluginError::UnsupportedFeature(format!(
+                    "CPI {} does not serve {}/{}", self.definition.name, feature, action
+                )))?;
+            (mapping.cpi_action.clone(), mapped_arguments(&self.definition, mapping, arguments))
+        };
+
+        let request = runner.plan(&cpi_action, &arguments, request_id).await?;
+        Ok(Some(serde_json::to_value(request)?))
+    }
+}
+
+/// Run the matching CPI action whenever `event_key` fires
+///
+/// With a mapping, the arguments are translated and the mapped CPI action is
+/// run instead of the event's own action name. Cancelling the request stops
+/// the action's command or HTTP request, and the cancellation is acknowledged
+/// once the action has ended.
+async fn subscribe(
+    events: &Arc<EventSystem>,
+    event_key: &str,
+    runner: CpiActionRunner,
+    running: CancellationTokens,
+    mapping: Option<ResolvedMapping>,
+) -> Result<(), PluginError> {
+    let events_for_handler = Arc::clone(events);
+
+    events.on_event::<FeatureActionEvent, _>(event_key, move |event| {
+        let runner = runner.clone();
+        let running = running.clone();
+        let events = Arc::clone(&events_for_handler);
+        let (action, arguments) = match &mapping {
+            Some(mapping) => (mapping.cpi_action.clone(), mapped_arguments(runner.definition(), mapping, &event.arguments)),
+            None => (event.action.clone(), event.arguments.clone()),
+        };
+        let token = running.start(event.request_id);
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let started = FeatureActionProgressEvent {
+                request_id: event.request_id,
+                percent: Some(0),
+                phase: Some("running".to_string()),
+                message: Some(format!("Running {}", action)),
+                data: None,
+            };
+            if let Err(e) = events.emit_event(PROGRESS_EVENT_KEY, &started).await {
+                eprintln!("Failed to report progress for request {}: {}", event.request_id, e);
+            }
+            let result = runner.run(&action, &arguments, event.request_id, &token).await;
+            running.finish(event.request_id, &token);
+            if token.is_cancelled() {
+                // Only now is it known whether the work was stopped in time
+                let acknowledgement = FeatureActionCancelAckEvent {
+                    request_id: event.request_id,
+                    provider: event.provider.clone(),
+                    acknowledged: true,
+                    stopped: matches!(result, Err(PluginError::Cancelled(_))),
+                };
+                if let Err(e) = events.emit_event(CANCEL_ACK_EVENT_KEY, &acknowledgement).await {
+                    eprintln!("Failed to acknowledge cancellation of request {}: {}", event.request_id, e);
+                }
+            }
+            let failure = result.as_ref().err().and_then(ActionFailure::of);
+            let completion = FeatureActionCompleteEvent {
+                request_id: event.request_id,
+                result: result.map_err(|e| ActionFailure::message(&e)),
+                execution_time_ms: start.elapsed().as_millis() as u64,
+                failure,
+            };
+            if let Err(e) = events.emit_event("feature:action:complete", &completion).await {
+                eprintln!("Failed to emit completion for request {}: {}", event.request_id, e);
+            }
+        });
+        Ok::<(), EventError>(())
+    }).await
+        .map_err(|e| PluginError::EventError(e.to_string()))
+}
+
+/// Stop running actions when the executor cancels their request
+async fn subscribe_cancel(
+    events: &Arc<EventSystem>,
+    provider: &str,
+    running: CancellationTokens,
+) -> Result<(), PluginError> {
+    let events_for_handler = Arc::clone(events);
+
+    events.on_event::<FeatureActionCancelEvent, _>(&cancel_event_key(provider), move |event| {
+        // A running action acknowledges once it has ended
+        if running.cancel(event.request_id) {
+            return Ok(());
+        }
+        let acknowledgement = FeatureActionCancelAckEvent {
+            request_id: event.request_id,
+            provider: event.provider.clone(),
+            acknowledged: false,
+            stopped: false,
+        };
+        let events = Arc::clone(&events_for_handler);
+        tokio::spawn(async move {
+            if let Err(e) = events.emit_event(CANCEL_ACK_EVENT_KEY, &acknowledgement).await {
+                eprintln!("Failed to acknowledge cancellation of request {}: {}", acknowledgement.request_id, e);
+            }
+        });
+        Ok::<(), EventError>(())
+    }).await
+        .map_err(|e| PluginError::EventError(e.to_string()))
+}
+
+/// Arguments for the CPI action a mapping runs
+///
+/// Feature arguments the CPI action doesn't take are left out; the feature
+/// schema already checked them, the provider just has no use for them.
+fn mapped_arguments(
+    definition: &CpiDefinition,
+    mapping: &ResolvedMapping,
+    arguments: &HashMap<String, Value>,
+) -> HashMap<String, Value> {
+    let action = definition.action(&mapping.cpi_action);
+    mapping.translate_arguments(arguments).into_iter()
+        .filter(|(name, _)| action.is_some_and(|action| action.takes_argument(name)))
+        .collect()
+}
+
+/// Infer an argument type from a default setting value
+fn argument_type_of(value: &Value) -> ArgumentType {
+    match value {
+        Value::Bool(_) => ArgumentType::Boolean,
+        Value::Number(_) => ArgumentType::Number { min: None, max: None },
+        Value::Array(_) => ArgumentType::Array {
+            item_type: Box::new(ArgumentType::String { max_length: None }),
+        },
+        Value::Object(_) => ArgumentType::Object { properties: HashMap::new() },
+        _ => ArgumentType::String { max_length: None },
+    }
+}
@@ -0,0 +1,565 @@
+//! # Command Rendering
+//!
+//! Turns a CPI command template such as
+//! `virsh start {worker_name}` into an argv vector. Parameter values are
+//! always inserted as data: a value never introduces new words, quotes or
+//! shell operators, so user-supplied names cannot inject commands.
+//!
+//! Templates that use shell syntax outside quotes (pipes, `&&`, redirects,
+//! `$(...)`) are run through the platform shell, with every substituted value
+//! quoted for the quoting context it appears in.
+//!
+//! `cmd` re-parses its whole command line and expands `%VAR%` even inside
+//! quotes, so no quoting makes arbitrary text safe there. Values rendered for
+//! `cmd`, whether as the Windows shell or as the program of a template like
+//! `cmd /c "echo {name}"`, are refused when they contain characters it acts on.
+//!
+//! A value that starts a word may not start with `-`, so it can't be read as
+//! an option by the program it is passed to. Placeholders named `*_param` are
+//! the exception: they are slots for optional flags. A request can't set them;
+//! their template comes from the CPI's `default_settings` or the operator's
+//! settings and is expanded by [`expand_slot`] from the other parameters.
+//!
+//! Commands that run inside a worker (`in_vm`) are rendered into a single
+//! POSIX shell script, since that is what the remote side executes.
+
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::Value;
+use super::super::PluginError;
+
+/// A rendered command ready to be executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedCommand {
+    /// Program followed by its arguments
+    pub argv: Vec<String>,
+    /// Whether the command is a script handed to the platform shell
+    pub uses_shell: bool,
+}
+
+impl RenderedCommand {
+    /// Program to execute
+    pub fn program(&self) -> &str {
+        &self.argv[0]
+    }
+
+    /// Arguments passed to the program
+    pub fn args(&self) -> Vec<&str> {
+        self.argv[1..].iter().map(String::as_str).collect()
+    }
+}
+
+impl fmt::Display for RenderedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.uses_shell {
+            return write!(f, "{}", self.argv.last().map(String::as_str).unwrap_or_default());
+        }
+        let posix = !cfg!(target_os = "windows");
+        let words: Vec<String> = self.argv.iter().map(|word| display_word(word, posix)).collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// Names of all `{placeholder}`s in a template, in order of appearance
+pub fn placeholders(template: &str) -> Vec<String> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some((name, next)) = placeholder_at(&chars, i) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+            i = next;
+        } else {
+            i += 1;
+        }
+    }
+    names
+}
+
+/// Text inserted for a parameter value
+pub fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(value_to_text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Whether a placeholder is a slot for an optional flag
+pub fn is_flag_slot(name: &str) -> bool {
+    name.ends_with("_param")
+}
+
+/// Expand a flag slot template such as `--ssh-key={ssh_key}`
+///
+/// Values are inserted as they are, since the expanded slot is itself
+/// substituted as a single value. If any of them is empty the flag has
+/// nothing to apply and the slot expands to empty text, which drops it.
+pub fn expand_slot(slot: &str, template: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut expanded = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some((name, next)) = placeholder_at(&chars, i) {
+            if is_flag_slot(&name) {
+                return Err(PluginError::InvalidArgument(format!(
+                    "Flag slot {{{}}} can't contain another slot {{{}}}", slot, name
+                )));
+            }
+            let text = lookup(&name, values)?;
+            if text.is_empty() {
+                return Ok(String::new());
+            }
+            check_not_option(&chars, i, &name, &text)?;
+            expanded.push_str(&text);
+            i = next;
+        } else {
+            expanded.push(chars[i]);
+            i += 1;
+        }
+    }
+    Ok(expanded)
+}
+
+/// Render a command template into an argv vector
+///
+/// Every placeholder must have an entry in `values`; optional parameters
+/// that were not supplied should be passed as `Value::Null`. A word that
+/// consists only of empty placeholders is dropped, like an unquoted empty
+/// variable in a shell.
+pub fn render_command(template: &str, values: &HashMap<String, Value>) -> Result<RenderedCommand, PluginError> {
+    let chars: Vec<char> = template.chars().collect();
+
+    if needs_shell(&chars)? {
+        let posix = !cfg!(target_os = "windows");
+        if !posix {
+            check_cmd_values(template, values)?;
+        }
+        let script = render_script(&chars, values, posix)?;
+        let (shell, flag) = platform_shell();
+        return Ok(RenderedCommand {
+            argv: vec![shell.to_string(), flag.to_string(), script],
+            uses_shell: true,
+        });
+    }
+
+    let argv = split_words(&chars, values)?;
+    if argv.is_empty() {
+        return Err(PluginError::InvalidArgument("Command template renders to an empty command".to_string()));
+    }
+    if is_cmd(&argv[0]) {
+        check_cmd_values(template, values)?;
+    }
+
+    Ok(RenderedCommand { argv, uses_shell: false })
+}
+
+/// Render a command template into a script for a remote POSIX shell
+pub fn render_remote_command(template: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    let chars: Vec<char> = template.chars().collect();
+
+    if needs_shell(&chars)? {
+        return render_script(&chars, values, true);
+    }
+
+    let argv = split_words(&chars, values)?;
+    if argv.is_empty() {
+        return Err(PluginError::InvalidArgument("Command template renders to an empty command".to_string()));
+    }
+
+    Ok(argv.iter().map(|word| display_word(word, true)).collect::<Vec<_>>().join(" "))
+}
+
+/// Shell used for templates that rely on shell syntax
+pub fn platform_shell() -> (&'static str, &'static str) {
+    if cfg!(target_os = "windows") {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    }
+}
+
+/// Characters `cmd` acts on even inside double quotes, or that end them
+const CMD_SPECIAL: &[char] = &['"', '%', '!', '^', '&', '|', '<', '>', '(', ')', '\r', '\n'];
+
+/// Whether a program is `cmd`, which parses its arguments as a command line
+fn is_cmd(program: &str) -> bool {
+    let name = program.rsplit(['/', '\\']).next().unwrap_or(program).to_ascii_lowercase();
+    name == "cmd" || name == "cmd.exe"
+}
+
+/// Refuse values that `cmd` would interpret instead of passing on
+fn check_cmd_values(template: &str, values: &HashMap<String, Value>) -> Result<(), PluginError> {
+    for name in placeholders(template) {
+        let text = lookup(&name, values)?;
+        if let Some(c) = text.chars().find(|c| CMD_SPECIAL.contains(c)) {
+            return Err(PluginError::InvalidArgument(format!(
+                "Value for {{{}}} contains '{}', which cmd would interpret", name, c.escape_default()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Refuse a value that would start a word with `-`, where it could be read as an option
+fn check_not_option(chars: &[char], start: usize, name: &str, text: &str) -> Result<(), PluginError> {
+    if is_flag_slot(name) {
+        return Ok(());
+    }
+    let mut before = start;
+    if before > 0 && matches!(chars[before - 1], '\'' | '"') {
+        before -= 1;
+    }
+    let starts_word = before == 0 || chars[before - 1].is_whitespace();
+    if starts_word && text.starts_with('-') {
+        return Err(PluginError::InvalidArgument(format!(
+            "Value for {{{}}} starts with '-' and would be read as an option", name
+        )));
+    }
+    Ok(())
+}
+
+/// Match `{identifier}` starting at `start`, returning the name and the index after it
+pub(super) fn placeholder_at(chars: &[char], start: usize) -> Option<(String, usize)> {
+    if chars.get(start) != Some(&'{') {
+        return None;
+    }
+    let mut end = start + 1;
+    while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+    let first = chars.get(start + 1)?;
+    if end == start + 1 || first.is_ascii_digit() || chars.get(end) != Some(&'}') {
+        return None;
+    }
+    Some((chars[start + 1..end].iter().collect(), end + 1))
+}
+
+pub(super) fn lookup(name: &str, values: &HashMap<String, Value>) -> Result<String, PluginError> {
+    values.get(name)
+        .map(value_to_text)
+        .ok_or_else(|| PluginError::InvalidArgument(format!("No value for placeholder {{{}}}", name)))
+}
+
+/// Whether the template uses shell operators outside of quotes
+fn needs_shell(chars: &[char]) -> Result<bool, PluginError> {
+    let mut quote = Quote::None;
+    let mut found = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Quote::None => match c {
+                '\\' => i += 1,
+                '\'' => quote = Quote::Single,
+                '"' => quote = Quote::Double,
+                '|' | '&' | ';' | '<' | '>' | '`' | '$' => found = true,
+                _ => {}
+            },
+            Quote::Single => if c == '\'' { quote = Quote::None },
+            Quote::Double => match c {
+                '\\' => i += 1,
+                '"' => quote = Quote::None,
+                '`' | '$' => found = true,
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    if quote != Quote::None {
+        return Err(PluginError::InvalidArgument("Command template has an unterminated quote".to_string()));
+    }
+    Ok(found)
+}
+
+/// Split a template into words, substituting placeholders inside each word
+fn split_words(chars: &[char], values: &HashMap<String, Value>) -> Result<Vec<String>, PluginError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut started = false;
+    let mut quote = Quote::None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if let Some((name, next)) = placeholder_at(chars, i) {
+            let text = lookup(&name, values)?;
+            check_not_option(chars, i, &name, &text)?;
+            started |= !text.is_empty();
+            word.push_str(&text);
+            i = next;
+            continue;
+        }
+
+        match quote {
+            Quote::None => match c {
+                c if c.is_whitespace() => {
+                    if started {
+                        words.push(std::mem::take(&mut word));
+                    }
+                    word.clear();
+                    started = false;
+                }
+                '\'' => { quote = Quote::Single; started = true; }
+                '"' => { quote = Quote::Double; started = true; }
+                '\\' if i + 1 < chars.len() => { i += 1; word.push(chars[i]); started = true; }
+                _ => { word.push(c); started = true; }
+            },
+            Quote::Single => match c {
+                '\'' => quote = Quote::None,
+                _ => word.push(c),
+            },
+            Quote::Double => match c {
+                '"' => quote = Quote::None,
+                '\\' if matches!(chars.get(i + 1), Some('"') | Some('\\')) => { i += 1; word.push(chars[i]); }
+                _ => word.push(c),
+            },
+        }
+        i += 1;
+    }
+
+    if started {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Copy a shell template verbatim, quoting each substituted value for its context
+fn render_script(chars: &[char], values: &HashMap<String, Value>, posix: bool) -> Result<String, PluginError> {
+    let mut script = String::new();
+    let mut quote = Quote::None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some((name, next)) = placeholder_at(chars, i) {
+            let text = lookup(&name, values)?;
+            check_not_option(chars, i, &name, &text)?;
+            match quote {
+                Quote::None if text.is_empty() => {}
+                Quote::None => script.push_str(&shell_quote(&text, posix)),
+                // cmd has no single quotes, and the values were already checked
+                _ if !posix => script.push_str(&text),
+                Quote::Single => script.push_str(&text.replace('\'', "'\\''")),
+                Quote::Double => script.push_str(&escape_double_quoted(&text)),
+            }
+            i = next;
+            continue;
+        }
+
+        let c = chars[i];
+        script.push(c);
+        match (quote, c) {
+            (Quote::None, '\\') | (Quote::Double, '\\') => {
+                if let Some(&escaped) = chars.get(i + 1) {
+                    script.push(escaped);
+                    i += 1;
+                }
+            }
+            (Quote::None, '\'') => quote = Quote::Single,
+            (Quote::None, '"') => quote = Quote::Double,
+            (Quote::Single, '\'') | (Quote::Double, '"') => quote = Quote::None,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Ok(script)
+}
+
+/// Quote a value as a single word for a POSIX shell or `cmd`
+///
+/// For `cmd` this only keeps spaces together; values with characters it
+/// interprets are refused before they get here.
+fn shell_quote(text: &str, posix: bool) -> String {
+    if posix {
+        return format!("'{}'", text.replace('\'', "'\\''"));
+    }
+    // Backslashes before the closing quote would escape it for the program
+    let trailing = text.len() - text.trim_end_matches('\\').len();
+    format!("\"{}{}\"", text, "\\".repeat(trailing))
+}
+
+fn escape_double_quoted(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn display_word(word: &str, posix: bool) -> String {
+    let plain = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        shell_quote(word, posix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn values(pairs: &[(&str, Value)]) -> HashMap<String, Value> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn values_stay_single_arguments() {
+        let rendered = render_command(
+            "virsh start {worker_name}",
+            &values(&[("worker_name", json!("vm1; rm -rf / #"))]),
+        ).unwrap();
+
+        assert!(!rendered.uses_shell);
+        assert_eq!(rendered.argv, vec!["virsh", "start", "vm1; rm -rf / #"]);
+    }
+
+    #[test]
+    fn quoted_template_words_keep_substitutions_together() {
+        let rendered = render_command(
+            "hcloud server create --name {name} --label \"team={team} owner\"",
+            &values(&[("name", json!("web")), ("team", json!("ops"))]),
+        ).unwrap();
+
+        assert_eq!(rendered.argv, vec!["hcloud", "server", "create", "--name", "web", "--label", "team=ops owner"]);
+    }
+
+    #[test]
+    fn empty_optional_words_are_dropped() {
+        let rendered = render_command(
+            "hcloud server create --name {name} {ssh_key_param} --type {server_type}",
+            &values(&[("name", json!("web")), ("ssh_key_param", Value::Null), ("server_type", json!(11))]),
+        ).unwrap();
+
+        assert_eq!(rendered.argv, vec!["hcloud", "server", "create", "--name", "web", "--type", "11"]);
+    }
+
+    #[test]
+    fn non_identifier_braces_are_literal() {
+        let rendered = render_command("awk '{print $1}' {file}", &values(&[("file", json!("a.txt"))])).unwrap();
+        assert_eq!(rendered.argv, vec!["awk", "{print $1}", "a.txt"]);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn shell_templates_quote_values() {
+        let rendered = render_command(
+            "vim-cmd vmsvc/getallvms | grep {worker_name} | awk '{print $1}'",
+            &values(&[("worker_name", json!("x'; reboot; echo '"))]),
+        ).unwrap();
+
+        assert!(rendered.uses_shell);
+        assert_eq!(rendered.argv[0], "sh");
+        assert_eq!(
+            rendered.argv[2],
+            "vim-cmd vmsvc/getallvms | grep 'x'\\''; reboot; echo '\\''' | awk '{print $1}'"
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn shell_templates_escape_inside_quotes() {
+        let rendered = render_command(
+            "echo '{body}' > {dir}/out.sh && grep \"{pattern}\" {dir}/out.sh",
+            &values(&[("body", json!("it's")), ("dir", json!("/tmp/a b")), ("pattern", json!("$(id)"))]),
+        ).unwrap();
+
+        assert_eq!(
+            rendered.argv[2],
+            "echo 'it'\\''s' > '/tmp/a b'/out.sh && grep \"\\$(id)\" '/tmp/a b'/out.sh"
+        );
+    }
+
+    #[test]
+    fn remote_commands_are_posix_scripts() {
+        let script = render_remote_command(
+            "systemctl restart {service} {extra}",
+            &values(&[("service", json!("nginx; reboot")), ("extra", Value::Null)]),
+        ).unwrap();
+        assert_eq!(script, "systemctl restart 'nginx; reboot'");
+
+        let script = render_remote_command(
+            "df -h | grep {mount}",
+            &values(&[("mount", json!("/data"))]),
+        ).unwrap();
+        assert_eq!(script, "df -h | grep '/data'");
+    }
+
+    #[test]
+    fn values_cannot_become_options() {
+        for template in ["rm {path}", "rm \"{path}\"", "ls {path} | wc -l"] {
+            let result = render_command(template, &values(&[("path", json!("-rf"))]));
+            assert!(
+                matches!(&result, Err(PluginError::InvalidArgument(message)) if message.contains("read as an option")),
+                "{}: {:?}", template, result
+            );
+        }
+        assert!(render_remote_command("rm {path}", &values(&[("path", json!("--no-preserve-root"))])).is_err());
+
+        // Inside a word the value is the option's argument, not an option
+        let rendered = render_command("tool --offset={offset}", &values(&[("offset", json!("-5"))])).unwrap();
+        assert_eq!(rendered.argv, vec!["tool", "--offset=-5"]);
+
+        let rendered = render_command("upctl server stop {uuid} {stop_type_param}", &values(&[("uuid", json!("0a1b")), ("stop_type_param", json!("--type=hard"))])).unwrap();
+        assert_eq!(rendered.args(), vec!["server", "stop", "0a1b", "--type=hard"]);
+    }
+
+    #[test]
+    fn flag_slots_expand_from_their_values() {
+        let slot = expand_slot("ssh_key_param", "--ssh-key={ssh_key}", &values(&[("ssh_key", json!("-deploy"))])).unwrap();
+        assert_eq!(slot, "--ssh-key=-deploy");
+
+        for missing in [Value::Null, json!("")] {
+            let slot = expand_slot("ssh_key_param", "--ssh-key={ssh_key}", &values(&[("ssh_key", missing)])).unwrap();
+            assert_eq!(slot, "");
+        }
+
+        assert!(expand_slot("key_param", "{key}", &values(&[("key", json!("--admin"))])).is_err());
+        assert!(expand_slot("key_param", "--key={other_param}", &values(&[("other_param", json!("x"))])).is_err());
+    }
+
+    #[test]
+    fn cmd_refuses_values_it_would_interpret() {
+        for value in ["%PATH%", "a & calc", "x\" & calc & \"", "!var!", "a^b"] {
+            let result = render_command("cmd /c \"echo {message}\"", &values(&[("message", json!(value))]));
+            assert!(
+                matches!(&result, Err(PluginError::InvalidArgument(message)) if message.contains("cmd would interpret")),
+                "{}: {:?}", value, result
+            );
+        }
+
+        let rendered = render_command("C:/Windows/System32/CMD.EXE /c \"echo {message}\"", &values(&[("message", json!("hello world"))])).unwrap();
+        assert_eq!(rendered.args(), vec!["/c", "echo hello world"]);
+        assert_eq!(shell_quote("C:\\dir\\", false), "\"C:\\dir\\\\\"");
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        assert!(render_command("virsh start {worker_name}", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_listed_once() {
+        assert_eq!(
+            placeholders("cp {src} {dst} && ls {dst} '{print $1}'"),
+            vec!["src".to_string(), "dst".to_string()]
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! # CPI Action Runner
+//!
+//! Executes a single action of a declarative CPI definition: resolves the
+//! parameters its target references, renders the target, runs it (locally,
+//! over SSH inside the worker for `in_vm` commands, or as an HTTP request)
+//! and parses the output with the action's `parse_rules`. Executions go
+//! through the provider's cassette, so they can be recorded or replayed.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde_json::Value;
+use uuid::Uuid;
+use super::cassette::{
+    CassetteMode, CassetteRequest, CassetteResponse, CassetteStore, Redactions, DEFAULT_CASSETTE_DIR,
+};
+use super::definition::{CpiActionDef, CpiDefinition, CpiTarget};
+use super::http::{
+    endpoint_placeholders, endpoint_setting_placeholders, render_request, EndpointExecutor, RenderedRequest,
+};
+use super::parse::parse_response;
+use super::render::{
+    expand_slot, is_flag_slot, placeholders, render_command, render_remote_command, value_to_text, RenderedCommand,
+};
+use super::ssh::{SshExecutor, SshTarget, SSH_ARGUMENTS, SSH_KNOWN_HOSTS_ARGUMENT};
+use super::super::{ArgumentResolution, CancellationToken, PluginError, ServerContext};
+
+/// An action's target, rendered with its parameter values
+#[derive(Debug, Clone)]
+enum RenderedTarget {
+    Command(RenderedCommand),
+    /// A command run over SSH inside the worker
+    Remote { target: SshTarget, script: String },
+    Endpoint(RenderedRequest),
+}
+
+impl RenderedTarget {
+    /// The target as a cassette records it
+    fn cassette_request(&self) -> CassetteRequest {
+        match self {
+            RenderedTarget::Command(command) => CassetteRequest::Command { argv: command.argv.clone() },
+            RenderedTarget::Remote { target, script } => CassetteRequest::Ssh { target: target.key(), script: script.clone() },
+            RenderedTarget::Endpoint(request) => CassetteRequest::http(request),
+        }
+    }
+}
+
+/// Runs actions for one CPI definition
+#[derive(Debug, Clone)]
+pub struct CpiActionRunner {
+    definition: Arc<CpiDefinition>,
+    context: Arc<dyn ServerContext>,
+    http: EndpointExecutor,
+    ssh: SshExecutor,
+    cassettes: CassetteStore,
+}
+
+impl CpiActionRunner {
+    pub fn new(definition: Arc<CpiDefinition>, context: Arc<dyn ServerContext>) -> Self {
+        let cassettes = CassetteStore::new(&definition.name);
+        Self {
+            definition,
+            context,
+            http: EndpointExecutor::new(),
+            ssh: SshExecutor::new(),
+            cassettes,
+        }
+    }
+
+    /// Run an action with the given request arguments
+    ///
+    /// Cancelling the token stops the command or request the action runs.
+    pub async fn run(
+        &self,
+        action_name: &str,
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+        token: &CancellationToken,
+    ) -> Result<Value, PluginError> {
+        let action = self.action(action_name)?;
+        let (rendered, redactions) = self.render(action_name, action, arguments, request_id).await?;
+        let request = rendered.cassette_request();
+
+        let response = match &rendered {
+            RenderedTarget::Command(command) => {
+                self.exchange(action_name, request, &redactions, || unless_cancelled(token, async {
+                    self.context.execute_system_command(command.program(), &command.args()).await
+                        .map(CassetteResponse::command)
+                        .map_err(|e| PluginError::ExecutionFailed(e.to_string()))
+                })).await?
+            }
+            RenderedTarget::Remote { target, script } => {
+                self.exchange(action_name, request, &redactions, || async {
+                    self.ssh.execute(target, script, token).await.map(CassetteResponse::command)
+                }).await?
+            }
+            RenderedTarget::Endpoint(http_request) => {
+                let response = self.exchange(action_name, request, &redactions, || unless_cancelled(token, async {
+                    self.http.fetch(http_request).await.map(CassetteResponse::http)
+                })).await?;
+                let body = response.into_http()?.into_result()?;
+                return Ok(parse_response(&body, action)?);
+            }
+        };
+        let result = response.into_command()?;
+
+        if result.exit_code != 0 {
+            let message = if result.stderr.trim().is_empty() { result.stdout } else { result.stderr };
+            return Err(PluginError::ExecutionFailed(format!(
+                "Command exited with code {}: {}", result.exit_code, message.trim()
+            )));
+        }
+
+        Ok(parse_response(&result.stdout, action)?)
+    }
+
+    /// Render an action without running it, with sensitive values replaced
+    /// by their placeholders
+    pub async fn plan(
+        &self,
+        action_name: &str,
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+    ) -> Result<CassetteRequest, PluginError> {
+        let action = self.action(action_name)?;
+        let (rendered, redactions) = self.render(action_name, action, arguments, request_id).await?;
+        Ok(rendered.cassette_request().redacted(&redactions))
+    }
+
+    pub fn definition(&self) -> &CpiDefinition {
+        &self.definition
+    }
+
+    fn action(&self, action_name: &str) -> Result<&CpiActionDef, PluginError> {
+        self.definition.action(action_name)
+            .ok_or_else(|| PluginError::InvalidArgument(format!(
+                "Action {} not found in CPI {}", action_name, self.definition.name
+            )))
+    }
+
+    /// Resolve the parameters of an action's target and render it
+    async fn render(
+        &self,
+        action_name: &str,
+        action: &CpiActionDef,
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+    ) -> Result<(RenderedTarget, Redactions), PluginError> {
+        let settings = match &action.target {
+            CpiTarget::Endpoint(target) => endpoint_setting_placeholders(target),
+            CpiTarget::Command(_) => Vec::new(),
+        };
+        self.check_arguments(action_name, action, arguments, &settings)?;
+
+        match &action.target {
+            CpiTarget::Command(target) => {
+                let template = target.template().ok_or_else(|| PluginError::UnsupportedFeature(format!(
+                    "{}/{} has no command for {}", self.definition.name, action_name, std::env::consts::OS
+                )))?;
+                let names = placeholders(template);
+                let values = self.resolve_params(action_name, action, &names, arguments, &[], request_id).await?;
+                let redactions = Redactions::from_values(&values);
+
+                let rendered = if target.in_vm {
+                    let script = render_remote_command(template, &values)?;
+                    let mut connection = self.resolve_settings(action, SSH_ARGUMENTS, arguments, request_id).await;
+                    if let Some(known_hosts) = self.setting(action, SSH_KNOWN_HOSTS_ARGUMENT, None).await {
+                        connection.insert(SSH_KNOWN_HOSTS_ARGUMENT.to_string(), known_hosts);
+                    }
+                    RenderedTarget::Remote { target: SshTarget::from_arguments(&connection)?, script }
+                } else {
+                    RenderedTarget::Command(render_command(template, &values)?)
+                };
+                Ok((rendered, redactions))
+            }
+            CpiTarget::Endpoint(target) => {
+                let names = endpoint_placeholders(target);
+                let values = self.resolve_params(action_name, action, &names, arguments, &settings, request_id).await?;
+                let request = render_request(target, &values)?;
+                Ok((RenderedTarget::Endpoint(request), Redactions::from_values(&values)))
+            }
+        }
+    }
+
+    /// Run, record or replay an execution, depending on the cassette mode
+    async fn exchange<F, Fut>(
+        &self,
+        action_name: &str,
+        request: CassetteRequest,
+        redactions: &Redactions,
+        live: F,
+    ) -> Result<CassetteResponse, PluginError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CassetteResponse, PluginError>>,
+    {
+        let mode = match self.global("cassette_mode").await {
+            Some(setting) => CassetteMode::for_provider(&setting, &self.definition.name)?,
+            None => CassetteMode::Off,
+        };
+        if mode == CassetteMode::Off {
+            return live().await;
+        }
+
+        let dir = self.global("cassette_dir").await
+            .map(|dir| PathBuf::from(value_to_text(&dir)))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CASSETTE_DIR));
+        self.cassettes.exchange(mode, &dir, action_name, request, redactions, live).await
+    }
+
+    /// A global argument, if it is set
+    async fn global(&self, name: &str) -> Option<Value> {
+        self.context.arguments()
+            .get_argument(&self.definition.name, name, None, ArgumentResolution::GlobalOnly)
+            .await
+            .ok()
+            .map(|argument| argument.value)
+    }
+
+    /// Reject arguments the action doesn't take, and settings a request may
+    /// not override
+    fn check_arguments(
+        &self,
+        action_name: &str,
+        action: &CpiActionDef,
+        arguments: &HashMap<String, Value>,
+        settings: &[String],
+    ) -> Result<(), PluginError> {
+        let mut names: Vec<&String> = arguments.keys().collect();
+        names.sort();
+        for name in names {
+            if is_flag_slot(name) {
+                return Err(PluginError::InvalidArgument(format!(
+                    "'{}' is filled in by CPI {} from its other parameters and can't be set by a request",
+                    name, self.definition.name
+                )));
+            }
+            if settings.contains(name) {
+                return Err(PluginError::InvalidArgument(format!(
+                    "'{}' is a setting of CPI {} and can't be set by a request", name, self.definition.name
+                )));
+            }
+            if !action.takes_argument(name) {
+                return Err(PluginError::InvalidArgument(format!(
+                    "{}/{} does not take an argument '{}'", self.definition.name, action_name, name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the values for the placeholders a target references
+    ///
+    /// Each placeholder must be declared in the action's `params` or provided
+    /// by `default_settings`. Values come from the request arguments first,
+    /// then the action's own defaults, then the argument manager (request,
+    /// global and provider default values). Placeholders in `settings` skip
+    /// the request and its arguments. Optional parameters without a value
+    /// resolve to `null` and render as empty text.
+    ///
+    /// Flag slots (`*_param`) are never taken from a request: their template
+    /// comes from the defaults or the argument manager and is expanded from
+    /// the parameters it references, which are added to the values too.
+    async fn resolve_params(
+        &self,
+        action_name: &str,
+        action: &CpiActionDef,
+        names: &[String],
+        arguments: &HashMap<String, Value>,
+        settings: &[String],
+        request_id: Uuid,
+    ) -> Result<HashMap<String, Value>, PluginError> {
+        let mut values = HashMap::new();
+
+        for name in names {
+            if !is_flag_slot(name) {
+                let value = self.resolve_param(action_name, action, name, arguments, settings, request_id).await?;
+                values.insert(name.clone(), value);
+                continue;
+            }
+
+            let template = self.setting(action, name, None).await.ok_or_else(|| PluginError::InvalidArgument(format!(
+                "Flag slot {{{}}} in {}/{} has no template in default_settings",
+                name, self.definition.name, action_name
+            )))?;
+            let template = value_to_text(&template);
+
+            let mut nested = HashMap::new();
+            for inner in placeholders(&template) {
+                if is_flag_slot(&inner) {
+                    continue;
+                }
+                let value = self.resolve_param(action_name, action, &inner, arguments, settings, request_id).await?;
+                nested.insert(inner, value);
+            }
+            let expanded = expand_slot(name, &template, &nested)?;
+
+            values.extend(nested);
+            values.insert(name.clone(), Value::String(expanded));
+        }
+
+        Ok(values)
+    }
+
+    /// Resolve the value of one declared parameter
+    async fn resolve_param(
+        &self,
+        action_name: &str,
+        action: &CpiActionDef,
+        name: &str,
+        arguments: &HashMap<String, Value>,
+        settings: &[String],
+        request_id: Uuid,
+    ) -> Result<Value, PluginError> {
+        let declared = action.param(name);
+        let has_default = action.default_settings.contains_key(name)
+            || self.definition.default_settings.contains_key(name);
+
+        if declared.is_none() && !has_default {
+            return Err(PluginError::InvalidArgument(format!(
+                "Placeholder {{{}}} in {}/{} is not declared in params",
+                name, self.definition.name, action_name
+            )));
+        }
+
+        let found = if settings.iter().any(|setting| setting == name) {
+            self.setting(action, name, None).await
+        } else {
+            self.lookup(action, name, arguments, request_id).await
+        };
+        match found {
+            Some(value) => Ok(value),
+            None if declared.map(|p| p.is_optional()).unwrap_or(false) => Ok(Value::Null),
+            None => Err(PluginError::InvalidArgument(format!(
+                "Missing required parameter '{}' for {}/{}",
+                name, self.definition.name, action_name
+            ))),
+        }
+    }
+
+    /// Resolve whichever of the given settings have a value, without
+    /// requiring them to be declared
+    async fn resolve_settings(
+        &self,
+        action: &CpiActionDef,
+        names: &[&str],
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+    ) -> HashMap<String, Value> {
+        let mut values = HashMap::new();
+        for name in names {
+            if let Some(value) = self.lookup(action, name, arguments, request_id).await {
+                values.insert(name.to_string(), value);
+            }
+        }
+        values
+    }
+
+    /// Find a value in the request arguments, the action's defaults or the
+    /// argument manager, in that order
+    async fn lookup(
+        &self,
+        action: &CpiActionDef,
+        name: &str,
+        arguments: &HashMap<String, Value>,
+        request_id: Uuid,
+    ) -> Option<Value> {
+        if let Some(value) = arguments.get(name) {
+            return Some(value.clone());
+        }
+        self.setting(action, name, Some(request_id)).await
+    }
+
+    /// Find a value in the action's defaults or the argument manager, with
+    /// request-scoped values only when a request id is given
+    async fn setting(&self, action: &CpiActionDef, name: &str, request_id: Option<Uuid>) -> Option<Value> {
+        if let Some(value) = action.default_settings.get(name) {
+            return Some(value.clone());
+        }
+
+        let request_id = request_id.map(|id| id.to_string());
+        self.context.arguments()
+            .get_argument(&self.definition.name, name, request_id.as_deref(), ArgumentResolution::UseDefault)
+            .await
+            .ok()
+            .map(|argument| argument.value)
+    }
+}
+
+/// Run `work` until the request is cancelled; dropping it kills a running
+/// command and aborts a running HTTP request
+async fn unless_cancelled<T>(
+    token: &CancellationToken,
+    work: impl Future<Output = Result<T, PluginError>>,
+) -> Result<T, PluginError> {
+    tokio::select! {
+        result = work => result,
+        _ = token.cancelled() => Err(PluginError::Cancelled("Action was stopped".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use serde_json::json;
+    use super::super::super::{ArgumentManager, EventSystem, FeatureRegistry, ServerContextBuilder};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancelled_commands_are_killed() {
+        let marker = std::env::temp_dir().join(format!("omni-cancelled-{}", Uuid::new_v4()));
+        let definition: CpiDefinition = serde_json::from_value(json!({
+            "name": "slow",
+            "type": "command",
+            "actions": {
+                "touch_later": {
+                    "target": { "Command": { "command": format!("sleep 1 && touch {}", marker.display()), "in_vm": false } },
+                    "parse_rules": { "type": "object" }
+                }
+            }
+        })).unwrap();
+        let context = ServerContextBuilder::new()
+            .with_event_system(Arc::new(EventSystem::new()))
+            .with_feature_registry(Arc::new(FeatureRegistry::new()))
+            .with_argument_manager(Arc::new(ArgumentManager::new()))
+            .build()
+            .unwrap();
+        let runner = CpiActionRunner::new(Arc::new(definition), context);
+
+        let token = CancellationToken::new();
+        let cancel = {
+            let token = token.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                token.cancel();
+            })
+        };
+        let result = runner.run("touch_later", &HashMap::new(), Uuid::new_v4(), &token).await;
+        cancel.await.unwrap();
+        assert!(matches!(result, Err(PluginError::Cancelled(_))), "{:?}", result);
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists(), "the command kept running after the cancel");
+    }
+}
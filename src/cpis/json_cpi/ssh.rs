@@ -0,0 +1,536 @@
+//! # SSH Execution
+//!
+//! Runs `in_vm` command targets inside a worker over SSH. Connection details
+//! come from the action arguments (or settings):
+//!
+//! - `ssh_host` (required), `ssh_port` (default 22), `ssh_user` (default "root")
+//! - `ssh_password`, or `ssh_key_path` with an optional `ssh_key_passphrase`;
+//!   with neither, the local SSH agent is used
+//! - `ssh_timeout_secs` (default 30), applied to connecting, to every
+//!   blocking write and to how long a command may stay silent
+//! - `ssh_known_hosts` (default `~/.ssh/known_hosts`), a setting only; the
+//!   host key must be listed there, unknown or changed keys are refused
+//!
+//! Sessions are pooled per `user@host:port` and credentials, so a request
+//! only reuses a session opened with the same password or key. A session
+//! runs one command at a time and goes back to the pool when the command
+//! finished cleanly; sessions idle for `SESSION_IDLE_TIMEOUT` are closed. A
+//! pooled session that can no longer open channels is replaced once before
+//! the command is run; commands are never re-run after they were sent.
+//!
+//! Commands report their process id first, so a cancelled command, and the
+//! processes it started, can be sent `SIGTERM` on the worker.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use ssh2::{Channel, CheckResult, KnownHostFileKind, Session};
+use super::render::value_to_text;
+use super::super::{CancellationToken, PluginError, SystemCommandResult};
+
+/// Arguments read to build an SSH connection
+pub const SSH_ARGUMENTS: &[&str] = &[
+    "ssh_host",
+    "ssh_port",
+    "ssh_user",
+    "ssh_password",
+    "ssh_key_path",
+    "ssh_key_passphrase",
+    "ssh_timeout_secs",
+];
+
+/// Setting naming the known_hosts file host keys are checked against
+pub const SSH_KNOWN_HOSTS_ARGUMENT: &str = "ssh_known_hosts";
+
+/// How long an unused session stays open
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Printed on stderr ahead of a command's process id
+const PID_MARKER: &str = "omni-pid:";
+
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_SSH_USER: &str = "root";
+const DEFAULT_SSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How to authenticate an SSH session
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum SshAuth {
+    Password(String),
+    Key { path: PathBuf, passphrase: Option<String> },
+    Agent,
+}
+
+impl fmt::Debug for SshAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SshAuth::Password(_) => write!(f, "Password(***)"),
+            SshAuth::Key { path, .. } => write!(f, "Key({})", path.display()),
+            SshAuth::Agent => write!(f, "Agent"),
+        }
+    }
+}
+
+/// Where and how to connect for an `in_vm` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshTarget {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub auth: SshAuth,
+    pub timeout: Duration,
+    /// File the host key has to be listed in
+    pub known_hosts: PathBuf,
+}
+
+impl SshTarget {
+    /// Build a target from resolved arguments
+    pub fn from_arguments(values: &HashMap<String, Value>) -> Result<Self, PluginError> {
+        let text = |name: &str| {
+            values.get(name)
+                .map(value_to_text)
+                .filter(|text| !text.is_empty())
+        };
+        let number = |name: &str| -> Result<Option<u64>, PluginError> {
+            text(name)
+                .map(|text| text.parse::<u64>().map_err(|_| {
+                    PluginError::InvalidArgument(format!("{} must be a whole number, got '{}'", name, text))
+                }))
+                .transpose()
+        };
+
+        let host = text("ssh_host").ok_or_else(|| {
+            PluginError::InvalidArgument("ssh_host is required for commands that run inside a worker".to_string())
+        })?;
+
+        let port = match number("ssh_port")? {
+            Some(port) => u16::try_from(port)
+                .map_err(|_| PluginError::InvalidArgument(format!("ssh_port {} is out of range", port)))?,
+            None => DEFAULT_SSH_PORT,
+        };
+
+        let auth = match (text("ssh_password"), text("ssh_key_path")) {
+            (_, Some(path)) => SshAuth::Key { path: PathBuf::from(path), passphrase: text("ssh_key_passphrase") },
+            (Some(password), None) => SshAuth::Password(password),
+            (None, None) => SshAuth::Agent,
+        };
+
+        Ok(Self {
+            host,
+            port,
+            user: text("ssh_user").unwrap_or_else(|| DEFAULT_SSH_USER.to_string()),
+            auth,
+            timeout: number("ssh_timeout_secs")?.map(Duration::from_secs).unwrap_or(DEFAULT_SSH_TIMEOUT),
+            known_hosts: text(SSH_KNOWN_HOSTS_ARGUMENT).map(PathBuf::from).unwrap_or_else(default_known_hosts),
+        })
+    }
+
+    /// Target in `user@host:port` form
+    pub fn key(&self) -> String {
+        format!("{}@{}:{}", self.user, self.host, self.port)
+    }
+
+    fn session_key(&self) -> SessionKey {
+        SessionKey { address: self.key(), auth: self.auth.clone() }
+    }
+}
+
+fn default_known_hosts() -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).unwrap_or_default();
+    PathBuf::from(home).join(".ssh").join("known_hosts")
+}
+
+/// Pooled sessions are only handed to targets with the same credentials
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SessionKey {
+    address: String,
+    auth: SshAuth,
+}
+
+struct IdleSession {
+    session: Session,
+    idle_since: Instant,
+}
+
+/// Runs commands over pooled SSH sessions
+#[derive(Clone, Default)]
+pub struct SshExecutor {
+    idle: Arc<Mutex<HashMap<SessionKey, Vec<IdleSession>>>>,
+}
+
+impl fmt::Debug for SshExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let idle = self.idle.lock().map(|idle| idle.values().map(Vec::len).sum()).unwrap_or(0);
+        f.debug_struct("SshExecutor").field("idle_sessions", &idle).finish()
+    }
+}
+
+impl SshExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a shell command on the target and capture its result
+    ///
+    /// Cancelling the token stops the command on the worker.
+    pub async fn execute(
+        &self,
+        target: &SshTarget,
+        command: &str,
+        token: &CancellationToken,
+    ) -> Result<SystemCommandResult, PluginError> {
+        let executor = self.clone();
+        let target = target.clone();
+        let command = command.to_string();
+        let token = token.clone();
+
+        tokio::task::spawn_blocking(move || executor.execute_blocking(&target, &command, &token))
+            .await
+            .map_err(|e| PluginError::ExecutionFailed(format!("SSH task failed: {}", e)))?
+    }
+
+    /// Run a command on a session of its own
+    ///
+    /// A session that failed along the way is dropped instead of going back
+    /// to the pool.
+    fn execute_blocking(
+        &self,
+        target: &SshTarget,
+        command: &str,
+        token: &CancellationToken,
+    ) -> Result<SystemCommandResult, PluginError> {
+        let start = Instant::now();
+
+        let (mut session, pooled) = self.checkout(target)?;
+        let mut channel = match session.channel_session() {
+            Ok(channel) => channel,
+            Err(_) if pooled => {
+                // The pooled session went stale; nothing has run yet, so reconnect
+                session = connect(target)?;
+                session.channel_session().map_err(|e| ssh_error(target, "open a channel", e))?
+            }
+            Err(e) => return Err(ssh_error(target, "open a channel", e)),
+        };
+
+        channel.exec(&report_pid(command)).map_err(|e| ssh_error(target, "start the command", e))?;
+        let (stdout, stderr) = read_output(&session, &channel, target, token)?;
+
+        channel.wait_close().map_err(|e| ssh_error(target, "close the channel", e))?;
+        let exit_code = channel.exit_status().map_err(|e| ssh_error(target, "read the exit status", e))?;
+        self.checkin(target, session);
+
+        Ok(SystemCommandResult {
+            exit_code,
+            stdout,
+            stderr,
+            execution_time_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Take an idle session with the target's credentials out of the pool,
+    /// or open a new one; the flag tells whether it was pooled
+    fn checkout(&self, target: &SshTarget) -> Result<(Session, bool), PluginError> {
+        let pooled = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|_, sessions| {
+                sessions.retain(|idle| idle.idle_since.elapsed() < SESSION_IDLE_TIMEOUT);
+                !sessions.is_empty()
+            });
+            idle.get_mut(&target.session_key()).and_then(Vec::pop)
+        };
+
+        match pooled {
+            Some(idle) => Ok((idle.session, true)),
+            None => Ok((connect(target)?, false)),
+        }
+    }
+
+    fn checkin(&self, target: &SshTarget, session: Session) {
+        let mut idle = self.idle.lock().unwrap();
+        idle.entry(target.session_key()).or_default().push(IdleSession { session, idle_since: Instant::now() });
+    }
+}
+
+/// Run a command through a shell that reports its process id first; `exec`
+/// keeps that id for the command itself
+fn report_pid(command: &str) -> String {
+    format!("echo {}$$ >&2; exec sh -c '{}'", PID_MARKER, command.replace('\'', "'\\''"))
+}
+
+/// Split the reported process id off the start of stderr
+fn take_pid(stderr: &[u8]) -> (Option<u32>, &[u8]) {
+    let Some(rest) = stderr.strip_prefix(PID_MARKER.as_bytes()) else {
+        return (None, stderr);
+    };
+    match rest.iter().position(|byte| *byte == b'\n') {
+        Some(end) => {
+            let pid = std::str::from_utf8(&rest[..end]).ok().and_then(|pid| pid.trim().parse().ok());
+            (pid, &rest[end + 1..])
+        }
+        None => (None, stderr),
+    }
+}
+
+/// Stdout and stderr of a command, and whether it was cancelled before it finished
+struct Output {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    cancelled: bool,
+}
+
+/// Read stdout and stderr together until the command closes them
+///
+/// Reading one stream to the end first would stall a command that fills
+/// the other one. Fails if the command stays silent for the target's timeout.
+/// A cancelled command is stopped on the worker.
+fn read_output(
+    session: &Session,
+    channel: &Channel,
+    target: &SshTarget,
+    token: &CancellationToken,
+) -> Result<(String, String), PluginError> {
+    session.set_blocking(false);
+    let output = drain(channel, target, token);
+    session.set_blocking(true);
+    let output = output?;
+
+    let (pid, stderr) = take_pid(&output.stderr);
+    if output.cancelled {
+        match pid {
+            Some(pid) => {
+                if let Err(e) = kill_remote(session, target, pid) {
+                    eprintln!("Failed to stop cancelled command {} on {}: {}", pid, target.key(), e);
+                }
+            }
+            None => eprintln!("Cancelled command on {} did not report its process id", target.key()),
+        }
+        return Err(PluginError::Cancelled(format!("Command on {} was stopped", target.key())));
+    }
+    Ok((String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(stderr).into_owned()))
+}
+
+fn drain(channel: &Channel, target: &SshTarget, token: &CancellationToken) -> Result<Output, PluginError> {
+    let mut output = Output { stdout: Vec::new(), stderr: Vec::new(), cancelled: false };
+    let mut buffer = [0u8; 8192];
+    let mut last_read = Instant::now();
+
+    loop {
+        if token.is_cancelled() {
+            output.cancelled = true;
+            return Ok(output);
+        }
+
+        let mut progressed = false;
+        for (stream_id, sink) in [(0, &mut output.stdout), (1, &mut output.stderr)] {
+            match channel.stream(stream_id).read(&mut buffer) {
+                Ok(0) => {}
+                Ok(read) => {
+                    sink.extend_from_slice(&buffer[..read]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(io_error(target, e)),
+            }
+        }
+
+        if progressed {
+            last_read = Instant::now();
+        } else if channel.eof() {
+            return Ok(output);
+        } else if last_read.elapsed() >= target.timeout {
+            return Err(io_error(target, ErrorKind::TimedOut.into()));
+        } else {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn connect(target: &SshTarget) -> Result<Session, PluginError> {
+    let addresses = (target.host.as_str(), target.port).to_socket_addrs()
+        .map_err(|e| io_error(target, e))?;
+
+    let mut last_error = None;
+    let mut stream = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, target.timeout) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let stream = match (stream, last_error) {
+        (Some(stream), _) => stream,
+        (None, Some(e)) => return Err(io_error(target, e)),
+        (None, None) => {
+            return Err(PluginError::ExecutionFailed(format!("SSH host {} did not resolve", target.host)));
+        }
+    };
+
+    let mut session = Session::new().map_err(|e| ssh_error(target, "create a session", e))?;
+    session.set_tcp_stream(stream);
+    session.set_timeout(target.timeout.as_millis().min(u32::MAX as u128) as u32);
+    session.handshake().map_err(|e| ssh_error(target, "complete the handshake", e))?;
+    verify_host_key(&session, target)?;
+
+    let auth = match &target.auth {
+        SshAuth::Password(password) => session.userauth_password(&target.user, password),
+        SshAuth::Key { path, passphrase } => {
+            session.userauth_pubkey_file(&target.user, None, path, passphrase.as_deref())
+        }
+        SshAuth::Agent => session.userauth_agent(&target.user),
+    };
+    auth.map_err(|e| ssh_error(target, "authenticate", e))?;
+
+    if !session.authenticated() {
+        return Err(PluginError::ExecutionFailed(format!("SSH authentication to {} was rejected", target.key())));
+    }
+
+    Ok(session)
+}
+
+/// Send `SIGTERM` to a command and to the processes it started
+fn kill_remote(session: &Session, target: &SshTarget, pid: u32) -> Result<(), PluginError> {
+    let mut channel = session.channel_session().map_err(|e| ssh_error(target, "open a channel", e))?;
+    channel.exec(&format!("pkill -TERM -P {pid}; kill -TERM {pid}", pid = pid))
+        .map_err(|e| ssh_error(target, "stop the command", e))?;
+    channel.wait_close().map_err(|e| ssh_error(target, "close the channel", e))
+}
+
+/// Refuse hosts whose key isn't listed in the known_hosts file, or differs
+fn verify_host_key(session: &Session, target: &SshTarget) -> Result<(), PluginError> {
+    let (key, _) = session.host_key()
+        .ok_or_else(|| PluginError::ExecutionFailed(format!("{} sent no host key", target.key())))?;
+    let mut known_hosts = session.known_hosts().map_err(|e| ssh_error(target, "load known hosts", e))?;
+    known_hosts.read_file(&target.known_hosts, KnownHostFileKind::OpenSSH).map_err(|e| {
+        PluginError::ExecutionFailed(format!("Could not read known hosts from {}: {}", target.known_hosts.display(), e))
+    })?;
+
+    let problem = match known_hosts.check_port(&target.host, target.port, key) {
+        CheckResult::Match => return Ok(()),
+        CheckResult::NotFound => "is not listed in",
+        CheckResult::Mismatch => "does not match the one in",
+        CheckResult::Failure => "could not be checked against",
+    };
+    Err(PluginError::ExecutionFailed(format!(
+        "Host key of {}:{} {} {}, refusing to connect", target.host, target.port, problem, target.known_hosts.display()
+    )))
+}
+
+fn ssh_error(target: &SshTarget, step: &str, error: ssh2::Error) -> PluginError {
+    // libssh2 reports blocking timeouts as LIBSSH2_ERROR_TIMEOUT (-9)
+    if matches!(error.code(), ssh2::ErrorCode::Session(-9)) {
+        return PluginError::ExecutionFailed(format!(
+            "SSH timeout after {}s while trying to {} on {}", target.timeout.as_secs(), step, target.key()
+        ));
+    }
+    PluginError::ExecutionFailed(format!("SSH failed to {} on {}: {}", step, target.key(), error))
+}
+
+fn io_error(target: &SshTarget, error: std::io::Error) -> PluginError {
+    if matches!(error.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock) {
+        return PluginError::ExecutionFailed(format!(
+            "SSH timeout after {}s talking to {}", target.timeout.as_secs(), target.key()
+        ));
+    }
+    PluginError::ExecutionFailed(format!("SSH connection to {} failed: {}", target.key(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn values(pairs: &[(&str, Value)]) -> HashMap<String, Value> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn targets_use_defaults() {
+        let target = SshTarget::from_arguments(&values(&[("ssh_host", json!("10.0.0.5"))])).unwrap();
+        assert_eq!(target.key(), "root@10.0.0.5:22");
+        assert_eq!(target.auth, SshAuth::Agent);
+        assert_eq!(target.timeout, DEFAULT_SSH_TIMEOUT);
+    }
+
+    #[test]
+    fn key_auth_wins_over_password() {
+        let target = SshTarget::from_arguments(&values(&[
+            ("ssh_host", json!("worker-1")),
+            ("ssh_port", json!("2222")),
+            ("ssh_user", json!("omni")),
+            ("ssh_password", json!("secret")),
+            ("ssh_key_path", json!("/keys/id_ed25519")),
+            ("ssh_timeout_secs", json!(5)),
+        ])).unwrap();
+
+        assert_eq!(target.key(), "omni@worker-1:2222");
+        assert_eq!(target.auth, SshAuth::Key { path: PathBuf::from("/keys/id_ed25519"), passphrase: None });
+        assert_eq!(target.timeout, Duration::from_secs(5));
+        assert!(!format!("{:?}", SshAuth::Password("secret".into())).contains("secret"));
+    }
+
+    #[test]
+    fn sessions_are_only_shared_with_the_same_credentials() {
+        let target = |password: Option<&str>| {
+            let mut pairs = vec![("ssh_host", json!("worker-1"))];
+            if let Some(password) = password {
+                pairs.push(("ssh_password", json!(password)));
+            }
+            SshTarget::from_arguments(&values(&pairs)).unwrap()
+        };
+
+        assert!(target(Some("right")).session_key() == target(Some("right")).session_key());
+        assert!(target(Some("right")).session_key() != target(Some("wrong")).session_key());
+        assert!(target(Some("right")).session_key() != target(None).session_key());
+    }
+
+    #[test]
+    fn commands_report_their_process_id() {
+        assert_eq!(report_pid("echo 'hi'"), "echo omni-pid:$$ >&2; exec sh -c 'echo '\\''hi'\\'''");
+        assert_eq!(take_pid(b"omni-pid:4242\nwarning\n"), (Some(4242), &b"warning\n"[..]));
+        assert_eq!(take_pid(b"plain error"), (None, &b"plain error"[..]));
+    }
+
+    #[test]
+    fn known_hosts_can_be_set() {
+        let target = SshTarget::from_arguments(&values(&[
+            ("ssh_host", json!("worker-1")),
+            ("ssh_known_hosts", json!("/etc/omni/known_hosts")),
+        ])).unwrap();
+        assert_eq!(target.known_hosts, PathBuf::from("/etc/omni/known_hosts"));
+        assert!(default_known_hosts().ends_with(".ssh/known_hosts"));
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        assert!(SshTarget::from_arguments(&HashMap::new()).is_err());
+        assert!(SshTarget::from_arguments(&values(&[("ssh_host", json!("h")), ("ssh_port", json!(70000))])).is_err());
+        assert!(SshTarget::from_arguments(&values(&[("ssh_host", json!("h")), ("ssh_port", json!("ssh"))])).is_err());
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_reported() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let target = SshTarget::from_arguments(&values(&[
+            ("ssh_host", json!("127.0.0.1")),
+            ("ssh_port", json!(port)),
+            ("ssh_timeout_secs", json!(2)),
+        ])).unwrap();
+
+        match SshExecutor::new().execute(&target, "true", &CancellationToken::new()).await {
+            Err(PluginError::ExecutionFailed(message)) => {
+                assert!(message.starts_with(&format!("SSH connection to root@127.0.0.1:{} failed", port)), "{}", message);
+            }
+            other => panic!("expected a connection failure, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! # Action Limits
+//!
+//! Bounds how many actions run at once and how fast they start, so providers
+//! like Hetzner or Linode stay within their API rate limits. Requests over a
+//! limit wait in a queue instead of failing.
+//!
+//! Limits are set per provider, or per feature of a provider, through the
+//! `action_limits` global argument (e.g. `OMNI_action_limits`):
+//!
+//! ```text
+//! OMNI_action_limits={"hetzner": {"max_concurrent": 4, "requests_per_second": 2, "burst": 5},
+//!                     "hetzner/VM_Manage": {"max_concurrent": 1}}
+//! ```
+//!
+//! A request has to fit within both the provider's and the feature's limits.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use super::PluginError;
+
+/// Global argument holding the limits
+pub const ACTION_LIMITS_ARGUMENT: &str = "action_limits";
+
+/// Limits for one provider or provider feature
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActionLimit {
+    /// Actions allowed to run at the same time
+    pub max_concurrent: Option<usize>,
+    /// Sustained rate at which actions may start
+    pub requests_per_second: Option<f64>,
+    /// Actions that may start at once before the rate applies, at least 1
+    pub burst: Option<u32>,
+}
+
+/// Queue statistics of one provider
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProviderQueueStats {
+    /// Requests currently waiting for a limit
+    pub queue_depth: usize,
+    /// Requests that passed the limits so far
+    pub admitted: u64,
+    pub average_wait_ms: u64,
+    pub max_wait_ms: u64,
+    #[serde(skip)]
+    total_wait: Duration,
+}
+
+/// Applies the configured limits to action attempts
+#[derive(Debug, Default)]
+pub struct ActionLimiter {
+    /// Limit state by scope, `<provider>` or `<provider>/<feature>`
+    scopes: RwLock<HashMap<String, Arc<ScopeLimiter>>>,
+    queues: Mutex<HashMap<String, ProviderQueueStats>>,
+}
+
+impl ActionLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the limits of a scope, replacing any earlier ones
+    pub fn set_limit(&self, scope: &str, limit: ActionLimit) {
+        let mut scopes = self.scopes.write().unwrap();
+        scopes.insert(scope.to_string(), Arc::new(ScopeLimiter::new(&limit)));
+    }
+
+    /// Load the limits from the `action_limits` setting
+    pub fn load(&self, setting: &Value) -> Result<usize, PluginError> {
+        let limits: HashMap<String, ActionLimit> = serde_json::from_value(setting.clone())
+            .map_err(|e| PluginError::InvalidArgument(format!(
+                "{} must map providers to limits: {}", ACTION_LIMITS_ARGUMENT, e
+            )))?;
+
+        let count = limits.len();
+        for (scope, limit) in limits {
+            self.set_limit(&scope, limit);
+        }
+        Ok(count)
+    }
+
+    /// Wait until a provider's feature action may run
+    ///
+    /// The returned permit holds the concurrency slots until it is dropped.
+    pub async fn acquire(&self, provider: &str, feature: &str) -> LimitPermit {
+        let limiters: Vec<Arc<ScopeLimiter>> = {
+            let scopes = self.scopes.read().unwrap();
+            [provider.to_string(), format!("{}/{}", provider, feature)]
+                .iter()
+                .filter_map(|scope| scopes.get(scope).cloned())
+                .collect()
+        };
+
+        let start = Instant::now();
+        let queued = QueuedRequest::new(self, provider);
+        let mut permits = Vec::new();
+        for limiter in limiters {
+            if let Some(semaphore) = &limiter.semaphore {
+                // The semaphore is never closed
+                permits.push(Arc::clone(semaphore).acquire_owned().await.unwrap());
+            }
+            if let Some(bucket) = &limiter.bucket {
+                let delay = bucket.lock().unwrap().reserve();
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+        queued.admit(start.elapsed());
+
+        LimitPermit { _permits: permits }
+    }
+
+    /// Queue statistics by provider
+    pub fn queue_stats(&self) -> HashMap<String, ProviderQueueStats> {
+        self.queues.lock().unwrap().clone()
+    }
+}
+
+/// Concurrency slots held by a running action
+#[derive(Debug)]
+pub struct LimitPermit {
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+#[derive(Debug)]
+struct ScopeLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    bucket: Option<Mutex<TokenBucket>>,
+}
+
+impl ScopeLimiter {
+    fn new(limit: &ActionLimit) -> Self {
+        Self {
+            semaphore: limit.max_concurrent.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            bucket: limit.requests_per_second
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Mutex::new(TokenBucket::new(rate, limit.burst.unwrap_or(1).max(1)))),
+        }
+    }
+}
+
+/// Token bucket handing out start times at a fixed rate
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    /// Goes negative while requests are waiting for tokens
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: u32) -> Self {
+        Self { rate, capacity: burst as f64, tokens: burst as f64, updated: Instant::now() }
+    }
+
+    /// Take a token, returning how long to wait until it is available
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.updated = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Counts a request in its provider's queue until it is admitted or dropped
+struct QueuedRequest<'a> {
+    limiter: &'a ActionLimiter,
+    provider: &'a str,
+    admitted: bool,
+}
+
+impl<'a> QueuedRequest<'a> {
+    fn new(limiter: &'a ActionLimiter, provider: &'a str) -> Self {
+        let mut queues = limiter.queues.lock().unwrap();
+        queues.entry(provider.to_string()).or_default().queue_depth += 1;
+        Self { limiter, provider, admitted: false }
+    }
+
+    fn admit(mut self, wait: Duration) {
+        self.admitted = true;
+        let mut queues = self.limiter.queues.lock().unwrap();
+        let stats = queues.entry(self.provider.to_string()).or_default();
+        stats.queue_depth -= 1;
+        stats.admitted += 1;
+        stats.total_wait += wait;
+        stats.average_wait_ms = (stats.total_wait / stats.admitted as u32).as_millis() as u64;
+        stats.max_wait_ms = stats.max_wait_ms.max(wait.as_millis() as u64);
+    }
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        // The request was dropped while waiting
+        if !self.admitted {
+            let mut queues = self.limiter.queues.lock().unwrap();
+            if let Some(stats) = queues.get_mut(self.provider) {
+                stats.queue_depth -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn requests_over_the_cap_queue() {
+        let limiter = Arc::new(ActionLimiter::new());
+        limiter.load(&json!({ "hetzner/VM_Manage": { "max_concurrent": 1 } })).unwrap();
+
+        let first = limiter.acquire("hetzner", "VM_Manage").await;
+        let waiting = {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move { limiter.acquire("hetzner", "VM_Manage").await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(limiter.queue_stats()["hetzner"].queue_depth, 1);
+
+        // Other features of the provider aren't capped
+        let _other = limiter.acquire("hetzner", "Worker_Manage").await;
+
+        drop(first);
+        let _second = waiting.await.unwrap();
+        let stats = &limiter.queue_stats()["hetzner"];
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.admitted, 3);
+        assert!(stats.max_wait_ms >= 40, "{:?}", stats);
+    }
+
+    #[test]
+    fn token_buckets_spread_requests_out() {
+        let mut bucket = TokenBucket::new(10.0, 2);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+
+        let third = bucket.reserve();
+        let fourth = bucket.reserve();
+        assert!(third > Duration::from_millis(80) && third <= Duration::from_millis(100), "{:?}", third);
+        assert!(fourth > Duration::from_millis(180) && fourth <= Duration::from_millis(200), "{:?}", fourth);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(ActionLimiter::new().load(&json!({ "hetzner": { "max_concurrent": "four" } })).is_err());
+    }
+}
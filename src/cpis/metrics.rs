@@ -0,0 +1,242 @@
+//! # Metrics
+//!
+//! Counts and latencies of the actions the executor ran, and the Prometheus
+//! text exposition of everything the system keeps statistics about, served
+//! on `/metrics`:
+//!
+//! - `omni_actions_total` and `omni_action_duration_seconds`, labelled by
+//!   provider, feature, action and outcome
+//! - `omni_events_emitted_total` and `omni_event_handler_failures_total` per
+//!   event key
+//! - `omni_plugin_state`, 1 for the state each plugin is in
+//! - `omni_arguments` per scope
+//!
+//! Retries belong to the action they retry, so an action counts once with the
+//! outcome of its last attempt and the time all attempts took.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+use super::history::ExecutionStatus;
+use super::{ArgumentStats, EventSystemStats, PluginState};
+
+/// Upper bounds of the action latency buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 12] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Labels an action series is kept under
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ActionLabels {
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    pub outcome: &'static str,
+}
+
+/// Count and latencies of one action series
+#[derive(Debug, Clone, Default)]
+pub struct ActionSeries {
+    pub count: u64,
+    pub total_seconds: f64,
+    /// Actions per bucket of `LATENCY_BUCKETS`, not cumulative
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+impl ActionSeries {
+    fn observe(&mut self, seconds: f64) {
+        self.count += 1;
+        self.total_seconds += seconds;
+        // Slower than the last bound only counts towards +Inf
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+    }
+}
+
+/// Action series of every action that ran
+#[derive(Debug, Default)]
+pub struct ActionMetrics {
+    series: Mutex<HashMap<ActionLabels, ActionSeries>>,
+}
+
+impl ActionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a finished action
+    pub fn record(&self, provider: &str, feature: &str, action: &str, status: ExecutionStatus, duration: Duration) {
+        let labels = ActionLabels {
+            provider: provider.to_string(),
+            feature: feature.to_string(),
+            action: action.to_string(),
+            outcome: status.as_str(),
+        };
+        let mut series = self.series.lock().unwrap();
+        series.entry(labels).or_default().observe(duration.as_secs_f64());
+    }
+
+    /// Every series, ordered by its labels
+    pub fn snapshot(&self) -> BTreeMap<ActionLabels, ActionSeries> {
+        let series = self.series.lock().unwrap();
+        series.iter().map(|(labels, series)| (labels.clone(), series.clone())).collect()
+    }
+}
+
+/// Everything `/metrics` reports, gathered from the parts of the system
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub actions: BTreeMap<ActionLabels, ActionSeries>,
+    pub events: EventSystemStats,
+    pub plugins: HashMap<String, PluginState>,
+    pub arguments: ArgumentStats,
+}
+
+impl MetricsSnapshot {
+    /// Render in the Prometheus text format, version 0.0.4
+    pub fn render(&self) -> String {
+        let mut out = Exposition::default();
+
+        out.family("omni_actions_total", "counter", "Actions executed, by outcome");
+        for (labels, series) in &self.actions {
+            out.sample("omni_actions_total", &action_labels(labels), series.count);
+        }
+
+        out.family("omni_action_duration_seconds", "histogram", "Time actions took, retries included");
+        for (labels, series) in &self.actions {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(series.buckets) {
+                cumulative += count;
+                let bound = bound.to_string();
+                let mut bucket_labels = action_labels(labels);
+                bucket_labels.push(("le", &bound));
+                out.sample("omni_action_duration_seconds_bucket", &bucket_labels, cumulative);
+            }
+            let mut bucket_labels = action_labels(labels);
+            bucket_labels.push(("le", "+Inf"));
+            out.sample("omni_action_duration_seconds_bucket", &bucket_labels, series.count);
+            out.sample("omni_action_duration_seconds_sum", &action_labels(labels), series.total_seconds);
+            out.sample("omni_action_duration_seconds_count", &action_labels(labels), series.count);
+        }
+
+        let mut event_keys: Vec<_> = self.events.by_key.iter().collect();
+        event_keys.sort_by(|a, b| a.0.cmp(b.0));
+        out.family("omni_events_emitted_total", "counter", "Events emitted, by event key");
+        for (key, stats) in &event_keys {
+            out.sample("omni_events_emitted_total", &[("event_key", key.as_str())], stats.emitted);
+        }
+        out.family("omni_event_handler_failures_total", "counter", "Event handlers that returned an error, by event key");
+        for (key, stats) in &event_keys {
+            out.sample("omni_event_handler_failures_total", &[("event_key", key.as_str())], stats.handler_failures);
+        }
+        out.family("omni_event_handlers", "gauge", "Registered event handlers");
+        out.sample("omni_event_handlers", &[], self.events.total_handlers);
+
+        let mut plugins: Vec<_> = self.plugins.iter().collect();
+        plugins.sort_by(|a, b| a.0.cmp(b.0));
+        out.family("omni_plugin_state", "gauge", "1 for the lifecycle state a plugin is in, 0 for the others");
+        for (plugin, state) in plugins {
+            for name in PluginState::NAMES {
+                let value = if state.name() == name { 1 } else { 0 };
+                out.sample("omni_plugin_state", &[("plugin", plugin.as_str()), ("state", name)], value);
+            }
+        }
+
+        out.family("omni_arguments", "gauge", "Stored arguments, by scope");
+        out.sample("omni_arguments", &[("scope", "global")], self.arguments.global_arguments);
+        out.sample("omni_arguments", &[("scope", "plugin")], self.arguments.plugin_arguments);
+        out.sample("omni_arguments", &[("scope", "request")], self.arguments.request_arguments);
+        out.family("omni_sensitive_arguments", "gauge", "Global arguments holding secrets");
+        out.sample("omni_sensitive_arguments", &[], self.arguments.sensitive_arguments);
+
+        out.text
+    }
+}
+
+fn action_labels(labels: &ActionLabels) -> Vec<(&str, &str)> {
+    vec![
+        ("provider", labels.provider.as_str()),
+        ("feature", labels.feature.as_str()),
+        ("action", labels.action.as_str()),
+        ("outcome", labels.outcome),
+    ]
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpis::EventKeyStats;
+
+    #[test]
+    fn snapshots_render_as_prometheus_text() {
+        let metrics = ActionMetrics::new();
+        metrics.record("hetzner", "VM_Manage", "create_vm", ExecutionStatus::Succeeded, Duration::from_millis(300));
+        metrics.record("hetzner", "VM_Manage", "create_vm", ExecutionStatus::Succeeded, Duration::from_secs(200));
+        metrics.record("hetzner", "VM_Manage", "create_vm", ExecutionStatus::TimedOut, Duration::from_secs(30));
+
+        let snapshot = MetricsSnapshot {
+            actions: metrics.snapshot(),
+            events: EventSystemStats {
+                total_handlers: 2,
+                events_emitted: 3,
+                by_key: HashMap::from([
+                    ("feature:action:complete".to_string(), EventKeyStats { emitted: 3, handler_failures: 1 }),
+                ]),
+            },
+            plugins: HashMap::from([("hetzner".to_string(), PluginState::Failed("no token".to_string()))]),
+            arguments: ArgumentStats { global_arguments: 4, plugin_arguments: 1, request_arguments: 0, sensitive_arguments: 2 },
+        };
+        let text = snapshot.render();
+        let lines: Vec<&str> = text.lines().collect();
+
+        let succeeded = r#"provider="hetzner",feature="VM_Manage",action="create_vm",outcome="succeeded""#;
+        for expected in [
+            format!("omni_actions_total{{{}}} 2", succeeded),
+            format!("omni_action_duration_seconds_bucket{{{},le=\"0.25\"}} 0", succeeded),
+            format!("omni_action_duration_seconds_bucket{{{},le=\"0.5\"}} 1", succeeded),
+            format!("omni_action_duration_seconds_bucket{{{},le=\"120\"}} 1", succeeded),
+            format!("omni_action_duration_seconds_bucket{{{},le=\"+Inf\"}} 2", succeeded),
+            format!("omni_action_duration_seconds_sum{{{}}} 200.3", succeeded),
+            "omni_actions_total{provider=\"hetzner\",feature=\"VM_Manage\",action=\"create_vm\",outcome=\"timed_out\"} 1".to_string(),
+            "omni_event_handler_failures_total{event_key=\"feature:action:complete\"} 1".to_string(),
+            "omni_plugin_state{plugin=\"hetzner\",state=\"failed\"} 1".to_string(),
+            "omni_plugin_state{plugin=\"hetzner\",state=\"running\"} 0".to_string(),
+            "omni_arguments{scope=\"global\"} 4".to_string(),
+            "# TYPE omni_action_duration_seconds histogram".to_string(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "missing {} in\n{}", expected, text);
+        }
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("a \"b\"\\c\nd"), "a \\\"b\\\"\\\\c\\nd");
+    }
+}
@@ -25,7 +25,23 @@ pub mod plugin;
 pub mod registry;
 pub mod context;
 pub mod arguments;
+pub mod breaker;
+pub mod cancellation;
+pub mod cron;
 pub mod executor;
+pub mod history;
+pub mod idempotency;
+pub mod jobs;
+pub mod json_cpi;
+pub mod limits;
+pub mod metrics;
+pub mod plan;
+pub mod progress;
+pub mod retry;
+pub mod rollback;
+pub mod schedules;
+pub mod validation;
+pub mod workflow;
 
 pub use events::*;
 pub use features::*;
@@ -34,7 +50,21 @@ pub use plugin::Plugin; // Bring the Plugin trait into scope for method resoluti
 pub use registry::*;
 pub use context::*;
 pub use arguments::*;
+pub use breaker::{BreakerConfig, CircuitState, CircuitStats};
+pub use cancellation::{CancellationStatus, CancellationToken, CancellationTokens};
 pub use executor::*;
+pub use history::{ExecutionHistory, ExecutionPage, ExecutionQuery, ExecutionRecord, ExecutionStatus};
+pub use jobs::{Job, JobManager, JobRequest, JobStatus};
+pub use limits::{ActionLimit, ProviderQueueStats};
+pub use metrics::{ActionLabels, ActionSeries, MetricsSnapshot};
+pub use plan::{ActionPlan, PlannedArgument};
+pub use progress::{ActionProgress, ProgressSubscription, ProgressUpdate, PROGRESS_EVENT_KEY};
+pub use retry::{ErrorClass, RetryPolicy};
+pub use rollback::{Compensation, CompensationResult, CompensationStatus};
+pub use schedules::{Schedule, ScheduleDefinition, ScheduleManager, ScheduleRun};
+pub use validation::{PluginViolations, ResultViolation, ValidationMode};
+pub use workflow::{StepResult, StepStatus, Workflow, WorkflowResult, WorkflowStep};
+pub use json_cpi::CpiHost;
 
 /// Main plugin system that manages events, plugins, and features
 #[derive(Debug)]
@@ -47,6 +77,8 @@ pub struct PluginSystem {
     pub feature_registry: Arc<FeatureRegistry>,
     /// Argument manager for handling dynamic parameters
     pub argument_manager: Arc<ArgumentManager>,
+    /// Host for the declarative JSON CPI providers
+    pub cpi_host: Arc<CpiHost>,
     /// Server context for plugin operations
     server_context: Arc<dyn ServerContext>,
 }
@@ -54,17 +86,20 @@ pub struct PluginSystem {
 impl PluginSystem {
     /// Create a new plugin system instance
     pub fn new(server_context: Arc<dyn ServerContext>) -> Self {
-        // Use the event system from the provided server_context, not a new one
+        // Share the context's services, so plugins see the same features and
+        // settings as the executor
         let event_system = server_context.events();
         let plugin_registry = Arc::new(PluginRegistry::new());
-        let feature_registry = Arc::new(FeatureRegistry::new());
-        let argument_manager = Arc::new(ArgumentManager::new());
+        let feature_registry = server_context.features();
+        let argument_manager = server_context.arguments();
+        let cpi_host = Arc::new(CpiHost::new());
 
         Self {
             event_system,
             plugin_registry,
             feature_registry,
             argument_manager,
+            cpi_host,
             server_context,
         }
     }
@@ -81,6 +116,16 @@ impl PluginSystem {
             Arc::clone(&self.server_context),
         ).await?;
 
+        // Load the feature action mappings before the CPIs that resolve them
+        self.cpi_host.load_mappings("./features/mappings").await?;
+
+        // Load the declarative JSON CPIs as in-process plugins
+        self.cpi_host.load_definitions(
+            "./CPIs",
+            &self.plugin_registry,
+            Arc::clone(&self.server_context),
+        ).await?;
+
         // No need to call initialize_plugin again, as plugins are now initialized at load time
         Ok(())
     }
@@ -97,15 +142,17 @@ impl PluginSystem {
         self.plugin_registry.initialize_plugin(plugin_name, Arc::clone(&self.server_context)).await
     }
 
-    /// Execute a feature action through the event system
+    /// Execute a provider's feature action through the event system
     pub async fn execute_feature_action(
         &self,
+        provider: &str,
         feature: &str,
         action: &str,
         args: HashMap<String, Value>,
     ) -> Result<Value, PluginError> {
-        let event_key = format!("feature:{}:{}", feature, action);
+        let event_key = self.event_system.feature_action_key(provider, feature, action).await;
         let event = FeatureActionEvent {
+            provider: provider.to_string(),
             feature: feature.to_string(),
             action: action.to_string(),
             arguments: args,
@@ -135,6 +182,11 @@ impl PluginSystem {
         self.feature_registry.get_action_arguments(feature, action).await
     }
 
+    /// The context plugins and system services share
+    pub fn server_context(&self) -> Arc<dyn ServerContext> {
+        Arc::clone(&self.server_context)
+    }
+
     /// Shutdown the plugin system gracefully
     pub async fn shutdown(&self) -> Result<(), PluginError> {
         // Shutdown all plugins using the registry's shutdown_all, which handles Arc mutability correctly
@@ -160,9 +212,30 @@ pub enum PluginError {
     #[error("Plugin execution failed: {0}")]
     ExecutionFailed(String),
     
+    #[error("Plugin execution timed out: {0}")]
+    Timeout(String),
+    
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    
+    #[error("Not found: {0}")]
+    NotFound(String),
+    
+    #[error("Request cancelled: {0}")]
+    Cancelled(String),
+    
+    #[error("Circuit open: {0}")]
+    CircuitOpen(String),
+    
     #[error("Invalid argument: {0}")]
     InvalidArgument(String),
     
+    #[error("Invalid result: {0}")]
+    InvalidResult(String),
+    
+    #[error("HTTP request failed with status {status}: {message}")]
+    HttpStatus { status: u16, message: String },
+    
     #[error("I/O error: {0}")]
     IoError(Arc<std::io::Error>),
     
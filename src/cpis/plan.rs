@@ -0,0 +1,76 @@
+//! # Dry Runs
+//!
+//! A dry run goes through the same checks as a real execution, then stops
+//! short of emitting the `FeatureActionEvent`. What comes back is a plan: the
+//! arguments the action would get and where each value came from, and the
+//! command line or HTTP request the provider would run, as far as the plugin
+//! can tell without running it.
+//!
+//! Sensitive values never appear in a plan.
+
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+use super::json_cpi::cassette::is_sensitive_name;
+use super::{ArgumentSource, ArgumentValue};
+
+/// Shown in place of sensitive argument values
+const MASKED: &str = "[redacted]";
+
+/// What executing an action would do
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionPlan {
+    pub request_id: Uuid,
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    pub is_mutating: bool,
+    pub estimated_duration_ms: Option<u64>,
+    /// Arguments of the action as resolved by the argument manager
+    pub arguments: HashMap<String, PlannedArgument>,
+    /// Command line or HTTP request the provider would run, with secrets
+    /// masked; `None` when the plugin can't show it
+    pub rendered: Option<Value>,
+}
+
+/// A resolved argument and where its value came from
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedArgument {
+    pub value: Value,
+    pub source: ArgumentSource,
+    pub sensitive: bool,
+}
+
+impl PlannedArgument {
+    pub fn new(name: &str, argument: ArgumentValue) -> Self {
+        let sensitive = argument.is_sensitive || is_sensitive_name(name);
+        let value = if sensitive { Value::String(MASKED.to_string()) } else { argument.value };
+        Self { value, source: argument.source, sensitive }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn argument(value: Value, source: ArgumentSource, is_sensitive: bool) -> ArgumentValue {
+        ArgumentValue { value, timestamp: chrono::Utc::now(), source, is_sensitive }
+    }
+
+    #[test]
+    fn sensitive_values_are_masked() {
+        let name = PlannedArgument::new("name", argument(json!("web-1"), ArgumentSource::UserInput, false));
+        assert_eq!(name.value, json!("web-1"));
+        assert!(!name.sensitive);
+
+        let token = PlannedArgument::new("api_token", argument(json!("abcd1234"), ArgumentSource::Global, false));
+        assert_eq!(token.value, json!(MASKED));
+        assert_eq!(token.source, ArgumentSource::Global);
+
+        let flagged = PlannedArgument::new("region", argument(json!("fsn1"), ArgumentSource::Default, true));
+        assert_eq!(flagged.value, json!(MASKED));
+        assert!(flagged.sensitive);
+    }
+}
@@ -3,8 +3,11 @@
 //! Defines the core plugin trait and lifecycle management.
 //! Plugins implement this trait to register event handlers and declare features.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use async_trait::async_trait;
+use serde_json::Value;
+use uuid::Uuid;
 use super::{PluginError, ServerContext};
 
 /// Core plugin trait that all plugins must implement
@@ -29,6 +32,21 @@ pub trait Plugin: Send + Sync {
     
     /// Shutdown phase: cleanup resources
     async fn shutdown(&mut self, context: Arc<dyn ServerContext>) -> Result<(), PluginError>;
+
+    /// Describe what an action would run, without running it
+    ///
+    /// Used for dry runs. Plugins that can render the command line or HTTP
+    /// request of an action return it with sensitive values masked; the
+    /// default has nothing to show.
+    async fn plan_action(
+        &self,
+        _feature: &str,
+        _action: &str,
+        _arguments: &HashMap<String, Value>,
+        _request_id: Uuid,
+    ) -> Result<Option<Value>, PluginError> {
+        Ok(None)
+    }
 }
 
 /// Plugin factory function type for dynamic loading
@@ -230,6 +248,27 @@ pub enum PluginState {
     Failed(String),
 }
 
+impl PluginState {
+    /// Names of every state, as `name` returns them
+    pub const NAMES: [&'static str; 8] = [
+        "unloaded", "loading", "pre_initialized", "initialized", "running", "stopping", "stopped", "failed",
+    ];
+
+    /// Name of the state without its details
+    pub fn name(&self) -> &'static str {
+        match self {
+            PluginState::Unloaded => "unloaded",
+            PluginState::Loading => "loading",
+            PluginState::PreInitialized => "pre_initialized",
+            PluginState::Initialized => "initialized",
+            PluginState::Running => "running",
+            PluginState::Stopping => "stopping",
+            PluginState::Stopped => "stopped",
+            PluginState::Failed(_) => "failed",
+        }
+    }
+}
+
 /// Plugin instance wrapper for state management
 pub struct PluginInstance {
     plugin: Box<dyn Plugin>,
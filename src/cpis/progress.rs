@@ -0,0 +1,184 @@
+//! # Action Progress
+//!
+//! Plugins report how far a running action got by emitting a
+//! `FeatureActionProgressEvent` on `PROGRESS_EVENT_KEY`. The executor keeps
+//! the updates of every request it runs, so clients can replay them and
+//! follow new ones until the request finishes.
+//!
+//! Updates for requests the executor isn't running are dropped.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use super::FeatureActionProgressEvent;
+
+/// Event key plugins report progress on
+pub const PROGRESS_EVENT_KEY: &str = "feature:action:progress";
+
+/// Updates a slow subscriber may fall behind by before it skips some
+const SUBSCRIBER_BUFFER: usize = 64;
+
+/// A progress update as received by the executor
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionProgress {
+    #[serde(flatten)]
+    pub event: FeatureActionProgressEvent,
+    pub received_at: DateTime<Utc>,
+}
+
+/// What a subscriber receives after the replayed history
+#[derive(Debug, Clone)]
+pub enum ProgressUpdate {
+    Progress(ActionProgress),
+    /// The request finished, no more updates follow
+    Finished,
+}
+
+/// Progress of one request, as seen when subscribing
+#[derive(Debug)]
+pub struct ProgressSubscription {
+    /// Updates received before subscribing
+    pub history: Vec<ActionProgress>,
+    pub finished: bool,
+    pub updates: broadcast::Receiver<ProgressUpdate>,
+}
+
+/// Progress updates of running and recently finished requests
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    requests: Mutex<HashMap<Uuid, RequestProgress>>,
+}
+
+#[derive(Debug)]
+struct RequestProgress {
+    history: Vec<ActionProgress>,
+    sender: broadcast::Sender<ProgressUpdate>,
+    started: bool,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl RequestProgress {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        Self { history: Vec::new(), sender, started: false, created_at: Utc::now(), finished_at: None }
+    }
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start accepting updates for a request
+    pub fn start(&self, request_id: Uuid) {
+        let mut requests = self.requests.lock().unwrap();
+        let progress = requests.entry(request_id).or_insert_with(RequestProgress::new);
+        progress.started = true;
+        progress.finished_at = None;
+    }
+
+    /// Record an update, returns whether the request was running
+    pub fn record(&self, event: FeatureActionProgressEvent) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let progress = match requests.get_mut(&event.request_id) {
+            Some(progress) if progress.started && progress.finished_at.is_none() => progress,
+            _ => return false,
+        };
+
+        let update = ActionProgress { event, received_at: Utc::now() };
+        progress.history.push(update.clone());
+        // No subscribers is fine, the history still has the update
+        let _ = progress.sender.send(ProgressUpdate::Progress(update));
+        true
+    }
+
+    /// Stop accepting updates and tell subscribers the request finished
+    pub fn finish(&self, request_id: Uuid) {
+        let mut requests = self.requests.lock().unwrap();
+        if let Some(progress) = requests.get_mut(&request_id) {
+            progress.finished_at = Some(Utc::now());
+            let _ = progress.sender.send(ProgressUpdate::Finished);
+        }
+    }
+
+    /// Replay a request's updates and follow new ones
+    ///
+    /// Subscribing before the request starts is allowed, so clients that
+    /// pick their own request id don't miss the first updates.
+    pub fn subscribe(&self, request_id: Uuid) -> ProgressSubscription {
+        let mut requests = self.requests.lock().unwrap();
+        let progress = requests.entry(request_id).or_insert_with(RequestProgress::new);
+        ProgressSubscription {
+            history: progress.history.clone(),
+            finished: progress.finished_at.is_some(),
+            updates: progress.sender.subscribe(),
+        }
+    }
+
+    /// Updates received for a request so far
+    pub fn history(&self, request_id: Uuid) -> Vec<ActionProgress> {
+        let requests = self.requests.lock().unwrap();
+        requests.get(&request_id).map(|progress| progress.history.clone()).unwrap_or_default()
+    }
+
+    /// Forget requests that finished, or never started, more than `retention` ago
+    pub fn purge(&self, retention: Duration) -> usize {
+        let cutoff = Utc::now() - retention;
+        let mut requests = self.requests.lock().unwrap();
+        let before = requests.len();
+        requests.retain(|_, progress| match progress.finished_at {
+            Some(finished_at) => finished_at > cutoff,
+            None => progress.started || progress.created_at > cutoff,
+        });
+        before - requests.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(request_id: Uuid, phase: &str) -> FeatureActionProgressEvent {
+        FeatureActionProgressEvent {
+            request_id,
+            percent: None,
+            phase: Some(phase.to_string()),
+            message: None,
+            data: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_the_history_then_live_updates() {
+        let tracker = ProgressTracker::new();
+        let request_id = Uuid::new_v4();
+
+        // Nothing is recorded before the request starts
+        assert!(!tracker.record(progress(request_id, "early")));
+
+        tracker.start(request_id);
+        assert!(tracker.record(progress(request_id, "allocating disk")));
+
+        let mut subscription = tracker.subscribe(request_id);
+        assert_eq!(subscription.history.len(), 1);
+        assert!(!subscription.finished);
+
+        tracker.record(progress(request_id, "booting"));
+        tracker.finish(request_id);
+        assert!(!tracker.record(progress(request_id, "late")));
+
+        match subscription.updates.recv().await.unwrap() {
+            ProgressUpdate::Progress(update) => assert_eq!(update.event.phase.as_deref(), Some("booting")),
+            other => panic!("expected progress, got {:?}", other),
+        }
+        assert!(matches!(subscription.updates.recv().await.unwrap(), ProgressUpdate::Finished));
+        assert_eq!(tracker.history(request_id).len(), 2);
+
+        assert_eq!(tracker.purge(Duration::zero()), 1);
+        assert!(tracker.history(request_id).is_empty());
+    }
+}
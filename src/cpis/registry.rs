@@ -218,6 +218,17 @@ impl PluginRegistry {
         }
     }
 
+    /// Get the state of every plugin
+    pub async fn get_plugin_states(&self) -> HashMap<String, PluginState> {
+        let plugins = self.plugins.read().await;
+        let mut states = HashMap::new();
+        for (name, instance) in plugins.iter() {
+            let instance = instance.read().await;
+            states.insert(name.clone(), instance.state().clone());
+        }
+        states
+    }
+
     /// Set plugin state
     pub async fn set_plugin_state(
         &self,
@@ -0,0 +1,186 @@
+//! # Retry Policies
+//!
+//! Decides whether a failed action attempt is tried again, and how long the
+//! executor waits before the next one. Policies come from two places:
+//!
+//! - the `retry` field of an action in a feature schema
+//! - the `retry_policy` global argument (e.g. `OMNI_retry_policy`), keyed by
+//!   provider name with `"*"` as the fallback
+//!
+//! ```text
+//! OMNI_retry_policy={"hetzner": {"max_attempts": 5}, "*": {"max_attempts": 2}}
+//! ```
+//!
+//! A policy set for the provider by name wins over the action's own, which
+//! wins over the `"*"` fallback. Mutating actions are only retried when the
+//! request carries an idempotency key, and never after a timeout: the timed
+//! out attempt is told to stop, but may still be finishing on the provider.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+use super::PluginError;
+
+/// Global argument holding the per-provider retry policies
+pub const RETRY_POLICY_ARGUMENT: &str = "retry_policy";
+
+/// Kind of failure a policy can retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// No completion arrived within the timeout
+    Timeout,
+    /// The provider answered with HTTP 429
+    RateLimited,
+    /// The provider answered with an HTTP 5xx status
+    ServerError,
+    /// The plugin reported a failure
+    ExecutionFailed,
+    /// The action event could not be delivered
+    EventError,
+}
+
+impl ErrorClass {
+    /// Class of an error, `None` for errors that retrying can't fix
+    pub fn of(error: &PluginError) -> Option<Self> {
+        match error {
+            PluginError::Timeout(_) => Some(ErrorClass::Timeout),
+            PluginError::HttpStatus { status: 429, .. } => Some(ErrorClass::RateLimited),
+            PluginError::HttpStatus { status, .. } if *status >= 500 => Some(ErrorClass::ServerError),
+            PluginError::ExecutionFailed(_) => Some(ErrorClass::ExecutionFailed),
+            PluginError::EventError(_) => Some(ErrorClass::EventError),
+            _ => None,
+        }
+    }
+}
+
+/// How often and how fast a failed action is retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_backoff_ms: u64,
+    /// Upper bound for any delay
+    pub max_backoff_ms: u64,
+    /// Factor the delay grows by with every retry
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomized, from 0 to 1
+    pub jitter: f64,
+    /// Failures that are retried
+    pub retry_on: Vec<ErrorClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.5,
+            retry_on: vec![ErrorClass::Timeout, ErrorClass::RateLimited, ErrorClass::ServerError],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Pick the policy for a provider's action
+    pub fn select(
+        setting: Option<&Value>,
+        provider: &str,
+        action_policy: Option<&RetryPolicy>,
+    ) -> Result<Option<Self>, PluginError> {
+        let parse = |value: &Value| serde_json::from_value::<RetryPolicy>(value.clone())
+            .map_err(|e| PluginError::InvalidArgument(format!(
+                "{} for {} is not a valid retry policy: {}", RETRY_POLICY_ARGUMENT, provider, e
+            )));
+
+        let providers = match setting {
+            Some(Value::Object(providers)) => Some(providers),
+            Some(other) => return Err(PluginError::InvalidArgument(format!(
+                "{} must be an object keyed by provider, got {}", RETRY_POLICY_ARGUMENT, other
+            ))),
+            None => None,
+        };
+
+        if let Some(policy) = providers.and_then(|providers| providers.get(provider)) {
+            return parse(policy).map(Some);
+        }
+        if let Some(policy) = action_policy {
+            return Ok(Some(policy.clone()));
+        }
+        match providers.and_then(|providers| providers.get("*")) {
+            Some(policy) => parse(policy).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether an attempt that failed with `error` should be retried
+    pub fn is_retryable(&self, error: &PluginError) -> bool {
+        ErrorClass::of(error).is_some_and(|class| self.retry_on.contains(&class))
+    }
+
+    /// Delay before the given retry, the first retry being 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial_backoff_ms as f64 * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_backoff_ms as f64);
+
+        // Take a random share of the jitter fraction off the delay
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let random = (Uuid::new_v4().as_u128() % 10_000) as f64 / 10_000.0;
+        Duration::from_millis((delay * (1.0 - jitter * random)) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn provider_policies_win_over_action_policies() {
+        let setting = json!({ "hetzner": { "max_attempts": 5 }, "*": { "max_attempts": 2 } });
+        let action = RetryPolicy { max_attempts: 4, ..RetryPolicy::default() };
+
+        let select = |provider, action_policy| {
+            RetryPolicy::select(Some(&setting), provider, action_policy).unwrap().map(|p| p.max_attempts)
+        };
+        assert_eq!(select("hetzner", Some(&action)), Some(5));
+        assert_eq!(select("linode", Some(&action)), Some(4));
+        assert_eq!(select("linode", None), Some(2));
+        assert_eq!(RetryPolicy::select(None, "linode", None).unwrap(), None);
+        assert!(RetryPolicy::select(Some(&json!("always")), "linode", None).is_err());
+    }
+
+    #[test]
+    fn only_listed_errors_are_retried() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable(&PluginError::Timeout("30s".into())));
+        assert!(policy.is_retryable(&PluginError::HttpStatus { status: 503, message: String::new() }));
+        assert!(!policy.is_retryable(&PluginError::HttpStatus { status: 404, message: String::new() }));
+        assert!(!policy.is_retryable(&PluginError::ExecutionFailed("exit 1".into())));
+        assert!(!policy.is_retryable(&PluginError::InvalidArgument("vm_id".into())));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_limit() {
+        let policy = RetryPolicy {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let delays: Vec<u64> = (1..=5).map(|retry| policy.backoff(retry).as_millis() as u64).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1_000]);
+
+        let jittered = RetryPolicy { jitter: 0.5, ..policy };
+        for _ in 0..20 {
+            let delay = jittered.backoff(2).as_millis() as u64;
+            assert!((100..=200).contains(&delay), "{}", delay);
+        }
+    }
+}
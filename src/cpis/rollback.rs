@@ -0,0 +1,192 @@
+//! # Rollback
+//!
+//! Feature schemas can declare how a mutating action is undone, so a batch
+//! or workflow that fails halfway doesn't leave orphaned VMs and volumes:
+//!
+//! ```text
+//! "create_vm": { ..., "compensation": { "action": "delete_vm",
+//!                                       "arguments": { "vm_id": "${result.vm_id}" } } }
+//! ```
+//!
+//! The compensating action belongs to the same feature and provider. Its
+//! arguments can reference the original request's arguments as
+//! `${arguments.<name>}` and its result as `${result...}`.
+//!
+//! When a batch or workflow with `rollback: true` fails, the mutating actions
+//! that succeeded are undone one at a time, the last one first.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use super::workflow::resolve;
+use super::{ActionRequest, PluginError, PluginExecutor};
+
+/// How a feature action is undone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Compensation {
+    /// Action of the same feature that undoes this one
+    pub action: String,
+    /// Arguments of the compensating action, may reference the original arguments and result
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+}
+
+/// How undoing an action ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompensationStatus {
+    Succeeded,
+    Failed,
+    /// The action declares no compensation, it has to be undone by hand
+    Unavailable,
+}
+
+/// Outcome of undoing one action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompensationResult {
+    /// Request of the action that was undone
+    pub original_request_id: Uuid,
+    pub provider: String,
+    pub feature: String,
+    /// The action that was undone
+    pub action: String,
+    /// The action that undid it
+    pub compensation: Option<String>,
+    pub status: CompensationStatus,
+    pub request_id: Option<Uuid>,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub execution_time_ms: u64,
+}
+
+impl CompensationResult {
+    fn not_run(original: &ActionRequest, compensation: Option<String>, status: CompensationStatus, error: String) -> Self {
+        Self {
+            original_request_id: original.request_id,
+            provider: original.provider.clone(),
+            feature: original.feature.clone(),
+            action: original.action.clone(),
+            compensation,
+            status,
+            request_id: None,
+            result: None,
+            error: Some(error),
+            execution_time_ms: 0,
+        }
+    }
+}
+
+/// An action that succeeded and may have to be undone
+#[derive(Debug, Clone)]
+pub struct CompletedAction {
+    pub request: ActionRequest,
+    pub result: Value,
+}
+
+/// Undo the completed actions, the last one first
+///
+/// Actions that don't change anything are passed over. Every mutating action
+/// gets an entry in the outcome, also when it can't be undone.
+pub async fn roll_back(executor: &PluginExecutor, completed: Vec<CompletedAction>) -> Vec<CompensationResult> {
+    let mut outcomes = Vec::new();
+
+    for CompletedAction { request: original, result } in completed.into_iter().rev() {
+        let action = match executor.get_action(&original.feature, &original.action).await {
+            Ok(action) => action,
+            Err(e) => {
+                outcomes.push(CompensationResult::not_run(&original, None, CompensationStatus::Unavailable, e.to_string()));
+                continue;
+            }
+        };
+        if !action.is_mutating {
+            continue;
+        }
+        let compensation = match action.compensation {
+            Some(compensation) => compensation,
+            None => {
+                outcomes.push(CompensationResult::not_run(&original, None, CompensationStatus::Unavailable, format!(
+                    "{}/{} declares no compensation", original.feature, original.action
+                )));
+                continue;
+            }
+        };
+
+        let request = match compensating_request(&original, &result, &compensation) {
+            Ok(request) => request,
+            Err(e) => {
+                outcomes.push(CompensationResult::not_run(
+                    &original, Some(compensation.action), CompensationStatus::Failed, e.to_string(),
+                ));
+                continue;
+            }
+        };
+        let execution = executor.execute(request).await;
+
+        let (status, result, error) = match execution.result {
+            Ok(value) => (CompensationStatus::Succeeded, Some(value), None),
+            Err(e) => (CompensationStatus::Failed, None, Some(e.to_string())),
+        };
+        outcomes.push(CompensationResult {
+            original_request_id: original.request_id,
+            provider: original.provider,
+            feature: original.feature,
+            action: original.action,
+            compensation: Some(compensation.action),
+            status,
+            request_id: Some(execution.request_id),
+            result,
+            error,
+            execution_time_ms: execution.execution_time.as_millis() as u64,
+        });
+    }
+
+    outcomes
+}
+
+/// Request undoing `original`, with the references in the arguments resolved
+fn compensating_request(
+    original: &ActionRequest,
+    result: &Value,
+    compensation: &Compensation,
+) -> Result<ActionRequest, PluginError> {
+    let root = json!({ "arguments": original.arguments, "result": result });
+    let arguments = compensation.arguments.iter()
+        .map(|(name, value)| Ok((name.clone(), resolve(value, &root)?)))
+        .collect::<Result<HashMap<String, Value>, PluginError>>()?;
+
+    let mut request = ActionRequest::new(
+        &original.provider, &original.feature, &compensation.action, arguments, Some(original.timeout),
+    );
+    request.caller = original.caller.clone();
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compensations_use_the_original_arguments_and_result() {
+        let original = ActionRequest::new(
+            "hetzner", "VM_Manage", "create_vm",
+            HashMap::from([("name".to_string(), json!("web-1"))]),
+            None,
+        );
+        let compensation = Compensation {
+            action: "delete_vm".to_string(),
+            arguments: HashMap::from([
+                ("vm_id".to_string(), json!("${result.vm_id}")),
+                ("reason".to_string(), json!("rollback of ${arguments.name}")),
+            ]),
+        };
+
+        let request = compensating_request(&original, &json!({ "vm_id": "vm-7" }), &compensation).unwrap();
+        assert_eq!(request.provider, "hetzner");
+        assert_eq!(request.action, "delete_vm");
+        assert_eq!(request.arguments["vm_id"], json!("vm-7"));
+        assert_eq!(request.arguments["reason"], json!("rollback of web-1"));
+
+        assert!(compensating_request(&original, &json!({}), &compensation).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+//! # Schedules
+//!
+//! Runs feature actions on a recurring cron schedule, like a nightly
+//! `create_snapshot` or an hourly `list_workers` inventory sweep, instead of
+//! external cron scripts calling the API.
+//!
+//! Each enabled schedule keeps one timer armed through
+//! `ServerContext::schedule_task`. When it fires, the next timer is armed
+//! and the action runs through the `PluginExecutor`, with `schedule:<id>` as
+//! the caller. The outcome of every run is kept with the schedule.
+//!
+//! Schedules are written to `<store_dir>/<id>.json`. Runs missed while the
+//! director was down are not made up.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+use super::context::SCHEDULED_TASK_EVENT_KEY;
+use super::cron::CronSchedule;
+use super::history::ExecutionStatus;
+use super::{ActionRequest, EventError, PluginError, PluginExecutor, ScheduledTaskEvent, ServerContext};
+
+/// Default directory for persisted schedules
+pub const DEFAULT_SCHEDULE_DIR: &str = "./data/schedules";
+
+/// Runs kept per schedule, older ones are dropped
+pub const MAX_SCHEDULE_RUNS: usize = 50;
+
+/// What a schedule runs and when
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleDefinition {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Cron expression, evaluated in UTC
+    pub cron: CronSchedule,
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_timeout_seconds() -> u64 {
+    60
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A stored schedule with its recent runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub definition: ScheduleDefinition,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the schedule fires next, `None` while it is disabled
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// Latest runs, oldest first
+    #[serde(default)]
+    pub runs: Vec<ScheduleRun>,
+}
+
+/// One time a schedule fired
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    /// Executor request the run went out as
+    pub request_id: Uuid,
+    /// When the run was due
+    pub scheduled_for: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub status: ExecutionStatus,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub execution_time_ms: u64,
+}
+
+/// Keeps schedules, their timers and their run history
+#[derive(Debug)]
+pub struct ScheduleManager {
+    executor: Arc<PluginExecutor>,
+    context: Arc<dyn ServerContext>,
+    store: ScheduleStore,
+    /// Task armed for each enabled schedule, by schedule id
+    timers: Mutex<HashMap<Uuid, Uuid>>,
+}
+
+impl ScheduleManager {
+    pub fn new(
+        executor: Arc<PluginExecutor>,
+        context: Arc<dyn ServerContext>,
+        store_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            executor,
+            context,
+            store: ScheduleStore { dir: store_dir.into(), schedules: RwLock::new(HashMap::new()) },
+            timers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run schedules when their timers fire
+    pub async fn initialize(self: &Arc<Self>) -> Result<(), PluginError> {
+        let manager = Arc::clone(self);
+        self.context.events().on_event::<ScheduledTaskEvent, _>(
+            SCHEDULED_TASK_EVENT_KEY,
+            move |event| {
+                let manager = Arc::clone(&manager);
+                tokio::spawn(async move { manager.fire(event).await });
+                Ok::<(), EventError>(())
+            }
+        ).await
+            .map_err(|e| PluginError::EventError(e.to_string()))
+    }
+
+    /// Load the persisted schedules and arm the enabled ones
+    pub async fn load(&self) -> Result<usize, PluginError> {
+        let dir = self.store.dir.clone();
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            match read_schedule(&path).await {
+                Ok(schedule) => {
+                    let id = schedule.id;
+                    self.store.schedules.write().await.insert(id, schedule);
+                    self.arm(id).await?;
+                    count += 1;
+                }
+                Err(e) => eprintln!("Failed to load schedule from {:?}: {}", path, e),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Store a new schedule and arm it
+    pub async fn create(&self, definition: ScheduleDefinition) -> Result<Schedule, PluginError> {
+        self.validate(&definition).await?;
+
+        let now = Utc::now();
+        let schedule = Schedule {
+            id: Uuid::new_v4(),
+            definition,
+            created_at: now,
+            updated_at: now,
+            next_run_at: None,
+            runs: Vec::new(),
+        };
+        let id = schedule.id;
+        self.store.insert(schedule).await?;
+        self.arm(id).await
+    }
+
+    /// Replace what a schedule runs and when
+    pub async fn update(&self, id: Uuid, definition: ScheduleDefinition) -> Result<Option<Schedule>, PluginError> {
+        self.validate(&definition).await?;
+
+        let updated = self.store.update(id, |schedule| {
+            schedule.definition = definition;
+            schedule.updated_at = Utc::now();
+        }).await;
+        if updated.is_none() {
+            return Ok(None);
+        }
+        self.arm(id).await.map(Some)
+    }
+
+    /// Refuse definitions for unknown actions or that would never run
+    async fn validate(&self, definition: &ScheduleDefinition) -> Result<(), PluginError> {
+        self.executor.get_action(&definition.feature, &definition.action).await?;
+        if definition.cron.next_after(Utc::now()).is_none() {
+            return Err(PluginError::InvalidArgument(format!(
+                "Cron expression {} never fires", definition.cron
+            )));
+        }
+        Ok(())
+    }
+
+    /// Remove a schedule, runs that already started finish
+    pub async fn delete(&self, id: Uuid) -> Result<Option<Schedule>, PluginError> {
+        self.disarm(id).await;
+        self.store.remove(id).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<Schedule> {
+        self.store.schedules.read().await.get(&id).cloned()
+    }
+
+    /// All schedules, oldest first
+    pub async fn list(&self) -> Vec<Schedule> {
+        let schedules = self.store.schedules.read().await;
+        let mut schedules: Vec<Schedule> = schedules.values().cloned().collect();
+        schedules.sort_by_key(|schedule| schedule.created_at);
+        schedules
+    }
+
+    /// Runs of a schedule, newest first
+    pub async fn runs(&self, id: Uuid) -> Option<Vec<ScheduleRun>> {
+        let schedule = self.get(id).await?;
+        Some(schedule.runs.into_iter().rev().collect())
+    }
+
+    /// Replace a schedule's timer with one for its next run
+    async fn arm(&self, id: Uuid) -> Result<Schedule, PluginError> {
+        // Held while scheduling, so a timer firing right away finds itself
+        let mut timers = self.timers.lock().await;
+        if let Some(task_id) = timers.remove(&id) {
+            let _ = self.context.cancel_task(task_id).await;
+        }
+
+        let schedule = self.get(id).await
+            .ok_or_else(|| PluginError::ExecutionFailed(format!("Schedule {} not found", id)))?;
+        let now = Utc::now();
+        let next_run_at = if schedule.definition.enabled {
+            schedule.definition.cron.next_after(now)
+        } else {
+            None
+        };
+
+        if let Some(next_run_at) = next_run_at {
+            let delay_ms = (next_run_at - now).num_milliseconds().max(0) as u64;
+            let data = json!({ "schedule_id": id, "scheduled_for": next_run_at });
+            let task_id = self.context.schedule_task(delay_ms, data).await
+                .map_err(|e| PluginError::ExecutionFailed(format!("Failed to arm schedule {}: {}", id, e)))?;
+            timers.insert(id, task_id);
+        }
+
+        Ok(self.store.update(id, |schedule| schedule.next_run_at = next_run_at).await.unwrap_or(schedule))
+    }
+
+    async fn disarm(&self, id: Uuid) {
+        if let Some(task_id) = self.timers.lock().await.remove(&id) {
+            let _ = self.context.cancel_task(task_id).await;
+        }
+    }
+
+    /// Run the schedule a timer belongs to, after arming its next run
+    async fn fire(&self, event: ScheduledTaskEvent) {
+        // Other tasks scheduled through the context aren't ours
+        let id = match event.data.get("schedule_id").and_then(|id| serde_json::from_value::<Uuid>(id.clone()).ok()) {
+            Some(id) => id,
+            None => return,
+        };
+        let scheduled_for = event.data.get("scheduled_for")
+            .and_then(|at| serde_json::from_value(at.clone()).ok())
+            .unwrap_or_else(Utc::now);
+
+        // A timer replaced by an update or delete is stale
+        {
+            let mut timers = self.timers.lock().await;
+            if timers.get(&id) != Some(&event.task_id) {
+                return;
+            }
+            timers.remove(&id);
+        }
+        let schedule = match self.arm(id).await {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                eprintln!("⏰ Schedule {} could not be re-armed: {}", id, e);
+                match self.get(id).await {
+                    Some(schedule) => schedule,
+                    None => return,
+                }
+            }
+        };
+
+        let run = self.run(&schedule, scheduled_for).await;
+        let updated = self.store.update(id, |schedule| {
+            if schedule.runs.len() >= MAX_SCHEDULE_RUNS {
+                schedule.runs.remove(0);
+            }
+            schedule.runs.push(run);
+        }).await;
+        if updated.is_none() {
+            println!("⏰ Schedule {} was deleted while it ran", id);
+        }
+    }
+
+    async fn run(&self, schedule: &Schedule, scheduled_for: DateTime<Utc>) -> ScheduleRun {
+        let definition = &schedule.definition;
+        let mut request = ActionRequest::new(
+            &definition.provider,
+            &definition.feature,
+            &definition.action,
+            definition.arguments.clone(),
+            Some(Duration::from_secs(definition.timeout_seconds)),
+        );
+        request.caller = Some(format!("schedule:{}", schedule.id));
+
+        let started_at = Utc::now();
+        let execution = self.executor.execute(request).await;
+        let status = ExecutionStatus::of(&execution.result);
+        let (result, error) = match execution.result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        ScheduleRun {
+            request_id: execution.request_id,
+            scheduled_for,
+            started_at,
+            finished_at: Utc::now(),
+            status,
+            result,
+            error,
+            execution_time_ms: execution.execution_time.as_millis() as u64,
+        }
+    }
+}
+
+/// Schedules in memory, mirrored to one file per schedule
+#[derive(Debug)]
+struct ScheduleStore {
+    dir: PathBuf,
+    schedules: RwLock<HashMap<Uuid, Schedule>>,
+}
+
+impl ScheduleStore {
+    async fn insert(&self, schedule: Schedule) -> Result<(), PluginError> {
+        let mut schedules = self.schedules.write().await;
+        self.save(&schedule).await?;
+        schedules.insert(schedule.id, schedule);
+        Ok(())
+    }
+
+    /// Apply a change to a schedule and persist it, `None` if it doesn't exist
+    async fn update<F>(&self, id: Uuid, change: F) -> Option<Schedule>
+    where
+        F: FnOnce(&mut Schedule),
+    {
+        let mut schedules = self.schedules.write().await;
+        let schedule = schedules.get_mut(&id)?;
+        change(schedule);
+
+        if let Err(e) = self.save(schedule).await {
+            eprintln!("Failed to persist schedule {}: {}", id, e);
+        }
+        Some(schedule.clone())
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<Option<Schedule>, PluginError> {
+        let mut schedules = self.schedules.write().await;
+        let removed = schedules.remove(&id);
+        if removed.is_some() {
+            let path = self.dir.join(format!("{}.json", id));
+            if path.exists() {
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn save(&self, schedule: &Schedule) -> Result<(), PluginError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{}.json", schedule.id));
+        let partial = path.with_extension("json.tmp");
+
+        // Replace the file in one step, a crash mid-write leaves the old state
+        tokio::fs::write(&partial, serde_json::to_string_pretty(schedule)?).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+}
+
+async fn read_schedule(path: &Path) -> Result<Schedule, PluginError> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ArgumentManager, EventSystem, FeatureRegistry, PluginRegistry, ServerContextBuilder};
+
+    async fn manager(dir: &Path) -> Arc<ScheduleManager> {
+        let events = Arc::new(EventSystem::new());
+        let features = Arc::new(FeatureRegistry::new());
+        features.load_schemas(dir.join("features")).await.unwrap();
+        let arguments = Arc::new(ArgumentManager::new());
+        let context = ServerContextBuilder::new()
+            .with_event_system(Arc::clone(&events))
+            .with_feature_registry(Arc::clone(&features))
+            .with_argument_manager(Arc::clone(&arguments))
+            .build()
+            .unwrap();
+        let executor = Arc::new(PluginExecutor::new(events, Arc::new(PluginRegistry::new()), features, arguments));
+
+        let manager = Arc::new(ScheduleManager::new(executor, context, dir.join("schedules")));
+        manager.initialize().await.unwrap();
+        manager
+    }
+
+    fn definition(cron: &str) -> ScheduleDefinition {
+        serde_json::from_value(json!({
+            "cron": cron,
+            "provider": "missing",
+            "feature": "VM_Manage",
+            "action": "delete_vm",
+            "arguments": { "vm_id": "vm-1" },
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn schedules_are_armed_and_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("omni-schedules-{}", Uuid::new_v4()));
+        let before = manager(&dir).await;
+
+        let nightly = before.create(definition("0 2 * * *")).await.unwrap();
+        assert!(nightly.next_run_at.is_some_and(|at| at > Utc::now()));
+        assert!(before.timers.lock().await.contains_key(&nightly.id));
+
+        let mut paused = definition("@hourly");
+        paused.enabled = false;
+        let paused = before.create(paused).await.unwrap();
+        assert!(paused.next_run_at.is_none());
+
+        let mut invalid = serde_json::to_value(definition("@daily")).unwrap();
+        invalid["cron"] = json!("every night");
+        assert!(serde_json::from_value::<ScheduleDefinition>(invalid).is_err());
+        let mut unknown = definition("@daily");
+        unknown.action = "format_disk".to_string();
+        assert!(before.create(unknown).await.is_err());
+        let never = before.create(definition("0 0 30 2 *")).await;
+        assert!(matches!(never, Err(PluginError::InvalidArgument(message)) if message.contains("never fires")));
+        assert!(matches!(before.update(nightly.id, definition("0 0 31 4 *")).await, Err(PluginError::InvalidArgument(_))));
+
+        let after = manager(&dir).await;
+        assert_eq!(after.load().await.unwrap(), 2);
+        assert!(after.timers.lock().await.contains_key(&nightly.id));
+        assert!(!after.timers.lock().await.contains_key(&paused.id));
+
+        assert!(after.delete(nightly.id).await.unwrap().is_some());
+        assert!(after.get(nightly.id).await.is_none());
+        assert!(after.runs(nightly.id).await.is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn due_timers_run_the_action_and_record_it() {
+        let dir = std::env::temp_dir().join(format!("omni-schedules-{}", Uuid::new_v4()));
+        let manager = manager(&dir).await;
+        let schedule = manager.create(definition("@yearly")).await.unwrap();
+
+        // Fire the armed timer now instead of waiting for new year
+        let task_id = manager.timers.lock().await[&schedule.id];
+        manager.fire(ScheduledTaskEvent {
+            task_id,
+            data: json!({ "schedule_id": schedule.id, "scheduled_for": Utc::now() }),
+        }).await;
+
+        let runs = manager.runs(schedule.id).await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, ExecutionStatus::Failed);
+        assert!(runs[0].error.as_deref().unwrap().contains("missing"));
+        // The next run is armed with a new timer, the old one is stale
+        assert_ne!(manager.timers.lock().await[&schedule.id], task_id);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
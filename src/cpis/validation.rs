@@ -0,0 +1,310 @@
+//! # Result Validation
+//!
+//! Checks what plugins return against the `return_type` of the action in
+//! its feature schema, so API consumers can rely on the schema. How a
+//! mismatch is handled is set per provider through the `result_validation`
+//! global argument:
+//!
+//! ```text
+//! OMNI_result_validation="strict"
+//! OMNI_result_validation={"hetzner": "strict", "*": "lenient"}
+//! ```
+//!
+//! In strict mode a mismatching result fails the request. In lenient mode,
+//! the default, the result is passed on and the mismatch is only recorded.
+//! Either way violations are counted per plugin.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+use super::{ArgumentManager, ArgumentResolution, ArgumentType, FeatureRegistry, PluginError, ReturnType};
+
+/// Global argument selecting the validation mode
+pub const RESULT_VALIDATION_ARGUMENT: &str = "result_validation";
+
+/// Violations kept per plugin for inspection
+const RECENT_VIOLATIONS: usize = 20;
+
+/// How results that don't match their return type are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Fail the request
+    Strict,
+    /// Pass the result on and record a warning
+    #[default]
+    Lenient,
+}
+
+impl ValidationMode {
+    /// Pick the mode for a provider from the `result_validation` setting
+    pub fn for_provider(setting: &Value, provider: &str) -> Result<Self, PluginError> {
+        let selected = match setting {
+            Value::Object(modes) => match modes.get(provider).or_else(|| modes.get("*")) {
+                Some(mode) => mode,
+                None => return Ok(ValidationMode::default()),
+            },
+            mode => mode,
+        };
+
+        serde_json::from_value(selected.clone()).map_err(|_| PluginError::InvalidArgument(format!(
+            "{} for {} must be strict or lenient, got {}", RESULT_VALIDATION_ARGUMENT, provider, selected
+        )))
+    }
+}
+
+/// A result that didn't match its return type
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultViolation {
+    pub request_id: Uuid,
+    pub feature: String,
+    pub action: String,
+    pub problems: Vec<String>,
+    pub mode: ValidationMode,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Violations of one plugin
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PluginViolations {
+    /// Results checked so far
+    pub checked: u64,
+    /// Results that didn't match
+    pub violations: u64,
+    /// The latest violations, oldest first
+    pub recent: Vec<ResultViolation>,
+}
+
+/// Validates results and counts violations per plugin
+#[derive(Debug)]
+pub struct ResultValidator {
+    feature_registry: Arc<FeatureRegistry>,
+    argument_manager: Arc<ArgumentManager>,
+    plugins: Mutex<HashMap<String, PluginViolations>>,
+}
+
+impl ResultValidator {
+    pub fn new(feature_registry: Arc<FeatureRegistry>, argument_manager: Arc<ArgumentManager>) -> Self {
+        Self { feature_registry, argument_manager, plugins: Mutex::new(HashMap::new()) }
+    }
+
+    /// Check a plugin's result against the action's return type
+    ///
+    /// Returns the result unchanged unless it mismatches in strict mode.
+    /// Actions without a schema aren't checked.
+    pub async fn check(
+        &self,
+        request_id: Uuid,
+        provider: &str,
+        feature: &str,
+        action: &str,
+        result: Value,
+    ) -> Result<Value, PluginError> {
+        let return_type = match self.feature_registry.get_action(feature, action).await {
+            Ok(definition) => definition.return_type,
+            Err(_) => return Ok(result),
+        };
+        let problems = validate_result(&result, &return_type);
+        let mode = self.mode(provider).await;
+
+        {
+            let mut plugins = self.plugins.lock().unwrap();
+            let stats = plugins.entry(provider.to_string()).or_default();
+            stats.checked += 1;
+            if problems.is_empty() {
+                return Ok(result);
+            }
+            stats.violations += 1;
+            if stats.recent.len() == RECENT_VIOLATIONS {
+                stats.recent.remove(0);
+            }
+            stats.recent.push(ResultViolation {
+                request_id,
+                feature: feature.to_string(),
+                action: action.to_string(),
+                problems: problems.clone(),
+                mode,
+                detected_at: Utc::now(),
+            });
+        }
+
+        let message = format!(
+            "Result of {}/{} from {} does not match its return type: {}",
+            feature, action, provider, problems.join("; ")
+        );
+        match mode {
+            ValidationMode::Strict => Err(PluginError::InvalidResult(message)),
+            ValidationMode::Lenient => {
+                eprintln!("⚠️ {}", message);
+                Ok(result)
+            }
+        }
+    }
+
+    /// Violation counts of every plugin that returned a result
+    pub fn violations(&self) -> HashMap<String, PluginViolations> {
+        self.plugins.lock().unwrap().clone()
+    }
+
+    async fn mode(&self, provider: &str) -> ValidationMode {
+        let setting = self.argument_manager
+            .get_argument(provider, RESULT_VALIDATION_ARGUMENT, None, ArgumentResolution::GlobalOnly)
+            .await
+            .ok()
+            .map(|argument| argument.value);
+        match setting {
+            Some(setting) => ValidationMode::for_provider(&setting, provider).unwrap_or_else(|e| {
+                eprintln!("⚠️ {}, validating leniently", e);
+                ValidationMode::Lenient
+            }),
+            None => ValidationMode::default(),
+        }
+    }
+}
+
+/// Ways a result differs from its return type, empty when it matches
+pub fn validate_result(value: &Value, return_type: &ReturnType) -> Vec<String> {
+    let mut problems = Vec::new();
+    check_return(value, return_type, "result", &mut problems);
+    problems
+}
+
+fn check_return(value: &Value, return_type: &ReturnType, path: &str, problems: &mut Vec<String>) {
+    match (return_type, value) {
+        // Plugins without anything to say return null or an empty object
+        (ReturnType::Void, Value::Null) => {}
+        (ReturnType::Void, Value::Object(object)) if object.is_empty() => {}
+        (ReturnType::String, Value::String(_)) => {}
+        (ReturnType::Number, Value::Number(_)) => {}
+        (ReturnType::Boolean, Value::Bool(_)) => {}
+        (ReturnType::Object { schema }, Value::Object(object)) => check_fields(object, schema, path, problems),
+        (ReturnType::Array { item_type }, Value::Array(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                check_return(item, item_type, &format!("{}[{}]", path, index), problems);
+            }
+        }
+        (expected, _) => problems.push(format!("{} should be {}, got {}", path, return_type_name(expected), kind(value))),
+    }
+}
+
+/// Every declared field has to be present, extra fields are allowed
+fn check_fields(
+    object: &serde_json::Map<String, Value>,
+    schema: &HashMap<String, ArgumentType>,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    let mut names: Vec<&String> = schema.keys().collect();
+    names.sort();
+    for name in names {
+        let field_path = format!("{}.{}", path, name);
+        match object.get(name.as_str()) {
+            Some(value) => check_argument(value, &schema[name], &field_path, problems),
+            None => problems.push(format!("{} is missing", field_path)),
+        }
+    }
+}
+
+fn check_argument(value: &Value, arg_type: &ArgumentType, path: &str, problems: &mut Vec<String>) {
+    match (arg_type, value) {
+        (ArgumentType::String { max_length }, Value::String(text)) => {
+            if let Some(max_length) = max_length {
+                if text.len() > *max_length {
+                    problems.push(format!("{} is longer than {} characters", path, max_length));
+                }
+            }
+        }
+        (ArgumentType::Number { min, max }, Value::Number(number)) => {
+            let number = number.as_f64().unwrap_or(0.0);
+            if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                problems.push(format!("{} is out of range: {}", path, number));
+            }
+        }
+        (ArgumentType::Boolean, Value::Bool(_)) => {}
+        (ArgumentType::Array { item_type }, Value::Array(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                check_argument(item, item_type, &format!("{}[{}]", path, index), problems);
+            }
+        }
+        (ArgumentType::Object { properties }, Value::Object(object)) => check_fields(object, properties, path, problems),
+        (ArgumentType::Enum { values }, Value::String(text)) => {
+            if !values.contains(text) {
+                problems.push(format!("{} has value {}, expected one of {}", path, text, values.join(", ")));
+            }
+        }
+        (expected, _) => problems.push(format!("{} should be {}, got {}", path, argument_type_name(expected), kind(value))),
+    }
+}
+
+fn return_type_name(return_type: &ReturnType) -> &'static str {
+    match return_type {
+        ReturnType::Void => "empty",
+        ReturnType::String => "a string",
+        ReturnType::Number => "a number",
+        ReturnType::Boolean => "a boolean",
+        ReturnType::Object { .. } => "an object",
+        ReturnType::Array { .. } => "an array",
+    }
+}
+
+fn argument_type_name(arg_type: &ArgumentType) -> &'static str {
+    match arg_type {
+        ArgumentType::String { .. } | ArgumentType::Enum { .. } => "a string",
+        ArgumentType::Number { .. } => "a number",
+        ArgumentType::Boolean => "a boolean",
+        ArgumentType::Array { .. } => "an array",
+        ArgumentType::Object { .. } => "an object",
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vm_schema() -> ReturnType {
+        ReturnType::Object {
+            schema: HashMap::from([
+                ("vm_id".to_string(), ArgumentType::String { max_length: None }),
+                ("status".to_string(), ArgumentType::Enum { values: vec!["running".into(), "stopped".into()] }),
+            ]),
+        }
+    }
+
+    #[test]
+    fn results_are_checked_against_the_return_type() {
+        assert!(validate_result(&json!({ "vm_id": "vm-1", "status": "running", "extra": 1 }), &vm_schema()).is_empty());
+        assert_eq!(
+            validate_result(&json!({ "status": "paused" }), &vm_schema()),
+            ["result.status has value paused, expected one of running, stopped", "result.vm_id is missing"],
+        );
+
+        let list = ReturnType::Array { item_type: Box::new(ReturnType::Number) };
+        assert_eq!(validate_result(&json!([1, "two"]), &list), ["result[1] should be a number, got a string"]);
+        assert!(validate_result(&json!(null), &ReturnType::Void).is_empty());
+        assert_eq!(validate_result(&json!("ok"), &ReturnType::Boolean), ["result should be a boolean, got a string"]);
+    }
+
+    #[test]
+    fn modes_are_picked_per_provider() {
+        let setting = json!({ "hetzner": "strict", "*": "lenient" });
+        assert_eq!(ValidationMode::for_provider(&setting, "hetzner").unwrap(), ValidationMode::Strict);
+        assert_eq!(ValidationMode::for_provider(&setting, "linode").unwrap(), ValidationMode::Lenient);
+        assert_eq!(ValidationMode::for_provider(&json!("strict"), "linode").unwrap(), ValidationMode::Strict);
+        assert!(ValidationMode::for_provider(&json!("loose"), "linode").is_err());
+    }
+}
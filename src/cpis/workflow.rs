@@ -0,0 +1,493 @@
+//! # Workflows
+//!
+//! Runs feature actions that depend on each other, like creating a volume,
+//! then a worker, then attaching the volume to the worker. A workflow is a
+//! list of named steps. A step starts once every step it depends on
+//! succeeded, steps without a dependency between them run in parallel.
+//!
+//! Arguments can use the results of earlier steps:
+//!
+//! ```text
+//! { "name": "attach", "provider": "hetzner", "feature": "Volume_Manage", "action": "attach_volume",
+//!   "depends_on": ["vol", "worker"],
+//!   "arguments": { "volume_id": "${steps.vol.result.id}", "server": "srv-${steps.worker.result.id}" } }
+//! ```
+//!
+//! An argument that is exactly one reference takes the referenced value as
+//! is, references inside longer text are replaced by their text. Referencing
+//! a step makes it a dependency even when `depends_on` doesn't list it. When
+//! a step fails, the steps depending on it are skipped, and with `rollback`
+//! set the steps that succeeded are undone (see `rollback`).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use super::rollback::{roll_back, CompensationResult, CompletedAction};
+use super::{ActionRequest, ExecutionAttempt, ExecutionResult, PluginError, PluginExecutor};
+
+/// Step timeout when neither the step nor the workflow sets one
+pub const DEFAULT_STEP_TIMEOUT_SECONDS: u64 = 60;
+
+/// Feature actions to run in dependency order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Workflow {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub steps: Vec<WorkflowStep>,
+    /// Timeout of steps that don't set their own
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    /// Undo the steps that succeeded when another one fails
+    #[serde(default)]
+    pub rollback: bool,
+}
+
+/// One action of a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowStep {
+    /// Name other steps refer to this one by
+    pub name: String,
+    pub provider: String,
+    pub feature: String,
+    pub action: String,
+    /// Arguments, which may reference the results of earlier steps
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+/// How a step ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    /// A step it depends on didn't succeed
+    Skipped,
+}
+
+/// Outcome of one step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepResult {
+    pub name: String,
+    pub status: StepStatus,
+    /// Executor request the step ran as, skipped steps have none
+    pub request_id: Option<Uuid>,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub execution_time_ms: u64,
+    #[serde(default)]
+    pub attempts: Vec<ExecutionAttempt>,
+}
+
+impl StepResult {
+    fn executed(step: &WorkflowStep, execution: ExecutionResult) -> Self {
+        let (status, result, error) = match execution.result {
+            Ok(value) => (StepStatus::Succeeded, Some(value), None),
+            Err(e) => (StepStatus::Failed, None, Some(e.to_string())),
+        };
+        Self {
+            name: step.name.clone(),
+            status,
+            request_id: Some(execution.request_id),
+            result,
+            error,
+            execution_time_ms: execution.execution_time.as_millis() as u64,
+            attempts: execution.attempts,
+        }
+    }
+
+    fn not_run(step: &WorkflowStep, status: StepStatus, error: String) -> Self {
+        Self {
+            name: step.name.clone(),
+            status,
+            request_id: None,
+            result: None,
+            error: Some(error),
+            execution_time_ms: 0,
+            attempts: Vec::new(),
+        }
+    }
+}
+
+/// Outcome of a whole workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowResult {
+    pub id: Uuid,
+    pub name: Option<String>,
+    /// Whether every step succeeded
+    pub success: bool,
+    /// Step outcomes in the order the workflow lists the steps
+    pub steps: Vec<StepResult>,
+    /// Steps undone after the failure, in the order they were undone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compensations: Option<Vec<CompensationResult>>,
+    pub execution_time_ms: u64,
+}
+
+impl WorkflowResult {
+    /// Outcome of a step by name
+    pub fn step(&self, name: &str) -> Option<&StepResult> {
+        self.steps.iter().find(|step| step.name == name)
+    }
+}
+
+impl Workflow {
+    /// Dependencies of every step by index, checking that they form no cycle
+    pub fn dependencies(&self) -> Result<Vec<Vec<usize>>, PluginError> {
+        let mut index = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            if index.insert(step.name.as_str(), i).is_some() {
+                return Err(PluginError::InvalidArgument(format!("Step {} is defined twice", step.name)));
+            }
+        }
+
+        let mut dependencies = Vec::with_capacity(self.steps.len());
+        for step in &self.steps {
+            let mut names = step.depends_on.clone();
+            for value in step.arguments.values() {
+                referenced_steps(value, &mut names)?;
+            }
+
+            let mut depends_on: Vec<usize> = Vec::new();
+            for name in names {
+                let dependency = *index.get(name.as_str()).ok_or_else(|| PluginError::InvalidArgument(format!(
+                    "Step {} depends on unknown step {}", step.name, name
+                )))?;
+                if !depends_on.contains(&dependency) {
+                    depends_on.push(dependency);
+                }
+            }
+            dependencies.push(depends_on);
+        }
+
+        // Kahn's algorithm, steps left over are part of a cycle
+        let mut waiting: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..waiting.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut ordered = 0;
+        while let Some(done) = ready.pop() {
+            ordered += 1;
+            for (i, depends_on) in dependencies.iter().enumerate() {
+                if depends_on.contains(&done) {
+                    waiting[i] -= 1;
+                    if waiting[i] == 0 {
+                        ready.push(i);
+                    }
+                }
+            }
+        }
+        if ordered < self.steps.len() {
+            let cycle: Vec<&str> = (0..self.steps.len())
+                .filter(|&i| waiting[i] > 0)
+                .map(|i| self.steps[i].name.as_str())
+                .collect();
+            return Err(PluginError::InvalidArgument(format!(
+                "Steps depend on each other in a cycle: {}", cycle.join(", ")
+            )));
+        }
+
+        Ok(dependencies)
+    }
+
+    /// Run the steps through the executor
+    ///
+    /// Fails without running anything when the steps can't be ordered.
+    /// Failed steps are reported in the result instead.
+    pub async fn run(&self, executor: &PluginExecutor, caller: Option<String>) -> Result<WorkflowResult, PluginError> {
+        let dependencies = self.dependencies()?;
+        let start = Instant::now();
+        let count = self.steps.len();
+
+        let mut results: Vec<Option<StepResult>> = vec![None; count];
+        let mut started = vec![false; count];
+        let mut outputs = Map::new();
+        let mut completed = Vec::new();
+        let mut running = FuturesUnordered::new();
+
+        loop {
+            // Skipping a step can settle the steps after it, so repeat until nothing changes
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (i, step) in self.steps.iter().enumerate() {
+                    if started[i] {
+                        continue;
+                    }
+                    let unsuccessful = dependencies[i].iter().find(|&&d| {
+                        results[d].as_ref().is_some_and(|result| result.status != StepStatus::Succeeded)
+                    });
+                    if let Some(&failed) = unsuccessful {
+                        started[i] = true;
+                        changed = true;
+                        results[i] = Some(StepResult::not_run(step, StepStatus::Skipped, format!(
+                            "Step {} did not succeed", self.steps[failed].name
+                        )));
+                        continue;
+                    }
+                    if !dependencies[i].iter().all(|&d| results[d].is_some()) {
+                        continue;
+                    }
+
+                    started[i] = true;
+                    match self.request(step, &outputs, caller.clone()) {
+                        Ok(request) => running.push(run_step(executor, i, request)),
+                        Err(e) => {
+                            changed = true;
+                            results[i] = Some(StepResult::not_run(step, StepStatus::Failed, e.to_string()));
+                        }
+                    }
+                }
+            }
+
+            match running.next().await {
+                Some((i, request, execution)) => {
+                    let step = &self.steps[i];
+                    let result = StepResult::executed(step, execution);
+                    if let Some(value) = &result.result {
+                        let mut output = Map::new();
+                        output.insert("result".to_string(), value.clone());
+                        outputs.insert(step.name.clone(), Value::Object(output));
+                        completed.push(CompletedAction { request, result: value.clone() });
+                    }
+                    results[i] = Some(result);
+                }
+                None => break,
+            }
+        }
+
+        let steps: Vec<StepResult> = results.into_iter().flatten().collect();
+        let success = steps.iter().all(|step| step.status == StepStatus::Succeeded);
+        let compensations = if self.rollback && !success {
+            Some(roll_back(executor, completed).await)
+        } else {
+            None
+        };
+        Ok(WorkflowResult {
+            id: Uuid::new_v4(),
+            name: self.name.clone(),
+            success,
+            steps,
+            compensations,
+            execution_time_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Executor request for a step, with the references in its arguments resolved
+    fn request(
+        &self,
+        step: &WorkflowStep,
+        outputs: &Map<String, Value>,
+        caller: Option<String>,
+    ) -> Result<ActionRequest, PluginError> {
+        let mut root = Map::new();
+        root.insert("steps".to_string(), Value::Object(outputs.clone()));
+        let root = Value::Object(root);
+        let arguments = step.arguments.iter()
+            .map(|(name, value)| Ok((name.clone(), resolve(value, &root)?)))
+            .collect::<Result<HashMap<String, Value>, PluginError>>()?;
+        let timeout = step.timeout_seconds
+            .or(self.timeout_seconds)
+            .unwrap_or(DEFAULT_STEP_TIMEOUT_SECONDS);
+
+        let mut request = ActionRequest::new(
+            &step.provider, &step.feature, &step.action, arguments, Some(Duration::from_secs(timeout)),
+        );
+        request.idempotency_key = step.idempotency_key.clone();
+        request.caller = caller;
+        Ok(request)
+    }
+}
+
+async fn run_step(executor: &PluginExecutor, index: usize, request: ActionRequest) -> (usize, ActionRequest, ExecutionResult) {
+    let execution = executor.execute(request.clone()).await;
+    (index, request, execution)
+}
+
+/// A `${...}` reference found in a string
+struct Reference<'a> {
+    /// Byte range of the whole `${...}`
+    start: usize,
+    end: usize,
+    /// Dot separated fields, array items by index
+    path: Vec<&'a str>,
+}
+
+/// References in a string, in order
+fn parse_references(text: &str) -> Result<Vec<Reference<'_>>, PluginError> {
+    let mut references = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = text[offset..].find("${") {
+        let start = offset + found;
+        let end = start + text[start..].find('}').ok_or_else(|| PluginError::InvalidArgument(format!(
+            "Unclosed reference in {}", text
+        )))? + 1;
+
+        let path: Vec<&str> = text[start + 2..end - 1].split('.').collect();
+        if path.iter().any(|field| field.is_empty()) {
+            return Err(PluginError::InvalidArgument(format!("Invalid reference {}", &text[start..end])));
+        }
+        references.push(Reference { start, end, path });
+        offset = end;
+    }
+    Ok(references)
+}
+
+/// Collect the names of the steps a value refers to
+fn referenced_steps(value: &Value, steps: &mut Vec<String>) -> Result<(), PluginError> {
+    match value {
+        Value::String(text) => {
+            for reference in parse_references(text)? {
+                match reference.path.as_slice() {
+                    ["steps", step, "result", ..] => steps.push(step.to_string()),
+                    _ => return Err(PluginError::InvalidArgument(format!(
+                        "Invalid reference {}, expected ${{steps.<name>.result...}}",
+                        &text[reference.start..reference.end]
+                    ))),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                referenced_steps(item, steps)?;
+            }
+        }
+        Value::Object(object) => {
+            for item in object.values() {
+                referenced_steps(item, steps)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Copy of a value with its `${...}` references replaced by the values they point to in `root`
+pub(crate) fn resolve(value: &Value, root: &Value) -> Result<Value, PluginError> {
+    match value {
+        Value::String(text) => {
+            let references = parse_references(text)?;
+            if let [only] = references.as_slice() {
+                if only.start == 0 && only.end == text.len() {
+                    return lookup(only, root);
+                }
+            }
+
+            let mut resolved = String::with_capacity(text.len());
+            let mut copied = 0;
+            for reference in &references {
+                resolved.push_str(&text[copied..reference.start]);
+                match lookup(reference, root)? {
+                    Value::String(value) => resolved.push_str(&value),
+                    other => resolved.push_str(&other.to_string()),
+                }
+                copied = reference.end;
+            }
+            resolved.push_str(&text[copied..]);
+            Ok(Value::String(resolved))
+        }
+        Value::Array(items) => Ok(Value::Array(
+            items.iter().map(|item| resolve(item, root)).collect::<Result<_, _>>()?
+        )),
+        Value::Object(object) => Ok(Value::Object(
+            object.iter()
+                .map(|(name, item)| Ok((name.clone(), resolve(item, root)?)))
+                .collect::<Result<_, PluginError>>()?
+        )),
+        other => Ok(other.clone()),
+    }
+}
+
+fn lookup(reference: &Reference<'_>, root: &Value) -> Result<Value, PluginError> {
+    let mut current = root;
+    for (depth, field) in reference.path.iter().enumerate() {
+        let next = match current {
+            Value::Array(items) => field.parse::<usize>().ok().and_then(|i| items.get(i)),
+            other => other.get(*field),
+        };
+        current = next.ok_or_else(|| PluginError::InvalidArgument(format!(
+            "Nothing found for ${{{}}}", reference.path[..=depth].join(".")
+        )))?;
+    }
+    Ok(current.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::super::{ArgumentManager, EventSystem, FeatureRegistry, PluginRegistry};
+
+    fn workflow(steps: Value) -> Workflow {
+        serde_json::from_value(json!({ "steps": steps })).unwrap()
+    }
+
+    fn step(name: &str, depends_on: &[&str], arguments: Value) -> Value {
+        json!({
+            "name": name, "provider": "missing", "feature": "VM_Manage", "action": "create_vm",
+            "depends_on": depends_on, "arguments": arguments,
+        })
+    }
+
+    #[test]
+    fn references_are_dependencies() {
+        let flow = workflow(json!([
+            step("vol", &[], json!({})),
+            step("worker", &[], json!({})),
+            step("attach", &["worker"], json!({ "volume_id": "${steps.vol.result.id}" })),
+        ]));
+        assert_eq!(flow.dependencies().unwrap(), vec![vec![], vec![], vec![1, 0]]);
+
+        let cyclic = workflow(json!([step("a", &["b"], json!({})), step("b", &["a"], json!({}))]));
+        assert!(cyclic.dependencies().unwrap_err().to_string().contains("cycle"));
+
+        let unknown = workflow(json!([step("a", &[], json!({ "id": "${steps.nope.result}" }))]));
+        assert!(unknown.dependencies().is_err());
+    }
+
+    #[test]
+    fn references_resolve_to_step_results() {
+        let volume = json!({ "id": 42, "zones": ["fsn1", "nbg1"] });
+        let root = json!({ "steps": { "vol": { "result": volume } } });
+
+        assert_eq!(resolve(&json!("${steps.vol.result.id}"), &root).unwrap(), json!(42));
+        assert_eq!(resolve(&json!("vol-${steps.vol.result.id}@${steps.vol.result.zones.1}"), &root).unwrap(), json!("vol-42@nbg1"));
+        assert_eq!(resolve(&json!({ "all": ["${steps.vol.result}"] }), &root).unwrap(), json!({ "all": [volume] }));
+        assert!(resolve(&json!("${steps.vol.result.size}"), &root).is_err());
+        assert!(resolve(&json!("${steps..id}"), &root).is_err());
+
+        let misnamed = workflow(json!([step("a", &[], json!({ "id": "${vol.id}" }))]));
+        assert!(misnamed.dependencies().is_err());
+    }
+
+    #[tokio::test]
+    async fn dependents_of_failed_steps_are_skipped() {
+        let executor = PluginExecutor::new(
+            Arc::new(EventSystem::new()),
+            Arc::new(PluginRegistry::new()),
+            Arc::new(FeatureRegistry::new()),
+            Arc::new(ArgumentManager::new()),
+        );
+        let flow = workflow(json!([
+            step("vol", &[], json!({})),
+            step("worker", &[], json!({})),
+            step("attach", &["worker"], json!({ "volume_id": "${steps.vol.result.id}" })),
+            step("tag", &["attach"], json!({})),
+        ]));
+
+        let result = flow.run(&executor, None).await.unwrap();
+        assert!(!result.success);
+        let statuses: Vec<StepStatus> = result.steps.iter().map(|step| step.status).collect();
+        assert_eq!(statuses, [StepStatus::Failed, StepStatus::Failed, StepStatus::Skipped, StepStatus::Skipped]);
+        assert!(result.step("vol").unwrap().request_id.is_some());
+        assert!(result.step("tag").unwrap().request_id.is_none());
+    }
+}
@@ -1,4 +1,5 @@
 mod api;
+mod cli;
 mod cpis;
 mod logging;
 
@@ -6,10 +7,15 @@ pub mod proposal;
 
 use anyhow::Result;
 use std::sync::Arc;
-use cpis::{PluginSystem, PluginExecutor, ServerContextBuilder};
+use cpis::{PluginSystem, PluginExecutor, ExecutionHistory, JobManager, ScheduleManager, ServerContextBuilder};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args).await {
+        std::process::exit(result?);
+    }
+
     // Initialize logging
     println!("🚀 Starting OmniDirector with Event-Driven Plugin System...");
     
@@ -47,13 +53,29 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Create plugin executor
+    // Create plugin executor, recording every execution to the history file
     println!("⚡ Setting up Plugin Executor...");
+    let history_path = std::env::var("EXECUTION_HISTORY_PATH")
+        .unwrap_or_else(|_| cpis::history::DEFAULT_HISTORY_PATH.to_string());
+    let history_retained = std::env::var("EXECUTION_HISTORY_RETAINED").ok()
+        .and_then(|retained| retained.parse().ok())
+        .unwrap_or(cpis::history::DEFAULT_RETAINED_RECORDS);
+    let history = match ExecutionHistory::open_retaining(&history_path, history_retained).await {
+        Ok(history) => {
+            println!("📜 Loaded {} recorded executions", history.len().await);
+            history
+        }
+        Err(e) => {
+            eprintln!("⚠️  Warning: Failed to load execution history, keeping it in memory: {}", e);
+            ExecutionHistory::in_memory().with_retention(history_retained)
+        }
+    };
     let executor = Arc::new(PluginExecutor::new(
         plugin_system.event_system.clone(),
+        plugin_system.plugin_registry.clone(),
         plugin_system.feature_registry.clone(),
         plugin_system.argument_manager.clone(),
-    ));
+    ).with_history(history));
 
     if let Err(e) = executor.initialize().await {
         eprintln!("❌ Failed to initialize executor: {}", e);
@@ -66,6 +88,10 @@ async fn main() -> Result<()> {
         Ok(count) => println!("📝 Loaded {} arguments from environment", count),
         Err(e) => eprintln!("⚠️  Warning: Failed to load environment arguments: {}", e),
     }
+    match executor.load_limits().await {
+        Ok(count) => println!("🚦 Loaded {} provider action limits", count),
+        Err(e) => eprintln!("⚠️  Warning: Failed to load action limits: {}", e),
+    }
 
     // Display system status
     display_system_status(&plugin_system, &executor).await?;
@@ -74,9 +100,36 @@ async fn main() -> Result<()> {
     println!("🧹 Starting cleanup task...");
     let _cleanup_handle = executor.start_cleanup_task().await;
 
+    // Restore persisted jobs, re-running the ones that never started
+    println!("🗂️ Loading background jobs...");
+    let job_dir = std::env::var("JOB_STORE_DIR").unwrap_or_else(|_| cpis::jobs::DEFAULT_JOB_DIR.to_string());
+    let job_retention = std::env::var("JOB_RETENTION_HOURS").ok()
+        .and_then(|hours| hours.parse::<u64>().ok())
+        .map(|hours| std::time::Duration::from_secs(hours * 60 * 60))
+        .unwrap_or(cpis::jobs::DEFAULT_JOB_RETENTION);
+    let jobs = Arc::new(JobManager::new(Arc::clone(&executor), job_dir).with_retention(job_retention));
+    match jobs.load().await {
+        Ok(count) => println!("📝 Loaded {} persisted jobs", count),
+        Err(e) => eprintln!("⚠️  Warning: Failed to load persisted jobs: {}", e),
+    }
+
+    // Restore recurring schedules and arm their timers
+    println!("⏰ Loading schedules...");
+    let schedule_dir = std::env::var("SCHEDULE_STORE_DIR")
+        .unwrap_or_else(|_| cpis::schedules::DEFAULT_SCHEDULE_DIR.to_string());
+    let schedules = Arc::new(ScheduleManager::new(Arc::clone(&executor), plugin_system.server_context(), schedule_dir));
+    if let Err(e) = schedules.initialize().await {
+        eprintln!("❌ Failed to initialize schedules: {}", e);
+        return Err(anyhow::anyhow!("Schedule initialization failed: {}", e));
+    }
+    match schedules.load().await {
+        Ok(count) => println!("📝 Loaded {} schedules", count),
+        Err(e) => eprintln!("⚠️  Warning: Failed to load schedules: {}", e),
+    }
+
     // Launch the API server
     println!("🌐 Starting API server...");
-    api::launch_rocket(plugin_system, executor).await;
+    api::launch_rocket(plugin_system, executor, jobs, schedules).await;
     
     Ok(())
 }
@@ -121,6 +174,9 @@ async fn display_system_status(
     println!("  Pending requests: {}", exec_stats.pending_requests);
     println!("  Average wait time: {:?}", exec_stats.average_wait_time);
     println!("  Oldest request age: {:?}", exec_stats.oldest_request_age);
+    for (provider, queue) in &exec_stats.provider_queues {
+        println!("  Queue {}: {} waiting, average wait {}ms", provider, queue.queue_depth, queue.average_wait_ms);
+    }
     
     println!("{}", "=".repeat(50));
     println!("✅ System is ready to serve requests!\n");
@@ -192,6 +248,7 @@ mod tests {
 
         let executor = PluginExecutor::new(
             plugin_system.event_system.clone(),
+            plugin_system.plugin_registry.clone(),
             plugin_system.feature_registry.clone(),
             plugin_system.argument_manager.clone(),
         );
@@ -224,6 +281,31 @@ mod tests {
         assert_eq!(arg_value.value, Value::String("test_value".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_settings_reach_plugins() {
+        use cpis::context::ServerContext;
+
+        let server_context = ServerContextBuilder::new()
+            .with_region_id("test".to_string())
+            .with_event_system(Arc::new(cpis::events::EventSystem::new()))
+            .with_feature_registry(Arc::new(cpis::features::FeatureRegistry::new()))
+            .with_argument_manager(Arc::new(cpis::arguments::ArgumentManager::new()))
+            .build()
+            .expect("Failed to create server context");
+        let plugin_system = PluginSystem::new(server_context.clone());
+
+        // main loads OMNI_* through the plugin system, plugins read the context
+        plugin_system.argument_manager
+            .set_global_argument("hetzner_token", Value::String("secret".to_string()), false)
+            .await
+            .expect("Failed to set global argument");
+        let seen = server_context.arguments()
+            .get_argument("hetzner", "hetzner_token", None, cpis::arguments::ArgumentResolution::GlobalOnly)
+            .await
+            .expect("Setting not visible to plugins");
+        assert_eq!(seen.value, Value::String("secret".to_string()));
+    }
+
     #[tokio::test]
     async fn test_feature_validation() {
         let server_context = ServerContextBuilder::new()
@@ -0,0 +1,202 @@
+//! Full `execute_action` flows replayed from the cassettes in
+//! `tests/cassettes/`, so no provider tooling has to be installed.
+//!
+//! Each test loads a single CPI into a fresh plugin system and replays its
+//! recorded commands. To refresh a cassette, run the server against the real
+//! provider with `OMNI_cassette_mode={"<provider>": "record"}`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use omni_director::cpis::{
+    ActionRequest, ArgumentManager, EventSystem, FeatureRegistry, PluginError, PluginExecutor, PluginSystem,
+    ServerContext, ServerContextBuilder,
+};
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plugin system with one CPI loaded and replaying from its cassette
+async fn replaying(provider_file: &str) -> PluginExecutor {
+    replaying_with(provider_file, json!({})).await
+}
+
+/// Like `replaying`, with extra global settings applied on top
+async fn replaying_with(provider_file: &str, settings: Value) -> PluginExecutor {
+    let event_system = Arc::new(EventSystem::new());
+    let arguments = Arc::new(ArgumentManager::new());
+    let context = ServerContextBuilder::new()
+        .with_region_id("test".to_string())
+        .with_event_system(Arc::clone(&event_system))
+        .with_feature_registry(Arc::new(FeatureRegistry::new()))
+        .with_argument_manager(Arc::clone(&arguments))
+        .build()
+        .unwrap() as Arc<dyn ServerContext>;
+
+    arguments.set_global_argument("cassette_mode", json!("replay"), false).await.unwrap();
+    arguments.set_global_argument("cassette_dir", json!("tests/cassettes"), false).await.unwrap();
+    for (name, value) in settings.as_object().unwrap() {
+        arguments.set_global_argument(name, value.clone(), false).await.unwrap();
+    }
+
+    let system = PluginSystem::new(Arc::clone(&context));
+    system.feature_registry.load_schemas("./features").await.unwrap();
+    system.cpi_host.load_mappings("./features/mappings").await.unwrap();
+
+    // load_definitions reads a whole directory, so give it just this CPI
+    let cpis_dir = std::env::temp_dir().join(format!("omni-cpis-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&cpis_dir).unwrap();
+    std::fs::copy(PathBuf::from("CPIs").join(provider_file), cpis_dir.join(provider_file)).unwrap();
+    let loaded = system.cpi_host.load_definitions(&cpis_dir, &system.plugin_registry, context).await.unwrap();
+    std::fs::remove_dir_all(&cpis_dir).unwrap();
+    assert_eq!(loaded, 1);
+
+    let executor = PluginExecutor::new(
+        Arc::clone(&system.event_system),
+        Arc::clone(&system.plugin_registry),
+        Arc::clone(&system.feature_registry),
+        Arc::clone(&system.argument_manager),
+    );
+    executor.initialize().await.unwrap();
+    executor
+}
+
+fn golden(provider_file: &str, name: &str) -> Value {
+    let path = PathBuf::from("tests/cpis/golden").join(provider_file.trim_end_matches(".json")).join(name);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn arguments(value: Value) -> HashMap<String, Value> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[tokio::test]
+async fn worker_actions_replay() {
+    let executor = replaying("cpi-dummy.json").await;
+
+    for action in ["test_install", "list_workers"] {
+        let result = executor.execute_action("dummy", "Worker_Manage", action, HashMap::new(), Some(TIMEOUT)).await
+            .unwrap_or_else(|e| panic!("{} failed: {}", action, e));
+        assert_eq!(result, golden("cpi-dummy.json", &format!("{}.json", action)), "{}", action);
+    }
+}
+
+#[tokio::test]
+async fn mapped_feature_actions_replay() {
+    let executor = replaying("cpi-dummy.json").await;
+
+    let created = executor.execute_action(
+        "dummy", "VM_Manage", "create_vm",
+        arguments(json!({ "name": "web-1", "memory_mb": 1024, "cpu_count": 1 })),
+        Some(TIMEOUT),
+    ).await.unwrap();
+    assert_eq!(created, golden("cpi-dummy.json", "create_worker.json"));
+
+    let info = executor.execute_action(
+        "dummy", "VM_Manage", "get_vm_info", arguments(json!({ "vm_id": "7" })), Some(TIMEOUT),
+    ).await.unwrap();
+    assert_eq!(info, golden("cpi-dummy.json", "get_worker.json"));
+}
+
+#[tokio::test]
+async fn unrecorded_requests_fail() {
+    let executor = replaying("cpi-dummy.json").await;
+
+    let result = executor.execute_action(
+        "dummy", "VM_Manage", "get_vm_info", arguments(json!({ "vm_id": "8" })), Some(TIMEOUT),
+    ).await;
+    match result {
+        Err(PluginError::ExecutionFailed(message)) => {
+            assert!(message.contains("has no recording of dummy/get_worker"), "{}", message);
+        }
+        other => panic!("expected a replay miss, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn requests_cannot_redirect_provider_credentials() {
+    let executor = replaying("cpi-truenas.json").await;
+
+    let cases = [
+        (json!({ "api_url": "https://attacker.example" }), "can't be set by a request"),
+        (json!({ "api_key": "guess" }), "can't be set by a request"),
+        (json!({ "verify": false }), "does not take an argument 'verify'"),
+    ];
+    for (request, expected) in cases {
+        let result = executor.execute_action(
+            "truenas", "Worker_Manage", "list_workers", arguments(request.clone()), Some(TIMEOUT),
+        ).await;
+        match result {
+            Err(e) => assert!(e.to_string().contains(expected), "{}: {}", request, e),
+            Ok(value) => panic!("{} was accepted: {}", request, value),
+        }
+    }
+}
+
+#[tokio::test]
+async fn requests_cannot_fill_flag_slots() {
+    let executor = replaying("cpi-hetzner.json").await;
+
+    let request = json!({
+        "name": "web-1", "server_type": "cx22", "image": "ubuntu-24.04", "datacenter": "fsn1-dc14",
+        "ssh_key_param": "--user-data-from-file=/etc/shadow",
+    });
+    let result = executor.execute_action(
+        "hetzner", "Worker_Manage", "create_worker", arguments(request), Some(TIMEOUT),
+    ).await;
+    match result {
+        Err(e) => assert!(e.to_string().contains("'ssh_key_param' is filled in by CPI hetzner"), "{}", e),
+        Ok(value) => panic!("the slot was accepted: {}", value),
+    }
+}
+
+#[tokio::test]
+async fn rate_limited_actions_are_retried() {
+    let cassette_dir = std::env::temp_dir().join(format!("omni-cassettes-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&cassette_dir).unwrap();
+    let cassette = json!({
+        "provider": "truenas",
+        "interactions": [{
+            "action": "list_workers",
+            "request": {
+                "kind": "http",
+                "method": "Get",
+                "url": "http://localhost/api/v2.0/pool/dataset",
+                "headers": [["Authorization", "Bearer "], ["Content-Type", "application/json"]]
+            },
+            "response": { "status": 429, "body": "{\"message\": \"slow down\"}" }
+        }]
+    });
+    std::fs::write(cassette_dir.join("truenas.json"), cassette.to_string()).unwrap();
+
+    let executor = replaying_with("cpi-truenas.json", json!({
+        "cassette_dir": cassette_dir.to_string_lossy(),
+        "retry_policy": { "truenas": { "max_attempts": 2, "initial_backoff_ms": 1, "jitter": 0.0 } },
+    })).await;
+    let execution = executor.execute(
+        ActionRequest::new("truenas", "Worker_Manage", "list_workers", HashMap::new(), Some(TIMEOUT)),
+    ).await;
+    std::fs::remove_dir_all(&cassette_dir).unwrap();
+
+    // The status survives the completion event, so the policy sees a 429
+    match execution.result {
+        Err(PluginError::HttpStatus { status: 429, .. }) => {}
+        other => panic!("expected a 429, got {:?}", other),
+    }
+    assert_eq!(execution.attempts.len(), 2);
+}
+
+#[tokio::test]
+async fn unknown_providers_fail_fast() {
+    let executor = replaying("cpi-dummy.json").await;
+
+    let result = executor.execute_action(
+        "missing", "VM_Manage", "get_vm_info", arguments(json!({ "vm_id": "7" })), Some(TIMEOUT),
+    ).await;
+    match result {
+        Err(PluginError::PluginNotFound(name)) => assert_eq!(name, "missing"),
+        other => panic!("expected an unknown provider, got {:?}", other),
+    }
+    assert!(executor.get_pending_requests().await.is_empty());
+}
@@ -0,0 +1,174 @@
+//! Endpoint target execution against a local warp stand-in for a provider API.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use omni_director::cpis::PluginError;
+use omni_director::cpis::json_cpi::{CpiDefinition, CpiTarget};
+use omni_director::cpis::json_cpi::http::{endpoint_placeholders, render_request, EndpointExecutor};
+use omni_director::cpis::json_cpi::parse::parse_response;
+use serde_json::{json, Value};
+use warp::Filter;
+use warp::http::StatusCode;
+
+const DEFINITION: &str = r#"{
+    "name": "stand-in",
+    "type": "endpoint",
+    "default_settings": { "api_url": "http://localhost" },
+    "actions": {
+        "get_worker": {
+            "target": {
+                "Endpoint": {
+                    "url": "{api_url}/servers/{id}",
+                    "method": "Get",
+                    "headers": { "Authorization": "Bearer {api_key}" }
+                }
+            },
+            "params": ["api_key", "id"],
+            "json_path": "$.server",
+            "parse_rules": { "type": "object", "patterns": {} }
+        },
+        "list_workers": {
+            "target": {
+                "Endpoint": {
+                    "url": "{api_url}/servers",
+                    "method": "Get",
+                    "headers": { "Authorization": "Bearer {api_key}" }
+                }
+            },
+            "params": ["api_key"],
+            "json_path": "servers[*].name",
+            "parse_rules": { "type": "array", "patterns": {} }
+        },
+        "create_worker": {
+            "target": {
+                "Endpoint": {
+                    "url": "{api_url}/servers",
+                    "method": "Post",
+                    "headers": { "Content-Type": "application/json", "Authorization": "Bearer {api_key}" },
+                    "body": "{\"name\":\"{name}\",\"cpus\":{cpus}}"
+                }
+            },
+            "params": ["api_key", "name", "cpus"],
+            "parse_rules": {
+                "type": "object",
+                "patterns": {
+                    "id": { "regex": "\"id\":\\s*(\\d+)", "group": 1, "transform": "number" },
+                    "cpus": { "regex": "\"cpus\":\\s*(\\d+)", "group": 1, "transform": "number" }
+                }
+            }
+        }
+    }
+}"#;
+
+fn authorized() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::exact("authorization", "Bearer secret")
+}
+
+async fn start_server() -> SocketAddr {
+    let get = warp::get()
+        .and(warp::path!("servers" / u64))
+        .and(authorized())
+        .map(|id: u64| {
+            if id == 1 {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "server": { "id": 1, "name": "web-1", "status": "running" } })),
+                    StatusCode::OK,
+                )
+            } else {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": { "code": "not_found", "message": "server not found" } })),
+                    StatusCode::NOT_FOUND,
+                )
+            }
+        });
+
+    let list = warp::get()
+        .and(warp::path!("servers"))
+        .and(authorized())
+        .map(|| warp::reply::json(&json!({ "servers": [{ "name": "web-1" }, { "name": "web-2" }] })));
+
+    let create = warp::post()
+        .and(warp::path!("servers"))
+        .and(authorized())
+        .and(warp::body::json())
+        .map(|body: Value| {
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "id": 42, "name": body["name"], "cpus": body["cpus"] })),
+                StatusCode::CREATED,
+            )
+        });
+
+    let unauthorized = warp::any().map(|| {
+        warp::reply::with_status("missing credentials", StatusCode::UNAUTHORIZED)
+    });
+
+    let routes = get.or(list).or(create).or(unauthorized);
+    let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+async fn run(addr: SocketAddr, action: &str, arguments: Value) -> Result<Value, PluginError> {
+    let definition: CpiDefinition = serde_json::from_str(DEFINITION).unwrap();
+    let action = definition.action(action).unwrap();
+    let CpiTarget::Endpoint(target) = &action.target else {
+        panic!("expected an endpoint target");
+    };
+
+    let mut values: HashMap<String, Value> = serde_json::from_value(arguments).unwrap();
+    values.insert("api_url".to_string(), json!(format!("http://{}", addr)));
+    for name in endpoint_placeholders(target) {
+        assert!(values.contains_key(&name), "test did not supply {}", name);
+    }
+
+    let request = render_request(target, &values)?;
+    let body = EndpointExecutor::new().fetch(&request).await?.into_result()?;
+    Ok(parse_response(&body, action)?)
+}
+
+#[tokio::test]
+async fn json_path_selects_the_response_value() {
+    let addr = start_server().await;
+
+    let worker = run(addr, "get_worker", json!({ "api_key": "secret", "id": 1 })).await.unwrap();
+    assert_eq!(worker, json!({ "id": 1, "name": "web-1", "status": "running" }));
+
+    let names = run(addr, "list_workers", json!({ "api_key": "secret" })).await.unwrap();
+    assert_eq!(names, json!(["web-1", "web-2"]));
+}
+
+#[tokio::test]
+async fn parse_rules_apply_to_the_response_body() {
+    let addr = start_server().await;
+
+    let created = run(addr, "create_worker", json!({
+        "api_key": "secret",
+        "name": "web \"3\"",
+        "cpus": "2"
+    })).await.unwrap();
+
+    // The quoted name only reaches the server if the body stayed valid JSON,
+    // and the string "2" is sent as a number
+    assert_eq!(created, json!({ "id": 42, "cpus": 2 }));
+}
+
+#[tokio::test]
+async fn non_2xx_responses_become_http_status_errors() {
+    let addr = start_server().await;
+
+    match run(addr, "get_worker", json!({ "api_key": "secret", "id": 2 })).await {
+        Err(PluginError::HttpStatus { status, message }) => {
+            assert_eq!(status, 404);
+            assert_eq!(message, "server not found");
+        }
+        other => panic!("expected an HTTP status error, got {:?}", other),
+    }
+
+    match run(addr, "get_worker", json!({ "api_key": "wrong", "id": 1 })).await {
+        Err(PluginError::HttpStatus { status, message }) => {
+            assert_eq!(status, 401);
+            assert_eq!(message, "missing credentials");
+        }
+        other => panic!("expected an HTTP status error, got {:?}", other),
+    }
+}
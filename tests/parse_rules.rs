@@ -0,0 +1,95 @@
+//! Golden-file tests for the CPI parse_rules engine.
+//!
+//! Each directory under `tests/cpis/golden/` is named after a file in `CPIs/`
+//! and holds sample outputs for its actions:
+//!
+//! - `<action>.out`   raw stdout captured from the provider tooling
+//! - `<action>.json`  the expected parse result
+//! - `<action>.error` the expected error message, for outputs that must fail
+//!
+//! Extra samples for the same action use a variant suffix, e.g.
+//! `get_worker.malformed.out`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the `.json` files from the current
+//! engine output after reviewing the differences.
+
+use std::fs;
+use std::path::Path;
+use omni_director::cpis::json_cpi::CpiDefinition;
+use omni_director::cpis::json_cpi::parse::parse_output;
+use serde_json::Value;
+
+const GOLDEN_DIR: &str = "tests/cpis/golden";
+
+fn load_definition(stem: &str) -> CpiDefinition {
+    let path = Path::new("CPIs").join(format!("{}.json", stem));
+    let content = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("cannot read {:?}: {}", path, e));
+    serde_json::from_str(&content)
+        .unwrap_or_else(|e| panic!("cannot parse {:?}: {}", path, e))
+}
+
+#[test]
+fn golden_outputs_match() {
+    let update = std::env::var("UPDATE_GOLDEN").is_ok();
+    let mut checked = 0;
+    let mut failures = Vec::new();
+
+    for provider_dir in fs::read_dir(GOLDEN_DIR).expect("golden directory missing") {
+        let provider_dir = provider_dir.unwrap().path();
+        let stem = provider_dir.file_name().unwrap().to_string_lossy().to_string();
+        let definition = load_definition(&stem);
+
+        for sample in fs::read_dir(&provider_dir).unwrap() {
+            let sample = sample.unwrap().path();
+            if sample.extension().and_then(|s| s.to_str()) != Some("out") {
+                continue;
+            }
+
+            let sample_name = sample.file_stem().unwrap().to_string_lossy().to_string();
+            let action_name = sample_name.split('.').next().unwrap().to_string();
+            let action = definition.action(&action_name)
+                .unwrap_or_else(|| panic!("{}: unknown action {}", stem, action_name));
+            let output = fs::read_to_string(&sample).unwrap();
+            let result = parse_output(&output, &action.parse_rules);
+            let label = format!("{}/{}", stem, sample_name);
+
+            let error_path = sample.with_extension("error");
+            let json_path = sample.with_extension("json");
+
+            if error_path.exists() {
+                let expected = fs::read_to_string(&error_path).unwrap();
+                match result {
+                    Err(e) if e.to_string() == expected.trim() => {}
+                    Err(e) => failures.push(format!("{}: expected error '{}', got '{}'", label, expected.trim(), e)),
+                    Ok(value) => failures.push(format!("{}: expected error, got {}", label, value)),
+                }
+            } else {
+                let value = match result {
+                    Ok(value) => value,
+                    Err(e) => {
+                        failures.push(format!("{}: {}", label, e));
+                        continue;
+                    }
+                };
+
+                if update {
+                    fs::write(&json_path, serde_json::to_string_pretty(&value).unwrap() + "\n").unwrap();
+                } else {
+                    let expected: Value = fs::read_to_string(&json_path)
+                        .ok()
+                        .and_then(|content| serde_json::from_str(&content).ok())
+                        .unwrap_or_else(|| panic!("{}: missing or invalid {:?}", label, json_path));
+                    if value != expected {
+                        failures.push(format!("{}: expected {}, got {}", label, expected, value));
+                    }
+                }
+            }
+
+            checked += 1;
+        }
+    }
+
+    assert!(checked > 0, "no golden samples found");
+    assert!(failures.is_empty(), "golden mismatches:\n{}", failures.join("\n"));
+}